@@ -0,0 +1,285 @@
+//! 合并镜像的头部格式，由 xtask 写入，固件在跳转之前解析和校验。
+//!
+//! 合并镜像的布局：
+//!
+//! ```text
+//! 0                       IMAGE_HEADER_OFFSET     PAYLOAD_OFFSET
+//! | rustsbi-nezha.bin ... | 头部 | 填充 ...        | 下一阶段 | 设备树（可选） | initrd（可选） |
+//! ```
+//!
+//! 所有字段都是小端序。各个条目的偏移都相对于镜像的开头，也就是 FIRMWARE_BASE。
+
+use core::fmt;
+
+/// 头部魔数
+pub const IMAGE_MAGIC: [u8; 8] = *b"NEZHAIMG";
+/// 当前的头部版本
+pub const IMAGE_VERSION: u32 = 1;
+/// 头部的大小
+pub const IMAGE_HEADER_SIZE: usize = 96;
+
+const ENTRY_SIZE: usize = 24;
+const OFFSET_VERSION: usize = 8;
+const OFFSET_HEADER_SIZE: usize = 12;
+const OFFSET_ENTRIES: usize = 16;
+const OFFSET_HEADER_CRC32: usize = OFFSET_ENTRIES + 3 * ENTRY_SIZE;
+
+/// 镜像中的一段数据
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageEntry {
+    /// 相对于镜像开头的偏移
+    pub offset: u32,
+    pub size: u32,
+    /// 固件会把这段数据放到这个地址；为 0 时表示原地使用
+    pub load_address: u64,
+    /// 数据的 CRC32
+    pub crc32: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub payload: ImageEntry,
+    pub device_tree: Option<ImageEntry>,
+    pub initrd: Option<ImageEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    BadHeaderSize(u32),
+    HeaderCorrupted { expected: u32, actual: u32 },
+    MissingPayload,
+    EntryOutOfRange(&'static str),
+    EntryCorrupted { name: &'static str, expected: u32, actual: u32 },
+    /// 条目必须移动到加载地址，但头部中的加载地址为 0
+    MissingLoadAddress(&'static str),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::BadMagic => write!(f, "bad magic, no boot image header found"),
+            ImageError::UnsupportedVersion(version) => write!(f, "unsupported header version {}", version),
+            ImageError::BadHeaderSize(size) => write!(f, "bad header size {}", size),
+            ImageError::HeaderCorrupted { expected, actual } =>
+                write!(f, "header crc32 mismatch, expected {:#010x}, actual {:#010x}", expected, actual),
+            ImageError::MissingPayload => write!(f, "no payload in boot image"),
+            ImageError::EntryOutOfRange(name) => write!(f, "{} is out of range", name),
+            ImageError::EntryCorrupted { name, expected, actual } =>
+                write!(f, "{} crc32 mismatch, expected {:#010x}, actual {:#010x}", name, expected, actual),
+            ImageError::MissingLoadAddress(name) => write!(f, "{} has no load address", name),
+        }
+    }
+}
+
+impl ImageHeader {
+    pub fn to_bytes(&self) -> [u8; IMAGE_HEADER_SIZE] {
+        let mut bytes = [0u8; IMAGE_HEADER_SIZE];
+        bytes[..8].copy_from_slice(&IMAGE_MAGIC);
+        put_u32(&mut bytes, OFFSET_VERSION, IMAGE_VERSION);
+        put_u32(&mut bytes, OFFSET_HEADER_SIZE, IMAGE_HEADER_SIZE as u32);
+        let entries = [Some(self.payload), self.device_tree, self.initrd];
+        for (i, entry) in entries.iter().enumerate() {
+            if let Some(entry) = entry {
+                let base = OFFSET_ENTRIES + i * ENTRY_SIZE;
+                put_u32(&mut bytes, base, entry.offset);
+                put_u32(&mut bytes, base + 4, entry.size);
+                put_u64(&mut bytes, base + 8, entry.load_address);
+                put_u32(&mut bytes, base + 16, entry.crc32);
+            }
+        }
+        let crc = crc32(&bytes);
+        put_u32(&mut bytes, OFFSET_HEADER_CRC32, crc);
+        bytes
+    }
+
+    /// 解析并校验头部本身，不检查各个条目的数据
+    pub fn parse(bytes: &[u8]) -> Result<Self, ImageError> {
+        if bytes.len() < IMAGE_HEADER_SIZE || bytes[..8] != IMAGE_MAGIC {
+            return Err(ImageError::BadMagic);
+        }
+        let version = get_u32(bytes, OFFSET_VERSION);
+        if version != IMAGE_VERSION {
+            return Err(ImageError::UnsupportedVersion(version));
+        }
+        let header_size = get_u32(bytes, OFFSET_HEADER_SIZE);
+        if header_size as usize != IMAGE_HEADER_SIZE {
+            return Err(ImageError::BadHeaderSize(header_size));
+        }
+        let mut copy = [0u8; IMAGE_HEADER_SIZE];
+        copy.copy_from_slice(&bytes[..IMAGE_HEADER_SIZE]);
+        put_u32(&mut copy, OFFSET_HEADER_CRC32, 0);
+        let expected = get_u32(bytes, OFFSET_HEADER_CRC32);
+        let actual = crc32(&copy);
+        if expected != actual {
+            return Err(ImageError::HeaderCorrupted { expected, actual });
+        }
+        let entry = |i: usize| {
+            let base = OFFSET_ENTRIES + i * ENTRY_SIZE;
+            let entry = ImageEntry {
+                offset: get_u32(bytes, base),
+                size: get_u32(bytes, base + 4),
+                load_address: get_u64(bytes, base + 8),
+                crc32: get_u32(bytes, base + 16),
+            };
+            if entry.size == 0 { None } else { Some(entry) }
+        };
+        Ok(ImageHeader {
+            payload: entry(0).ok_or(ImageError::MissingPayload)?,
+            device_tree: entry(1),
+            initrd: entry(2),
+        })
+    }
+}
+
+impl ImageEntry {
+    /// 检查条目的数据，image 是整个合并镜像
+    pub fn verify(&self, name: &'static str, image: &[u8]) -> Result<(), ImageError> {
+        let start = self.offset as usize;
+        let end = start.checked_add(self.size as usize).ok_or(ImageError::EntryOutOfRange(name))?;
+        let data = image.get(start..end).ok_or(ImageError::EntryOutOfRange(name))?;
+        let actual = crc32(data);
+        if actual != self.crc32 {
+            return Err(ImageError::EntryCorrupted { name, expected: self.crc32, actual });
+        }
+        Ok(())
+    }
+}
+
+/// CRC-32（IEEE 802.3），和 zlib 的 crc32 相同
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 { 0xedb8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(bytes: &mut [u8], offset: usize, value: u64) {
+    bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn get_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
+
+fn get_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, ImageEntry, ImageError, ImageHeader, IMAGE_HEADER_SIZE};
+
+    fn entry(offset: u32, data: &[u8], load_address: u64) -> ImageEntry {
+        ImageEntry { offset, size: data.len() as u32, load_address, crc32: crc32(data) }
+    }
+
+    fn sample() -> ImageHeader {
+        ImageHeader {
+            payload: entry(0x100, b"payload", 0x4002_0000),
+            device_tree: Some(entry(0x200, b"dtb", 0)),
+            initrd: None,
+        }
+    }
+
+    #[test]
+    fn crc32_known_answers() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414f_a339);
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let header = sample();
+        assert_eq!(ImageHeader::parse(&header.to_bytes()), Ok(header));
+        let full = ImageHeader { initrd: Some(entry(0x300, b"initrd", 0x4200_0000)), ..header };
+        assert_eq!(ImageHeader::parse(&full.to_bytes()), Ok(full));
+        // 后面跟着其它数据时只解析头部
+        let mut bytes = [0xffu8; IMAGE_HEADER_SIZE + 16];
+        bytes[..IMAGE_HEADER_SIZE].copy_from_slice(&header.to_bytes());
+        assert_eq!(ImageHeader::parse(&bytes), Ok(header));
+    }
+
+    #[test]
+    fn corrupted_header_is_rejected() {
+        let bytes = sample().to_bytes();
+        for &offset in [8, 12, 20, 40, 60].iter() {
+            let mut corrupted = bytes;
+            corrupted[offset] ^= 1;
+            assert!(ImageHeader::parse(&corrupted).is_err(), "flipped byte {}", offset);
+        }
+        let mut corrupted = bytes;
+        corrupted[20] ^= 1;
+        assert!(matches!(ImageHeader::parse(&corrupted), Err(ImageError::HeaderCorrupted { .. })));
+        let mut corrupted = bytes;
+        corrupted[8] = 2;
+        assert_eq!(ImageHeader::parse(&corrupted), Err(ImageError::UnsupportedVersion(2)));
+        let mut corrupted = bytes;
+        corrupted[12] = 64;
+        assert_eq!(ImageHeader::parse(&corrupted), Err(ImageError::BadHeaderSize(64)));
+    }
+
+    #[test]
+    fn bad_magic_and_truncation() {
+        let bytes = sample().to_bytes();
+        let mut bad = bytes;
+        bad[0] = b'X';
+        assert_eq!(ImageHeader::parse(&bad), Err(ImageError::BadMagic));
+        assert_eq!(ImageHeader::parse(&bytes[..IMAGE_HEADER_SIZE - 1]), Err(ImageError::BadMagic));
+        assert_eq!(ImageHeader::parse(&[]), Err(ImageError::BadMagic));
+    }
+
+    #[test]
+    fn missing_payload() {
+        let mut header = sample();
+        header.payload.size = 0;
+        assert_eq!(ImageHeader::parse(&header.to_bytes()), Err(ImageError::MissingPayload));
+    }
+
+    #[test]
+    fn entry_data_is_verified() {
+        let mut image = [0u8; 0x210];
+        image[0x100..0x107].copy_from_slice(b"payload");
+        image[0x200..0x203].copy_from_slice(b"dtb");
+        let header = sample();
+        assert_eq!(header.payload.verify("payload", &image), Ok(()));
+        assert_eq!(header.device_tree.unwrap().verify("device tree", &image), Ok(()));
+        image[0x106] = b'D';
+        assert!(matches!(
+            header.payload.verify("payload", &image),
+            Err(ImageError::EntryCorrupted { name: "payload", .. })
+        ));
+        assert_eq!(header.device_tree.unwrap().verify("device tree", &image[..0x202]), Err(ImageError::EntryOutOfRange("device tree")));
+        let huge = ImageEntry { offset: u32::MAX, size: u32::MAX, load_address: 0, crc32: 0 };
+        assert_eq!(huge.verify("initrd", &image), Err(ImageError::EntryOutOfRange("initrd")));
+    }
+}
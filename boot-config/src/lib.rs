@@ -0,0 +1,94 @@
+//! 启动流程的内存布局，固件、链接脚本、测试内核和 xtask 都从这里读取。
+//!
+//! 每个平台有一份布局，编译固件时由 `qemu-virt` 特性选择，默认是哪吒 D1。
+//! 修改这里的值之后重新编译即可，不需要再分别修改各个 crate。
+#![no_std]
+
+pub mod image;
+
+/// 下一阶段的特权级
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privilege {
+    User,
+    Supervisor,
+}
+
+/// 一个平台的内存布局
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryLayout {
+    /// 固件的加载地址
+    pub firmware_base: usize,
+    /// 合并镜像中下一阶段相对于固件开头的偏移
+    pub payload_offset: usize,
+    /// 固件修正后的设备树存放的地址
+    pub device_tree_address: usize,
+    /// 合并镜像中带有 initrd 时，固件把它放到这个地址
+    pub initrd_address: usize,
+    /// 下一阶段是 ELF 文件时，固件先把整个文件放到这里，再从这里加载各个段
+    pub elf_staging_address: usize,
+}
+
+/// 哪吒 D1，xfel 把合并镜像写到 DRAM 开头
+pub const D1: MemoryLayout = MemoryLayout {
+    firmware_base: 0x4000_0000,
+    payload_offset: 0x2_0000,
+    device_tree_address: 0x4400_0000,
+    initrd_address: 0x4200_0000,
+    elf_staging_address: 0x4800_0000,
+};
+
+/// QEMU virt，`-bios` 指定的文件加载到 DRAM 开头，默认的 128MiB 内存要放得下所有区域
+pub const QEMU_VIRT: MemoryLayout = MemoryLayout {
+    firmware_base: 0x8000_0000,
+    payload_offset: 0x2_0000,
+    device_tree_address: 0x8400_0000,
+    initrd_address: 0x8200_0000,
+    elf_staging_address: 0x8600_0000,
+};
+
+impl MemoryLayout {
+    /// 合并镜像头部相对于固件开头的偏移，固件本身不能超过这个大小
+    pub const fn image_header_offset(&self) -> usize {
+        self.payload_offset - 0x1000
+    }
+
+    /// 下一阶段的入口地址
+    pub const fn next_stage_address(&self) -> usize {
+        self.firmware_base + self.payload_offset
+    }
+}
+
+#[cfg(not(feature = "qemu-virt"))]
+const LAYOUT: MemoryLayout = D1;
+#[cfg(feature = "qemu-virt")]
+const LAYOUT: MemoryLayout = QEMU_VIRT;
+
+/// 固件的加载地址，也是 xfel 写入和执行的地址
+pub const FIRMWARE_BASE: usize = LAYOUT.firmware_base;
+
+/// 合并镜像中下一阶段相对于固件开头的偏移
+pub const PAYLOAD_OFFSET: usize = LAYOUT.payload_offset;
+
+/// 固件占用并由 PMP 保护的内存大小，下一阶段不能使用这段内存
+pub const FIRMWARE_SIZE: usize = PAYLOAD_OFFSET;
+
+/// 合并镜像头部相对于固件开头的偏移，固件本身不能超过这个大小
+pub const IMAGE_HEADER_OFFSET: usize = LAYOUT.image_header_offset();
+
+/// 下一阶段的入口地址
+pub const NEXT_STAGE_ADDRESS: usize = LAYOUT.next_stage_address();
+
+/// 进入下一阶段时的特权级
+pub const NEXT_STAGE_PRIVILEGE: Privilege = Privilege::Supervisor;
+
+/// 固件修正后的设备树存放的地址
+pub const DEVICE_TREE_ADDRESS: usize = LAYOUT.device_tree_address;
+
+/// 设备树存放区域的大小，修正时新增的节点也要放得下
+pub const DEVICE_TREE_MAX_SIZE: usize = 0x2_0000;
+
+/// 合并镜像中带有 initrd 时，固件把它放到这个地址
+pub const INITRD_ADDRESS: usize = LAYOUT.initrd_address;
+
+/// 下一阶段是 ELF 文件时，固件先把整个文件放到这里，再从这里加载各个段
+pub const ELF_STAGING_ADDRESS: usize = LAYOUT.elf_staging_address;
@@ -0,0 +1,179 @@
+//! 按 hartid 访问的 CLINT（或 ACLINT 的 MSWI 和 MTIMER）。
+//!
+//! 每个核有一个 32 位的 MSIP 寄存器和一个 64 位的 MTIMECMP 寄存器，按 hartid 依次排列；
+//! MTIME 所有核共用一个。各组寄存器的偏移由 Layout 给出，玄铁 C9xx 的 CLINT 没有 MTIME。
+//! 所有访问都是 32 位的，64 位的寄存器分高低两半读写。
+#![no_std]
+
+use core::ptr::{read_volatile, write_volatile};
+
+/// 各组寄存器相对于 CLINT 基地址的偏移
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    /// hart 0 的 MSIP，之后每个核 4 字节
+    pub msip: usize,
+    /// hart 0 的 MTIMECMP，之后每个核 8 字节
+    pub mtimecmp: usize,
+    /// MTIME，没有映射到内存时为 None
+    pub mtime: Option<usize>,
+}
+
+impl Layout {
+    /// SiFive CLINT 的布局，QEMU virt 使用这种布局
+    pub const SIFIVE: Layout = Layout { msip: 0x0, mtimecmp: 0x4000, mtime: Some(0xbff8) };
+    /// 玄铁 C9xx 的 CLINT，time 寄存器由 CSR 读取，MTIME 不在 CLINT 中
+    pub const THEAD: Layout = Layout { msip: 0x0, mtimecmp: 0x4000, mtime: None };
+}
+
+/// 一个 CLINT 设备
+#[derive(Debug, Clone, Copy)]
+pub struct Clint {
+    base: usize,
+    layout: Layout,
+}
+
+impl Clint {
+    /// # Safety
+    ///
+    /// base 开始的内存必须是按 layout 排列的 CLINT 寄存器，并且覆盖所有用到的 hartid
+    pub const unsafe fn new(base: usize, layout: Layout) -> Self {
+        Self { base, layout }
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// 向核 hartid 发送 M 态软件中断
+    pub fn set_msip(&self, hartid: usize) {
+        self.write_u32(self.msip_offset(hartid), 1)
+    }
+
+    pub fn clear_msip(&self, hartid: usize) {
+        self.write_u32(self.msip_offset(hartid), 0)
+    }
+
+    pub fn read_msip(&self, hartid: usize) -> bool {
+        self.read_u32(self.msip_offset(hartid)) & 1 != 0
+    }
+
+    /// 设置核 hartid 的时钟比较值，同时清除它的时钟中断
+    pub fn write_mtimecmp(&self, hartid: usize, value: u64) {
+        let offset = self.mtimecmp_offset(hartid);
+        // 先把低半部分写成最大值，写高半部分的过程中不会产生多余的中断
+        self.write_u32(offset, u32::MAX);
+        self.write_u32(offset + 4, (value >> 32) as u32);
+        self.write_u32(offset, value as u32);
+    }
+
+    pub fn read_mtimecmp(&self, hartid: usize) -> u64 {
+        self.read_u64(self.mtimecmp_offset(hartid))
+    }
+
+    /// 读取 MTIME，没有映射到内存时返回 None
+    pub fn read_mtime(&self) -> Option<u64> {
+        self.layout.mtime.map(|offset| self.read_u64(offset))
+    }
+
+    fn msip_offset(&self, hartid: usize) -> usize {
+        self.layout.msip + 4 * hartid
+    }
+
+    fn mtimecmp_offset(&self, hartid: usize) -> usize {
+        self.layout.mtimecmp + 8 * hartid
+    }
+
+    // 两次读到的高半部分相同时，低半部分没有在读的过程中进位
+    fn read_u64(&self, offset: usize) -> u64 {
+        loop {
+            let hi = self.read_u32(offset + 4);
+            let lo = self.read_u32(offset);
+            if self.read_u32(offset + 4) == hi {
+                return (hi as u64) << 32 | lo as u64;
+            }
+        }
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write_u32(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{Clint, Layout};
+    use std::{boxed::Box, vec};
+
+    // 用一块普通内存模拟 CLINT 的寄存器
+    struct Mmio(Box<[u32]>);
+
+    impl Mmio {
+        fn new() -> Self {
+            Mmio(vec![0; 0xc000 / 4].into_boxed_slice())
+        }
+
+        fn clint(&mut self, layout: Layout) -> Clint {
+            unsafe { Clint::new(self.0.as_mut_ptr() as usize, layout) }
+        }
+
+        fn word(&self, offset: usize) -> u32 {
+            self.0[offset / 4]
+        }
+
+        fn set_word(&mut self, offset: usize, value: u32) {
+            self.0[offset / 4] = value;
+        }
+    }
+
+    #[test]
+    fn msip_is_indexed_by_hartid() {
+        let mut mmio = Mmio::new();
+        let clint = mmio.clint(Layout::SIFIVE);
+        clint.set_msip(1);
+        clint.set_msip(3);
+        assert!(!clint.read_msip(0));
+        assert!(clint.read_msip(1));
+        assert!(clint.read_msip(3));
+        clint.clear_msip(1);
+        assert!(!clint.read_msip(1));
+        assert_eq!((mmio.word(0x0), mmio.word(0x4), mmio.word(0x8), mmio.word(0xc)), (0, 0, 0, 1));
+    }
+
+    #[test]
+    fn mtimecmp_is_indexed_by_hartid() {
+        let mut mmio = Mmio::new();
+        let clint = mmio.clint(Layout::SIFIVE);
+        clint.write_mtimecmp(0, 0x1111_2222_3333_4444);
+        clint.write_mtimecmp(2, 0x5555_6666_7777_8888);
+        assert_eq!(clint.read_mtimecmp(0), 0x1111_2222_3333_4444);
+        assert_eq!(clint.read_mtimecmp(1), 0);
+        assert_eq!(clint.read_mtimecmp(2), 0x5555_6666_7777_8888);
+        assert_eq!((mmio.word(0x4010), mmio.word(0x4014)), (0x7777_8888, 0x5555_6666));
+    }
+
+    #[test]
+    fn mtime_follows_layout() {
+        let mut mmio = Mmio::new();
+        mmio.set_word(0xbff8, 0x9abc_def0);
+        mmio.set_word(0xbffc, 0x1234_5678);
+        assert_eq!(mmio.clint(Layout::SIFIVE).read_mtime(), Some(0x1234_5678_9abc_def0));
+        assert_eq!(mmio.clint(Layout::THEAD).read_mtime(), None);
+    }
+
+    #[test]
+    fn custom_layout() {
+        let mut mmio = Mmio::new();
+        let layout = Layout { msip: 0x100, mtimecmp: 0x200, mtime: Some(0x300) };
+        let clint = mmio.clint(layout);
+        clint.set_msip(2);
+        clint.write_mtimecmp(1, u64::MAX - 1);
+        assert_eq!(mmio.word(0x108), 1);
+        assert_eq!((mmio.word(0x208), mmio.word(0x20c)), (u32::MAX - 1, u32::MAX));
+    }
+}
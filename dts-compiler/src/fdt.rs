@@ -0,0 +1,192 @@
+//! 解析引用并生成扁平设备树，布局和 dtc 的输出相同
+use std::collections::HashMap;
+
+use crate::{Chunk, Error, Location, Node, Property, Reference, Tree};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const HEADER_SIZE: usize = 40;
+
+pub fn flatten(tree: &mut Tree) -> Result<Vec<u8>, Error> {
+    check_labels(&tree.root, &mut Vec::new(), &mut HashMap::new(), tree)?;
+    resolve_references(tree)?;
+
+    let mut reserve_map = Vec::new();
+    for &(address, size) in tree.memreserves.iter().chain(Some(&(0, 0))) {
+        reserve_map.extend_from_slice(&address.to_be_bytes());
+        reserve_map.extend_from_slice(&size.to_be_bytes());
+    }
+    let mut structure = Vec::new();
+    let mut strings = Vec::new();
+    write_node(&tree.root, &mut structure, &mut strings);
+    structure.extend_from_slice(&FDT_END.to_be_bytes());
+
+    let off_mem_rsvmap = HEADER_SIZE;
+    let off_dt_struct = off_mem_rsvmap + reserve_map.len();
+    let off_dt_strings = off_dt_struct + structure.len();
+    let total_size = off_dt_strings + strings.len();
+    let header = [
+        FDT_MAGIC,
+        total_size as u32,
+        off_dt_struct as u32,
+        off_dt_strings as u32,
+        off_mem_rsvmap as u32,
+        FDT_VERSION,
+        FDT_LAST_COMP_VERSION,
+        0,
+        strings.len() as u32,
+        structure.len() as u32,
+    ];
+    let mut blob = Vec::with_capacity(total_size);
+    for word in header.iter() {
+        blob.extend_from_slice(&word.to_be_bytes());
+    }
+    blob.extend_from_slice(&reserve_map);
+    blob.extend_from_slice(&structure);
+    blob.extend_from_slice(&strings);
+    Ok(blob)
+}
+
+// 标签在整棵树中不能重复
+fn check_labels<'t>(node: &'t Node, path: &mut Vec<usize>, seen: &mut HashMap<&'t str, Vec<usize>>, tree: &Tree) -> Result<(), Error> {
+    for label in &node.labels {
+        if let Some(other) = seen.insert(label, path.clone()) {
+            return Err(Error {
+                file: "<device tree>".into(),
+                line: 0,
+                column: 0,
+                message: format!("duplicate label {} on {} and {}", label, tree.path_string(&other), tree.path_string(path)),
+            });
+        }
+    }
+    for (index, child) in node.children.iter().enumerate() {
+        path.push(index);
+        check_labels(child, path, seen, tree)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+// 引用所在的节点、属性和属性值中的位置
+struct Use {
+    node: Vec<usize>,
+    property: usize,
+    chunk: usize,
+    target: Reference,
+    location: Location,
+    phandle: bool,
+}
+
+fn resolve_references(tree: &mut Tree) -> Result<(), Error> {
+    let mut uses = Vec::new();
+    let mut max_phandle = 0;
+    collect(&tree.root, &mut Vec::new(), &mut uses, &mut max_phandle);
+    for reference in uses {
+        let target = tree.resolve(&reference.target)
+            .ok_or_else(|| reference.location.error(format!("reference to missing node {}", reference.target)))?;
+        let bytes = if reference.phandle {
+            let phandle = match phandle_of(tree.node(&target)) {
+                Some(phandle) => phandle,
+                None => {
+                    // 没有 phandle 的节点被引用时分配一个新的
+                    max_phandle += 1;
+                    tree.node_mut(&target).properties.push(Property {
+                        name: "phandle".into(),
+                        value: vec![Chunk::Bytes(max_phandle.to_be_bytes().to_vec())],
+                    });
+                    max_phandle
+                }
+            };
+            phandle.to_be_bytes().to_vec()
+        } else {
+            let mut path = tree.path_string(&target).into_bytes();
+            path.push(0);
+            path
+        };
+        let node = tree.node_mut(&reference.node);
+        node.properties[reference.property].value[reference.chunk] = Chunk::Bytes(bytes);
+    }
+    Ok(())
+}
+
+fn collect(node: &Node, path: &mut Vec<usize>, uses: &mut Vec<Use>, max_phandle: &mut u32) {
+    if let Some(phandle) = phandle_of(node) {
+        *max_phandle = (*max_phandle).max(phandle);
+    }
+    for (property_index, property) in node.properties.iter().enumerate() {
+        for (chunk_index, chunk) in property.value.iter().enumerate() {
+            let (target, location, phandle) = match chunk {
+                Chunk::Bytes(_) => continue,
+                Chunk::Phandle(target, location) => (target, location, true),
+                Chunk::Path(target, location) => (target, location, false),
+            };
+            uses.push(Use {
+                node: path.clone(),
+                property: property_index,
+                chunk: chunk_index,
+                target: target.clone(),
+                location: location.clone(),
+                phandle,
+            });
+        }
+    }
+    for (index, child) in node.children.iter().enumerate() {
+        path.push(index);
+        collect(child, path, uses, max_phandle);
+        path.pop();
+    }
+}
+
+fn phandle_of(node: &Node) -> Option<u32> {
+    let property = node.properties.iter().find(|p| p.name == "phandle" || p.name == "linux,phandle")?;
+    match property.value.as_slice() {
+        [Chunk::Bytes(bytes)] if bytes.len() == 4 => Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        _ => None,
+    }
+}
+
+fn write_node(node: &Node, structure: &mut Vec<u8>, strings: &mut Vec<u8>) {
+    structure.extend_from_slice(&FDT_BEGIN_NODE.to_be_bytes());
+    structure.extend_from_slice(node.name.as_bytes());
+    structure.push(0);
+    pad(structure);
+    for property in &node.properties {
+        let value: Vec<u8> = property.value.iter().flat_map(|chunk| match chunk {
+            Chunk::Bytes(bytes) => bytes.iter().copied(),
+            _ => unreachable!("references are resolved before flattening"),
+        }).collect();
+        structure.extend_from_slice(&FDT_PROP.to_be_bytes());
+        structure.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        structure.extend_from_slice(&string_offset(strings, &property.name).to_be_bytes());
+        structure.extend_from_slice(&value);
+        pad(structure);
+    }
+    for child in &node.children {
+        write_node(child, structure, strings);
+    }
+    structure.extend_from_slice(&FDT_END_NODE.to_be_bytes());
+}
+
+// 和 dtc 一样，名字是已有字符串的后缀时共用它
+fn string_offset(strings: &mut Vec<u8>, name: &str) -> u32 {
+    let needle: Vec<u8> = name.bytes().chain(Some(0)).collect();
+    for start in 0..strings.len() {
+        if strings[start..].starts_with(&needle) {
+            return start as u32;
+        }
+    }
+    let offset = strings.len();
+    strings.extend_from_slice(&needle);
+    offset as u32
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    while buf.len() & 3 != 0 {
+        buf.push(0);
+    }
+}
@@ -0,0 +1,619 @@
+//! 把设备树源文件编译成扁平设备树（DTB），固件的 build.rs 用它生成内置的设备树。
+//!
+//! 支持 dtc 的源文件语法，不支持 C 预处理器。叠加文件使用同样的语法，
+//! 其中的 `/ { ... }`、`&label { ... }` 和 `&{/path} { ... }` 按顺序合并到基础设备树上。
+mod fdt;
+mod parser;
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// 编译错误，带有出错的文件和位置
+#[derive(Debug)]
+pub struct Error {
+    pub file: PathBuf,
+    /// 从 1 开始的行号，读文件出错时为 0
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file.display(), self.message)
+        } else {
+            write!(f, "{}:{}:{}: {}", self.file.display(), self.line, self.column, self.message)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// 编译好的设备树，以及编译时读过的所有文件
+pub struct Output {
+    pub dtb: Vec<u8>,
+    pub files: Vec<PathBuf>,
+}
+
+/// 编译 base，再按顺序合并 overlays
+pub fn compile(base: &Path, overlays: &[PathBuf]) -> Result<Output, Error> {
+    let mut files = Vec::new();
+    let mut tree = Tree::default();
+    let fragments = parser::parse_file(base, true, &mut files)?;
+    tree.apply(fragments)?;
+    for overlay in overlays {
+        let fragments = parser::parse_file(overlay, false, &mut files)?;
+        tree.apply(fragments)?;
+    }
+    let dtb = fdt::flatten(&mut tree)?;
+    Ok(Output { dtb, files })
+}
+
+pub(crate) fn read_source(path: &Path) -> Result<String, Error> {
+    fs::read_to_string(path).map_err(|e: io::Error| Error {
+        file: path.to_path_buf(),
+        line: 0,
+        column: 0,
+        message: e.to_string(),
+    })
+}
+
+/// 源文件中的位置，用来报告解析之后才发现的错误
+#[derive(Debug, Clone)]
+pub(crate) struct Location {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    pub fn error(&self, message: String) -> Error {
+        Error { file: self.file.clone(), line: self.line, column: self.column, message }
+    }
+}
+
+/// 对节点的引用
+#[derive(Debug, Clone)]
+pub(crate) enum Reference {
+    Label(String),
+    Path(String),
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reference::Label(label) => write!(f, "&{}", label),
+            Reference::Path(path) => write!(f, "&{{{}}}", path),
+        }
+    }
+}
+
+/// 属性值的一段，引用在所有文件合并之后才能确定
+#[derive(Debug, Clone)]
+pub(crate) enum Chunk {
+    Bytes(Vec<u8>),
+    /// 单元格中的引用，替换成节点的 phandle
+    Phandle(Reference, Location),
+    /// 单元格之外的引用，替换成节点的完整路径
+    Path(Reference, Location),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Property {
+    pub name: String,
+    pub value: Vec<Chunk>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Node {
+    pub name: String,
+    pub labels: Vec<String>,
+    pub properties: Vec<Property>,
+    pub children: Vec<Node>,
+}
+
+/// 节点体中的一项，合并时按顺序应用
+#[derive(Debug)]
+pub(crate) enum Item {
+    Property(Property),
+    Child { name: String, labels: Vec<String>, body: Vec<Item> },
+    DeleteProperty(String),
+    DeleteNode(String),
+}
+
+/// 文件顶层的一项
+#[derive(Debug)]
+pub(crate) enum Fragment {
+    MemReserve(u64, u64),
+    Node { target: Option<Reference>, labels: Vec<String>, body: Vec<Item>, location: Location },
+    DeleteNode(Reference, Location),
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Tree {
+    pub memreserves: Vec<(u64, u64)>,
+    pub root: Node,
+}
+
+impl Tree {
+    fn apply(&mut self, fragments: Vec<Fragment>) -> Result<(), Error> {
+        for fragment in fragments {
+            match fragment {
+                Fragment::MemReserve(address, size) => self.memreserves.push((address, size)),
+                Fragment::Node { target, labels, body, location } => {
+                    let node = match &target {
+                        None => &mut self.root,
+                        Some(target) => {
+                            let path = self.resolve(target).ok_or_else(|| location.error(format!("reference to missing node {}", target)))?;
+                            self.node_mut(&path)
+                        }
+                    };
+                    merge(node, labels, body);
+                }
+                Fragment::DeleteNode(target, location) => {
+                    let path = self.resolve(&target).ok_or_else(|| location.error(format!("reference to missing node {}", target)))?;
+                    let (index, parent) = path.split_last().ok_or_else(|| location.error("cannot delete the root node".into()))?;
+                    self.node_mut(parent).children.remove(*index);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 找到引用的节点，返回从根节点开始的子节点下标
+    pub fn resolve(&self, reference: &Reference) -> Option<Vec<usize>> {
+        match reference {
+            Reference::Label(label) => find_label(&self.root, label, &mut Vec::new()),
+            Reference::Path(path) => {
+                let mut node = &self.root;
+                let mut indices = Vec::new();
+                for component in path.split('/').filter(|c| !c.is_empty()) {
+                    // 和 dtc 一样，不带单元地址的名字也能匹配唯一的节点
+                    let index = node.children.iter().position(|child| child.name == component)
+                        .or_else(|| node.children.iter().position(|child| {
+                            !component.contains('@') && child.name.split('@').next() == Some(component)
+                        }))?;
+                    indices.push(index);
+                    node = &node.children[index];
+                }
+                Some(indices)
+            }
+        }
+    }
+
+    pub fn node_mut(&mut self, path: &[usize]) -> &mut Node {
+        path.iter().fold(&mut self.root, |node, &index| &mut node.children[index])
+    }
+
+    pub fn node(&self, path: &[usize]) -> &Node {
+        path.iter().fold(&self.root, |node, &index| &node.children[index])
+    }
+
+    /// 节点的完整路径
+    pub fn path_string(&self, path: &[usize]) -> String {
+        if path.is_empty() {
+            return "/".into();
+        }
+        let mut node = &self.root;
+        let mut result = String::new();
+        for &index in path {
+            node = &node.children[index];
+            result.push('/');
+            result.push_str(&node.name);
+        }
+        result
+    }
+}
+
+fn find_label(node: &Node, label: &str, path: &mut Vec<usize>) -> Option<Vec<usize>> {
+    if node.labels.iter().any(|l| l == label) {
+        return Some(path.clone());
+    }
+    for (index, child) in node.children.iter().enumerate() {
+        path.push(index);
+        if let Some(found) = find_label(child, label, path) {
+            return Some(found);
+        }
+        path.pop();
+    }
+    None
+}
+
+// 同名的属性替换原来的值，同名的子节点递归合并
+fn merge(node: &mut Node, labels: Vec<String>, body: Vec<Item>) {
+    for label in labels {
+        if !node.labels.contains(&label) {
+            node.labels.push(label);
+        }
+    }
+    for item in body {
+        match item {
+            Item::Property(property) => match node.properties.iter_mut().find(|p| p.name == property.name) {
+                Some(existing) => existing.value = property.value,
+                None => node.properties.push(property),
+            },
+            Item::Child { name, labels, body } => {
+                let index = match node.children.iter().position(|child| child.name == name) {
+                    Some(index) => index,
+                    None => {
+                        node.children.push(Node { name, ..Node::default() });
+                        node.children.len() - 1
+                    }
+                };
+                merge(&mut node.children[index], labels, body);
+            }
+            Item::DeleteProperty(name) => node.properties.retain(|p| p.name != name),
+            Item::DeleteNode(name) => node.children.retain(|child| child.name != name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile, Error};
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        process,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    // 把源文件写到临时目录中，返回它们的路径
+    fn sources(files: &[(&str, &str)]) -> Vec<PathBuf> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!("dts-compiler-{}-{}", process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        fs::create_dir_all(&dir).unwrap();
+        files.iter().map(|(name, source)| {
+            let path = dir.join(name);
+            fs::write(&path, source).unwrap();
+            path
+        }).collect()
+    }
+
+    fn compile_sources(base: &str, overlays: &[&str]) -> Result<Vec<u8>, Error> {
+        let mut files = vec![("base.dts", base)];
+        let names: Vec<String> = (0..overlays.len()).map(|i| format!("overlay{}.dtso", i)).collect();
+        files.extend(names.iter().map(|name| name.as_str()).zip(overlays.iter().copied()));
+        let paths = sources(&files);
+        compile(&paths[0], &paths[1..]).map(|output| output.dtb)
+    }
+
+    fn error_at(base: &str, overlays: &[&str]) -> (String, usize, usize, String) {
+        let e = compile_sources(base, overlays).expect_err("compile should fail");
+        (e.file.file_name().unwrap().to_string_lossy().into_owned(), e.line, e.column, e.message)
+    }
+
+    fn be32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    }
+
+    fn cstr(bytes: &[u8]) -> &str {
+        std::str::from_utf8(&bytes[..bytes.iter().position(|&b| b == 0).unwrap()]).unwrap()
+    }
+
+    #[derive(Debug, Default)]
+    struct Node {
+        name: String,
+        properties: Vec<(String, Vec<u8>)>,
+        children: Vec<Node>,
+    }
+
+    impl Node {
+        fn find(&self, path: &str) -> &Node {
+            path.split('/').filter(|c| !c.is_empty()).fold(self, |node, name| {
+                node.children.iter().find(|child| child.name == name).unwrap_or_else(|| panic!("no node {}", path))
+            })
+        }
+
+        fn property(&self, name: &str) -> Option<&[u8]> {
+            self.properties.iter().find(|(n, _)| n == name).map(|(_, value)| &value[..])
+        }
+
+        fn child_names(&self) -> Vec<&str> {
+            self.children.iter().map(|child| child.name.as_str()).collect()
+        }
+    }
+
+    // 解析编译出来的设备树，同时检查头部和结构块的格式
+    fn decode(blob: &[u8]) -> (Vec<(u64, u64)>, Node) {
+        assert_eq!(be32(blob, 0), 0xd00d_feed);
+        assert_eq!(be32(blob, 4) as usize, blob.len());
+        let (structure, strings, reserve) = (be32(blob, 8) as usize, be32(blob, 12) as usize, be32(blob, 16) as usize);
+        assert_eq!((be32(blob, 20), be32(blob, 24)), (17, 16));
+        assert_eq!(strings + be32(blob, 32) as usize, blob.len());
+        assert_eq!(structure + be32(blob, 36) as usize, strings);
+        let mut memreserves = Vec::new();
+        for entry in blob[reserve..structure].chunks(16) {
+            let address = (be32(entry, 0) as u64) << 32 | be32(entry, 4) as u64;
+            let size = (be32(entry, 8) as u64) << 32 | be32(entry, 12) as u64;
+            if (address, size) == (0, 0) {
+                break;
+            }
+            memreserves.push((address, size));
+        }
+        let mut pos = structure;
+        let mut stack: Vec<Node> = Vec::new();
+        let mut root = None;
+        loop {
+            let token = be32(blob, pos);
+            pos += 4;
+            match token {
+                1 => {
+                    let name = cstr(&blob[pos..]).to_string();
+                    pos = (pos + name.len() + 1 + 3) & !3;
+                    stack.push(Node { name, ..Node::default() });
+                }
+                2 => {
+                    let node = stack.pop().unwrap();
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(node),
+                        None => root = Some(node),
+                    }
+                }
+                3 => {
+                    let len = be32(blob, pos) as usize;
+                    let name = cstr(&blob[strings + be32(blob, pos + 4) as usize..]).to_string();
+                    let value = blob[pos + 8..pos + 8 + len].to_vec();
+                    pos = (pos + 8 + len + 3) & !3;
+                    stack.last_mut().unwrap().properties.push((name, value));
+                }
+                9 => break,
+                other => panic!("bad token {} at {:#x}", other, pos - 4),
+            }
+        }
+        assert_eq!(pos, strings);
+        (memreserves, root.expect("no root node"))
+    }
+
+    fn cells(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_be_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn values_and_expressions() {
+        let dtb = compile_sources(r#"/dts-v1/;
+/memreserve/ 0x40000000 0x200000;
+/ {
+    empty;
+    string = "a\tb\x41", "c";
+    cells = <1 0x10 017 'A' (1 + 2 * 3) (-1) ((1 << 4) | 3)>;
+    bytes = [00 ab CD];
+    wide = /bits/ 64 <0x100000000>, /bits/ 8 <0xff 1>;
+};
+"#, &[]).unwrap();
+        let (memreserves, root) = decode(&dtb);
+        assert_eq!(memreserves, [(0x4000_0000, 0x20_0000)]);
+        assert_eq!(root.name, "");
+        assert_eq!(root.property("empty"), Some(&[][..]));
+        assert_eq!(root.property("string"), Some(&b"a\tbA\0c\0"[..]));
+        assert_eq!(root.property("cells").unwrap(), &cells(&[1, 0x10, 0o17, 0x41, 7, 0xffff_ffff, 0x13])[..]);
+        assert_eq!(root.property("bytes"), Some(&[0x00, 0xab, 0xcd][..]));
+        assert_eq!(root.property("wide"), Some(&[0, 0, 0, 1, 0, 0, 0, 0, 0xff, 1][..]));
+    }
+
+    #[test]
+    fn parse_errors_have_locations() {
+        assert_eq!(error_at("/ { };\n", &[]), ("base.dts".into(), 1, 1, "missing /dts-v1/ tag".into()));
+        assert_eq!(error_at("/dts-v1/;\n/ {\n\tfoo = <1 2>\n};\n", &[]), ("base.dts".into(), 4, 1, "expected ';'".into()));
+        let (_, line, column, message) = error_at("/dts-v1/;\n/ {\n  foo = <1 0x1g>;\n};\n", &[]);
+        assert_eq!((line, column, message.as_str()), (3, 12, "expected an integer, found \"0x1g\""));
+        let (_, line, column, message) = error_at("/dts-v1/;\n/ { s = \"abc\n\"; };\n", &[]);
+        assert_eq!((line, column, message.as_str()), (2, 13, "unterminated string"));
+        let (_, line, column, _) = error_at("/dts-v1/;\n#include <foo.h>\n", &[]);
+        assert_eq!((line, column), (2, 1));
+        let (_, line, column, _) = error_at("/dts-v1/;\n/ {\n  /* comment\n", &[]);
+        assert_eq!((line, column), (3, 3));
+        let (_, line, column, message) = error_at("/dts-v1/;\n/ { c = /bits/ 16 <0x10000>; };\n", &[]);
+        assert_eq!((line, column, message.as_str()), (2, 20, "0x10000 does not fit in a 16-bit cell"));
+    }
+
+    #[test]
+    fn errors_in_overlays_name_the_overlay() {
+        let base = "/dts-v1/;\n/ { uart: uart { }; };\n";
+        let (file, line, column, message) = error_at(base, &["\n\n  &missing { status = \"okay\"; };\n"]);
+        assert_eq!((file.as_str(), line, column), ("overlay0.dtso", 3, 3));
+        assert_eq!(message, "reference to missing node &missing");
+        let (file, line, column, _) = error_at(base, &["&uart { x = <&nope>; };\n"]);
+        assert_eq!((file.as_str(), line, column), ("overlay0.dtso", 1, 14));
+    }
+
+    #[test]
+    fn labels_and_phandles() {
+        let dtb = compile_sources(r#"/dts-v1/;
+/ {
+    intc: interrupt-controller@1000 {
+        phandle = <5>;
+    };
+    clk: clock {
+    };
+    soc {
+        uart@2000 {
+            interrupt-parent = <&intc>;
+            clocks = <&clk 3>, <&{/soc/timer}>;
+            alias = &clk;
+        };
+        other: timer@3000 {
+            clocks = <&clk 4>;
+        };
+    };
+    aliases {
+        timer = &{/soc/timer@3000};
+    };
+};
+"#, &[]).unwrap();
+        let (_, root) = decode(&dtb);
+        // 已有的 phandle 不变，新分配的从最大的 phandle 之后开始
+        assert_eq!(root.find("/interrupt-controller@1000").property("phandle").unwrap(), &cells(&[5])[..]);
+        assert_eq!(root.find("/clock").property("phandle").unwrap(), &cells(&[6])[..]);
+        assert_eq!(root.find("/soc/timer@3000").property("phandle").unwrap(), &cells(&[7])[..]);
+        let uart = root.find("/soc/uart@2000");
+        assert_eq!(uart.property("interrupt-parent").unwrap(), &cells(&[5])[..]);
+        assert_eq!(uart.property("clocks").unwrap(), &cells(&[6, 3, 7])[..]);
+        assert_eq!(uart.property("alias"), Some(&b"/clock\0"[..]));
+        assert_eq!(root.find("/soc/timer@3000").property("clocks").unwrap(), &cells(&[6, 4])[..]);
+        assert_eq!(root.find("/aliases").property("timer"), Some(&b"/soc/timer@3000\0"[..]));
+        // 标签本身不出现在设备树中
+        assert!(root.find("/clock").properties.iter().all(|(name, _)| name == "phandle"));
+    }
+
+    #[test]
+    fn duplicate_labels_are_rejected() {
+        let e = compile_sources("/dts-v1/;\n/ { a: x { }; a: y { }; };\n", &[]).unwrap_err();
+        assert_eq!(e.message, "duplicate label a on /x and /y");
+    }
+
+    #[test]
+    fn overlays_merge_and_override() {
+        let base = r#"/dts-v1/;
+/ {
+    #address-cells = <1>;
+    soc {
+        uart0: uart@100 {
+            status = "disabled";
+            clocks = <1>;
+        };
+    };
+};
+"#;
+        let first = r#"
+&uart0 {
+    status = "okay";
+    pinctrl { pins = "PB8"; };
+};
+&{/soc} {
+    new_label: spi@200 { status = "disabled"; };
+};
+"#;
+        let second = r#"
+/ {
+    #address-cells = <2>;
+    chosen { stdout-path = &uart0; };
+};
+&new_label {
+    status = "okay";
+    /delete-property/ missing;
+};
+&{/soc/uart} {
+    /delete-property/ clocks;
+    pinctrl { pins = "PB9"; };
+};
+"#;
+        let dtb = compile_sources(base, &[first, second]).unwrap();
+        let (_, root) = decode(&dtb);
+        assert_eq!(root.property("#address-cells").unwrap(), &cells(&[2])[..]);
+        let uart = root.find("/soc/uart@100");
+        // 覆盖的属性保持原来的位置
+        assert_eq!(uart.properties.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["status"]);
+        assert_eq!(uart.property("status"), Some(&b"okay\0"[..]));
+        assert_eq!(uart.find("pinctrl").property("pins"), Some(&b"PB9\0"[..]));
+        assert_eq!(root.find("/soc").child_names(), ["uart@100", "spi@200"]);
+        assert_eq!(root.find("/soc/spi@200").property("status"), Some(&b"okay\0"[..]));
+        assert_eq!(root.find("/chosen").property("stdout-path"), Some(&b"/soc/uart@100\0"[..]));
+        assert_eq!(root.child_names(), ["soc", "chosen"]);
+    }
+
+    #[test]
+    fn delete_node() {
+        let base = r#"/dts-v1/;
+/ {
+    soc {
+        a: first { };
+        second { };
+        third { child { }; };
+    };
+    keep { };
+};
+/delete-node/ &a;
+/ {
+    soc {
+        /delete-node/ third;
+    };
+};
+"#;
+        let dtb = compile_sources(base, &["/delete-node/ &{/keep};\n/ { keep { again; }; };\n"]).unwrap();
+        let (_, root) = decode(&dtb);
+        assert_eq!(root.find("/soc").child_names(), ["second"]);
+        // 删除之后再定义的节点是新的节点
+        assert_eq!(root.child_names(), ["soc", "keep"]);
+        assert_eq!(root.find("/keep").property("again"), Some(&[][..]));
+        // 删除的节点上的标签不能再引用
+        let (_, line, column, message) = error_at(base, &["\n&a { };\n"]);
+        assert_eq!((line, column, message.as_str()), (2, 1, "reference to missing node &a"));
+        let (_, _, _, message) = error_at("/dts-v1/;\n/ { };\n/delete-node/ &{/};\n", &[]);
+        assert_eq!(message, "cannot delete the root node");
+    }
+
+    #[test]
+    fn include_is_relative_to_the_file() {
+        let paths = sources(&[
+            ("board.dts", "/dts-v1/;\n/include/ \"common.dtsi\"\n&uart { status = \"okay\"; };\n"),
+            ("common.dtsi", "/ { uart: uart { status = \"disabled\"; }; };\n"),
+        ]);
+        let output = compile(&paths[0], &[]).unwrap();
+        assert_eq!(output.files, paths);
+        let (_, root) = decode(&output.dtb);
+        assert_eq!(root.find("/uart").property("status"), Some(&b"okay\0"[..]));
+    }
+
+    // 按 dtc 反编译的格式输出设备树
+    fn to_source(memreserves: &[(u64, u64)], root: &Node) -> String {
+        fn value(bytes: &[u8]) -> String {
+            let is_strings = !bytes.is_empty() && bytes[bytes.len() - 1] == 0 && bytes[0] != 0
+                && !bytes.windows(2).any(|w| w == [0, 0])
+                && bytes.iter().all(|&b| b == 0 || (0x20..0x7f).contains(&b));
+            if is_strings {
+                let strings: Vec<_> = bytes[..bytes.len() - 1].split(|&b| b == 0)
+                    .map(|s| String::from_utf8_lossy(s).replace('\\', "\\\\").replace('"', "\\\"")).collect();
+                format!("\"{}\"", strings.join("\\0"))
+            } else if bytes.len() & 3 == 0 {
+                let words: Vec<_> = bytes.chunks(4).map(|w| format!("{:#04x}", be32(w, 0))).collect();
+                format!("<{}>", words.join(" "))
+            } else {
+                let bytes: Vec<_> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("[{}]", bytes.join(" "))
+            }
+        }
+        fn write_node(node: &Node, depth: usize, out: &mut String) {
+            let indent = "\t".repeat(depth);
+            let name = if depth == 0 { "/" } else { &node.name };
+            out.push_str(&format!("{}{} {{\n", indent, name));
+            for (name, bytes) in &node.properties {
+                if bytes.is_empty() {
+                    out.push_str(&format!("{}\t{};\n", indent, name));
+                } else {
+                    out.push_str(&format!("{}\t{} = {};\n", indent, name, value(bytes)));
+                }
+            }
+            for child in &node.children {
+                out.push('\n');
+                write_node(child, depth + 1, out);
+            }
+            out.push_str(&format!("{}}};\n", indent));
+        }
+        let mut out = String::from("/dts-v1/;\n\n");
+        for (address, size) in memreserves {
+            out.push_str(&format!("/memreserve/\t{:#018x} {:#018x};\n", address, size));
+        }
+        write_node(root, 0, &mut out);
+        out
+    }
+
+    // sunxi.dts 是 dtc 反编译的输出，编译之后再按同样的格式输出应当得到原来的文件
+    #[test]
+    fn sunxi_golden() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../rustsbi-nezha/dts/sunxi.dts");
+        let output = compile(&path, &[]).unwrap();
+        let (memreserves, root) = decode(&output.dtb);
+        assert_eq!(memreserves, [(0x4000_0000, 0x20_0000), (0x4200_0000, 0x10_0000)]);
+        assert_eq!(root.property("model"), Some(&b"sun20iw1p1\0"[..]));
+        let expected = fs::read_to_string(&path).unwrap();
+        let actual = to_source(&memreserves, &root);
+        for (index, (expected, actual)) in expected.lines().zip(actual.lines()).enumerate() {
+            assert_eq!(expected, actual, "sunxi.dts line {}", index + 1);
+        }
+        assert_eq!(expected.lines().count(), actual.lines().count());
+    }
+}
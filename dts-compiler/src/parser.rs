@@ -0,0 +1,496 @@
+//! 设备树源文件的解析，语法和 dtc 相同。单元格中只有字面量可以直接写，表达式要加括号
+use std::path::{Path, PathBuf};
+
+use crate::{read_source, Chunk, Error, Fragment, Item, Location, Property, Reference};
+
+/// 解析一个文件，/include/ 的文件也一起解析。files 记录读过的文件
+pub fn parse_file(path: &Path, require_version: bool, files: &mut Vec<PathBuf>) -> Result<Vec<Fragment>, Error> {
+    let source = read_source(path)?;
+    files.push(path.to_path_buf());
+    let mut parser = Parser { file: path, src: source.as_bytes(), pos: 0 };
+    parser.file(require_version, files)
+}
+
+struct Parser<'a> {
+    file: &'a Path,
+    src: &'a [u8],
+    pos: usize,
+}
+
+// 属性名和节点名可以使用的字符
+fn is_name_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b",._+*#?@-".contains(&c)
+}
+
+fn is_label_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+impl<'a> Parser<'a> {
+    fn file(&mut self, require_version: bool, files: &mut Vec<PathBuf>) -> Result<Vec<Fragment>, Error> {
+        let mut fragments = Vec::new();
+        self.skip_whitespace()?;
+        if require_version && !self.src[self.pos..].starts_with(b"/dts-v1/") {
+            return Err(self.error("missing /dts-v1/ tag"));
+        }
+        loop {
+            self.skip_whitespace()?;
+            if self.pos == self.src.len() {
+                break;
+            }
+            let location = self.location();
+            if self.eat_keyword("/dts-v1/") {
+                self.expect(b';')?;
+            } else if self.eat_keyword("/plugin/") {
+                return Err(self.error("/plugin/ overlays are not supported, write the overlay as plain source fragments"));
+            } else if self.eat_keyword("/memreserve/") {
+                let address = self.literal()?;
+                let size = self.literal()?;
+                self.expect(b';')?;
+                fragments.push(Fragment::MemReserve(address, size));
+            } else if self.eat_keyword("/include/") {
+                self.skip_whitespace()?;
+                let name = self.string()?;
+                let name = String::from_utf8(name).map_err(|_| self.error("include path is not UTF-8"))?;
+                let path = self.file.parent().unwrap_or_else(|| Path::new(".")).join(name);
+                fragments.extend(parse_file(&path, false, files)?);
+            } else if self.eat_keyword("/delete-node/") {
+                self.skip_whitespace()?;
+                self.expect(b'&')?;
+                let target = self.reference()?;
+                self.expect(b';')?;
+                fragments.push(Fragment::DeleteNode(target, location));
+            } else if self.peek() == Some(b'#') {
+                return Err(self.error("C preprocessor directives are not supported"));
+            } else {
+                let labels = self.labels()?;
+                self.skip_whitespace()?;
+                let location = self.location();
+                let target = if self.eat(b'/') {
+                    None
+                } else if self.eat(b'&') {
+                    Some(self.reference()?)
+                } else {
+                    return Err(self.error("expected a node definition"));
+                };
+                let body = self.node_body()?;
+                fragments.push(Fragment::Node { target, labels, body, location });
+            }
+        }
+        Ok(fragments)
+    }
+
+    // '{' 之后的内容，直到 '}' ';'
+    fn node_body(&mut self) -> Result<Vec<Item>, Error> {
+        self.expect(b'{')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace()?;
+            if self.eat(b'}') {
+                self.expect(b';')?;
+                return Ok(items);
+            }
+            if self.eat_keyword("/delete-property/") {
+                let name = self.name()?;
+                self.expect(b';')?;
+                items.push(Item::DeleteProperty(name));
+                continue;
+            }
+            if self.eat_keyword("/delete-node/") {
+                let name = self.name()?;
+                self.expect(b';')?;
+                items.push(Item::DeleteNode(name));
+                continue;
+            }
+            self.eat_keyword("/omit-if-no-ref/");
+            let labels = self.labels()?;
+            let name = self.name()?;
+            self.skip_whitespace()?;
+            match self.peek() {
+                Some(b'{') => {
+                    let body = self.node_body()?;
+                    items.push(Item::Child { name, labels, body });
+                }
+                Some(b'=') => {
+                    self.pos += 1;
+                    let value = self.property_value()?;
+                    self.expect(b';')?;
+                    items.push(Item::Property(Property { name, value }));
+                }
+                Some(b';') => {
+                    self.pos += 1;
+                    items.push(Item::Property(Property { name, value: Vec::new() }));
+                }
+                _ => return Err(self.error("expected '=', ';' or '{'")),
+            }
+        }
+    }
+
+    fn property_value(&mut self) -> Result<Vec<Chunk>, Error> {
+        let mut value = Vec::new();
+        loop {
+            self.labels()?;
+            self.skip_whitespace()?;
+            let location = self.location();
+            match self.peek() {
+                Some(b'"') => {
+                    let mut bytes = self.string()?;
+                    bytes.push(0);
+                    value.push(Chunk::Bytes(bytes));
+                }
+                Some(b'<') => self.cells(32, &mut value)?,
+                Some(b'[') => value.push(Chunk::Bytes(self.byte_string()?)),
+                Some(b'&') => {
+                    self.pos += 1;
+                    value.push(Chunk::Path(self.reference()?, location));
+                }
+                _ if self.eat_keyword("/bits/") => {
+                    let bits = self.literal()?;
+                    if ![8, 16, 32, 64].contains(&bits) {
+                        return Err(location.error(format!("/bits/ {} is not 8, 16, 32 or 64", bits)));
+                    }
+                    self.skip_whitespace()?;
+                    self.cells(bits as u32, &mut value)?;
+                }
+                _ => return Err(self.error("expected a property value")),
+            }
+            self.labels()?;
+            self.skip_whitespace()?;
+            if !self.eat(b',') {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn cells(&mut self, bits: u32, value: &mut Vec<Chunk>) -> Result<(), Error> {
+        self.expect(b'<')?;
+        let mut bytes = Vec::new();
+        loop {
+            self.labels()?;
+            self.skip_whitespace()?;
+            let location = self.location();
+            if self.eat(b'>') {
+                break;
+            }
+            if self.eat(b'&') {
+                if bits != 32 {
+                    return Err(location.error("references are only allowed in 32-bit cells".into()));
+                }
+                value.push(Chunk::Bytes(std::mem::take(&mut bytes)));
+                value.push(Chunk::Phandle(self.reference()?, location));
+                continue;
+            }
+            let cell = self.primary()?;
+            if bits < 64 {
+                let mask = (1u64 << bits) - 1;
+                // 和 dtc 一样，负数按补码截断
+                if cell > mask && cell | mask != u64::MAX {
+                    return Err(location.error(format!("{:#x} does not fit in a {}-bit cell", cell, bits)));
+                }
+            }
+            bytes.extend_from_slice(&cell.to_be_bytes()[(64 - bits as usize) / 8..]);
+        }
+        value.push(Chunk::Bytes(bytes));
+        Ok(())
+    }
+
+    fn byte_string(&mut self) -> Result<Vec<u8>, Error> {
+        self.expect(b'[')?;
+        let mut bytes = Vec::new();
+        loop {
+            self.skip_whitespace()?;
+            if self.eat(b']') {
+                return Ok(bytes);
+            }
+            let high = self.hex_digit()?;
+            let low = self.hex_digit()?;
+            bytes.push(high << 4 | low);
+        }
+    }
+
+    fn hex_digit(&mut self) -> Result<u8, Error> {
+        let digit = self.peek().and_then(|c| (c as char).to_digit(16)).ok_or_else(|| self.error("expected a hex digit"))?;
+        self.pos += 1;
+        Ok(digit as u8)
+    }
+
+    // 整数字面量、字符字面量或者括号中的表达式
+    fn primary(&mut self) -> Result<u64, Error> {
+        self.skip_whitespace()?;
+        match self.peek() {
+            Some(b'(') => {
+                self.pos += 1;
+                let value = self.expression(0)?;
+                self.expect(b')')?;
+                Ok(value)
+            }
+            Some(b'\'') => {
+                self.pos += 1;
+                let c = self.char_in_quotes()?;
+                if !self.eat(b'\'') {
+                    return Err(self.error("unterminated character literal"));
+                }
+                Ok(c as u64)
+            }
+            _ => self.literal(),
+        }
+    }
+
+    // 按优先级解析二元运算，min 是允许的最低优先级
+    fn expression(&mut self, min: u8) -> Result<u64, Error> {
+        let mut left = self.unary()?;
+        loop {
+            self.skip_whitespace()?;
+            let location = self.location();
+            if min == 0 && self.eat(b'?') {
+                let then = self.expression(0)?;
+                self.expect(b':')?;
+                let otherwise = self.expression(0)?;
+                left = if left != 0 { then } else { otherwise };
+                continue;
+            }
+            let (op, precedence) = match self.binary_operator() {
+                Some(op) if op.1 >= min.max(1) => op,
+                _ => return Ok(left),
+            };
+            self.pos += op.len();
+            let right = self.expression(precedence + 1)?;
+            left = match op {
+                "||" => (left != 0 || right != 0) as u64,
+                "&&" => (left != 0 && right != 0) as u64,
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "==" => (left == right) as u64,
+                "!=" => (left != right) as u64,
+                "<" => (left < right) as u64,
+                ">" => (left > right) as u64,
+                "<=" => (left <= right) as u64,
+                ">=" => (left >= right) as u64,
+                "<<" => left.checked_shl(right as u32).unwrap_or(0),
+                ">>" => left.checked_shr(right as u32).unwrap_or(0),
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                "/" => left.checked_div(right).ok_or_else(|| location.error("division by zero".into()))?,
+                "%" => left.checked_rem(right).ok_or_else(|| location.error("division by zero".into()))?,
+                _ => unreachable!(),
+            };
+        }
+    }
+
+    fn binary_operator(&self) -> Option<(&'static str, u8)> {
+        const OPERATORS: [(&str, u8); 18] = [
+            ("||", 1), ("&&", 2), ("==", 6), ("!=", 6), ("<=", 7), (">=", 7), ("<<", 8), (">>", 8),
+            ("|", 3), ("^", 4), ("&", 5), ("<", 7), (">", 7), ("+", 9), ("-", 9), ("*", 10), ("/", 10), ("%", 10),
+        ];
+        let rest = &self.src[self.pos..];
+        OPERATORS.iter().copied().find(|(op, _)| rest.starts_with(op.as_bytes()))
+    }
+
+    fn unary(&mut self) -> Result<u64, Error> {
+        self.skip_whitespace()?;
+        if self.eat(b'-') {
+            Ok(self.unary()?.wrapping_neg())
+        } else if self.eat(b'~') {
+            Ok(!self.unary()?)
+        } else if self.eat(b'!') {
+            Ok((self.unary()? == 0) as u64)
+        } else {
+            self.primary()
+        }
+    }
+
+    fn literal(&mut self) -> Result<u64, Error> {
+        self.skip_whitespace()?;
+        let start = self.pos;
+        while self.peek_is(|c| c.is_ascii_alphanumeric()) {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.src[start..self.pos]).unwrap();
+        let digits = text.trim_end_matches(&['u', 'U', 'l', 'L'][..]);
+        let parsed = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+            u64::from_str_radix(hex, 16)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            u64::from_str_radix(&digits[1..], 8)
+        } else {
+            digits.parse()
+        };
+        parsed.map_err(|_| {
+            self.pos = start;
+            if text.is_empty() {
+                self.error("expected an integer")
+            } else {
+                self.error(&format!("expected an integer, found {:?}", text))
+            }
+        })
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>, Error> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(b'\n') => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(bytes);
+                }
+                _ => bytes.push(self.char_in_quotes()?),
+            }
+        }
+    }
+
+    // 字符串或字符字面量中的一个字符，处理转义
+    fn char_in_quotes(&mut self) -> Result<u8, Error> {
+        let c = self.peek().ok_or_else(|| self.error("unexpected end of file"))?;
+        self.pos += 1;
+        if c != b'\\' {
+            return Ok(c);
+        }
+        let escape = self.peek().ok_or_else(|| self.error("unexpected end of file"))?;
+        self.pos += 1;
+        Ok(match escape {
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b't' => b'\t',
+            b'n' => b'\n',
+            b'v' => 0x0b,
+            b'f' => 0x0c,
+            b'r' => b'\r',
+            b'x' => {
+                let start = self.pos;
+                while self.pos < start + 2 && self.peek_is(|c| c.is_ascii_hexdigit()) {
+                    self.pos += 1;
+                }
+                let text = std::str::from_utf8(&self.src[start..self.pos]).unwrap();
+                u8::from_str_radix(text, 16).map_err(|_| self.error("bad \\x escape"))?
+            }
+            b'0'..=b'7' => {
+                let start = self.pos - 1;
+                while self.pos < start + 3 && self.peek_is(|c| (b'0'..=b'7').contains(&c)) {
+                    self.pos += 1;
+                }
+                let text = std::str::from_utf8(&self.src[start..self.pos]).unwrap();
+                u8::from_str_radix(text, 8).map_err(|_| self.error("octal escape out of range"))?
+            }
+            other => other,
+        })
+    }
+
+    // '&' 之后的标签或 {路径}
+    fn reference(&mut self) -> Result<Reference, Error> {
+        if self.eat(b'{') {
+            let start = self.pos;
+            while self.peek_is(|c| c != b'}') {
+                self.pos += 1;
+            }
+            let path = String::from_utf8_lossy(&self.src[start..self.pos]).into_owned();
+            self.expect(b'}')?;
+            return Ok(Reference::Path(path));
+        }
+        let start = self.pos;
+        while self.peek_is(is_label_char) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(self.error("expected a label or {path} after '&'"));
+        }
+        Ok(Reference::Label(String::from_utf8_lossy(&self.src[start..self.pos]).into_owned()))
+    }
+
+    // 零个或多个 "label:"
+    fn labels(&mut self) -> Result<Vec<String>, Error> {
+        let mut labels = Vec::new();
+        loop {
+            self.skip_whitespace()?;
+            let start = self.pos;
+            if self.peek_is(|c| c.is_ascii_alphabetic() || c == b'_') {
+                while self.peek_is(is_label_char) {
+                    self.pos += 1;
+                }
+                if self.eat(b':') {
+                    labels.push(String::from_utf8_lossy(&self.src[start..self.pos - 1]).into_owned());
+                    continue;
+                }
+            }
+            self.pos = start;
+            return Ok(labels);
+        }
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        self.skip_whitespace()?;
+        let start = self.pos;
+        while self.peek_is(is_name_char) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(self.error("expected a property or node name"));
+        }
+        Ok(String::from_utf8_lossy(&self.src[start..self.pos]).into_owned())
+    }
+
+    fn skip_whitespace(&mut self) -> Result<(), Error> {
+        loop {
+            let rest = &self.src[self.pos..];
+            if self.peek_is(|c| c.is_ascii_whitespace()) {
+                self.pos += 1;
+            } else if rest.starts_with(b"//") {
+                self.pos += rest.iter().position(|&c| c == b'\n').unwrap_or(rest.len());
+            } else if rest.starts_with(b"/*") {
+                let end = rest.windows(2).position(|w| w == b"*/").ok_or_else(|| self.error("unterminated comment"))?;
+                self.pos += end + 2;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.src[self.pos..].starts_with(keyword.as_bytes()) {
+            self.pos += keyword.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), Error> {
+        self.skip_whitespace()?;
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c as char)))
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    // 下一个字符存在并且满足 f
+    fn peek_is(&self, f: impl Fn(u8) -> bool) -> bool {
+        matches!(self.peek(), Some(c) if f(c))
+    }
+
+    fn location(&self) -> Location {
+        let before = &self.src[..self.pos];
+        let line = before.iter().filter(|&&c| c == b'\n').count() + 1;
+        let column = self.pos - before.iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1) + 1;
+        Location { file: self.file.to_path_buf(), line, column }
+    }
+
+    fn error(&self, message: &str) -> Error {
+        self.location().error(message.to_string())
+    }
+}
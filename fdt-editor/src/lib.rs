@@ -0,0 +1,586 @@
+//! 扁平设备树（FDT）的简单编辑层
+//!
+//! 只支持固件需要的操作：按路径查找节点、读写属性、添加子节点。
+//! 设备树先被复制到一块可写的缓冲区里，结构块之后的内容在插入时整体后移，
+//! 字符串块始终位于设备树的末尾。
+#![no_std]
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+pub const FDT_HEADER_SIZE: usize = 40;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const FDT_MIN_VERSION: u32 = 17;
+
+// 头部各字段的偏移
+const HDR_MAGIC: usize = 0;
+const HDR_TOTALSIZE: usize = 4;
+const HDR_OFF_DT_STRUCT: usize = 8;
+const HDR_OFF_DT_STRINGS: usize = 12;
+const HDR_OFF_MEM_RSVMAP: usize = 16;
+const HDR_VERSION: usize = 20;
+const HDR_LAST_COMP_VERSION: usize = 24;
+const HDR_SIZE_DT_STRINGS: usize = 32;
+const HDR_SIZE_DT_STRUCT: usize = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdtError {
+    BadMagic,
+    BadVersion,
+    BadLayout,
+    Truncated,
+    NoSpace,
+    NotFound,
+}
+
+/// 只检查设备树头部的魔数，返回头部记录的 totalsize
+pub fn peek_total_size(header: &[u8]) -> Result<usize, FdtError> {
+    if header.len() < FDT_HEADER_SIZE {
+        return Err(FdtError::Truncated);
+    }
+    if be32(header, HDR_MAGIC) != FDT_MAGIC {
+        return Err(FdtError::BadMagic);
+    }
+    Ok(be32(header, HDR_TOTALSIZE) as usize)
+}
+
+/// 检查一段内存是否是合法的设备树，返回其 totalsize
+pub fn check_header(blob: &[u8]) -> Result<usize, FdtError> {
+    peek_total_size(blob)?;
+    if be32(blob, HDR_VERSION) < FDT_MIN_VERSION || be32(blob, HDR_LAST_COMP_VERSION) > FDT_LAST_COMP_VERSION {
+        return Err(FdtError::BadVersion);
+    }
+    let total_size = be32(blob, HDR_TOTALSIZE) as usize;
+    if total_size < FDT_HEADER_SIZE {
+        return Err(FdtError::BadLayout);
+    }
+    if total_size > blob.len() {
+        return Err(FdtError::Truncated);
+    }
+    let off_rsvmap = be32(blob, HDR_OFF_MEM_RSVMAP) as usize;
+    let off_struct = be32(blob, HDR_OFF_DT_STRUCT) as usize;
+    let size_struct = be32(blob, HDR_SIZE_DT_STRUCT) as usize;
+    let off_strings = be32(blob, HDR_OFF_DT_STRINGS) as usize;
+    let size_strings = be32(blob, HDR_SIZE_DT_STRINGS) as usize;
+    // 只接受 dtc 生成的布局：头部、保留内存表、结构块、字符串块
+    if off_rsvmap < FDT_HEADER_SIZE
+        || off_struct < off_rsvmap
+        || off_struct & 3 != 0
+        || off_struct + size_struct > off_strings
+        || off_strings + size_strings > total_size {
+        return Err(FdtError::BadLayout);
+    }
+    Ok(total_size)
+}
+
+pub struct Fdt<'a> {
+    buf: &'a mut [u8],
+}
+
+impl<'a> Fdt<'a> {
+    /// 把设备树复制到可写的缓冲区 buf 中，缓冲区剩余的空间用于之后的修改
+    pub fn open_into(blob: &[u8], buf: &'a mut [u8]) -> Result<Self, FdtError> {
+        let total_size = check_header(blob)?;
+        if total_size > buf.len() {
+            return Err(FdtError::NoSpace);
+        }
+        buf[..total_size].copy_from_slice(&blob[..total_size]);
+        let mut fdt = Fdt { buf };
+        // 去掉字符串块之后的空闲空间，保证字符串块位于末尾
+        let end = fdt.header(HDR_OFF_DT_STRINGS) + fdt.header(HDR_SIZE_DT_STRINGS);
+        fdt.set_header(HDR_TOTALSIZE, end);
+        Ok(fdt)
+    }
+
+    pub fn total_size(&self) -> usize {
+        self.header(HDR_TOTALSIZE)
+    }
+
+    /// 根节点的偏移
+    pub fn root(&self) -> Result<usize, FdtError> {
+        let mut offset = self.header(HDR_OFF_DT_STRUCT);
+        loop {
+            match self.read_u32(offset)? {
+                FDT_NOP => offset += 4,
+                FDT_BEGIN_NODE => return Ok(offset),
+                _ => return Err(FdtError::BadLayout),
+            }
+        }
+    }
+
+    /// 按绝对路径查找节点，路径的某一级没有写出单元地址时，匹配第一个同名节点
+    pub fn find_node(&self, path: &str) -> Result<usize, FdtError> {
+        let mut node = self.root()?;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            node = self.subnode(node, name)?;
+        }
+        Ok(node)
+    }
+
+    /// 查找 parent 的直接子节点
+    pub fn subnode(&self, parent: usize, name: &str) -> Result<usize, FdtError> {
+        let mut index = 0;
+        while let Some(child) = self.child(parent, index)? {
+            if name_matches(self.node_name(child)?, name) {
+                return Ok(child);
+            }
+            index += 1;
+        }
+        Err(FdtError::NotFound)
+    }
+
+    /// parent 的第 index 个直接子节点
+    pub fn child(&self, parent: usize, index: usize) -> Result<Option<usize>, FdtError> {
+        let mut offset = self.next_token(parent)?.1;
+        let mut depth = 0;
+        let mut count = 0;
+        loop {
+            let (token, next) = self.next_token(offset)?;
+            match token {
+                FDT_BEGIN_NODE => {
+                    if depth == 0 {
+                        if count == index {
+                            return Ok(Some(offset));
+                        }
+                        count += 1;
+                    }
+                    depth += 1;
+                }
+                FDT_END_NODE => {
+                    if depth == 0 {
+                        return Ok(None);
+                    }
+                    depth -= 1;
+                }
+                FDT_END => return Err(FdtError::BadLayout),
+                _ => {}
+            }
+            offset = next;
+        }
+    }
+
+    pub fn node_name(&self, node: usize) -> Result<&[u8], FdtError> {
+        self.c_str(node + 4)
+    }
+
+    pub fn property(&self, node: usize, name: &str) -> Option<&[u8]> {
+        let prop = self.find_property(node, name).ok()??;
+        let len = self.read_u32(prop + 4).ok()? as usize;
+        self.buf.get(prop + 12..prop + 12 + len)
+    }
+
+    pub fn property_u32(&self, node: usize, name: &str) -> Option<u32> {
+        self.property(node, name).filter(|value| value.len() >= 4).map(|value| be32(value, 0))
+    }
+
+    pub fn set_property(&mut self, node: usize, name: &str, value: &[u8]) -> Result<(), FdtError> {
+        self.reserve_property(node, name, value.len())?.copy_from_slice(value);
+        Ok(())
+    }
+
+    pub fn set_property_str(&mut self, node: usize, name: &str, value: &str) -> Result<(), FdtError> {
+        let slot = self.reserve_property(node, name, value.len() + 1)?;
+        slot[..value.len()].copy_from_slice(value.as_bytes());
+        slot[value.len()] = 0;
+        Ok(())
+    }
+
+    pub fn set_property_cells(&mut self, node: usize, name: &str, cells: &[u32]) -> Result<(), FdtError> {
+        let slot = self.reserve_property(node, name, cells.len() * 4)?;
+        for (i, cell) in cells.iter().enumerate() {
+            slot[i * 4..i * 4 + 4].copy_from_slice(&cell.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    /// 在 parent 的末尾添加一个空的子节点，返回新节点的偏移
+    pub fn add_subnode(&mut self, parent: usize, name: &str) -> Result<usize, FdtError> {
+        let offset = self.end_of_node(parent)?;
+        let name_len = align4(name.len() + 1);
+        self.splice(offset, 0, 4 + name_len + 4)?;
+        self.write_u32(offset, FDT_BEGIN_NODE);
+        let name_slot = &mut self.buf[offset + 4..offset + 4 + name_len];
+        name_slot.fill(0);
+        name_slot[..name.len()].copy_from_slice(name.as_bytes());
+        self.write_u32(offset + 4 + name_len, FDT_END_NODE);
+        Ok(offset)
+    }
+
+    // 创建或者修改属性，返回长度为 len 的值所在的缓冲区
+    fn reserve_property(&mut self, node: usize, name: &str, len: usize) -> Result<&mut [u8], FdtError> {
+        let prop = match self.find_property(node, name)? {
+            Some(prop) => {
+                let old_len = self.read_u32(prop + 4)? as usize;
+                self.splice(prop + 12, align4(old_len), align4(len))?;
+                prop
+            }
+            None => {
+                let name_offset = self.find_or_add_string(name)?;
+                // 属性必须位于子节点之前，直接插在节点名之后
+                let prop = self.next_token(node)?.1;
+                self.splice(prop, 0, 12 + align4(len))?;
+                self.write_u32(prop, FDT_PROP);
+                self.write_u32(prop + 8, name_offset as u32);
+                prop
+            }
+        };
+        self.write_u32(prop + 4, len as u32);
+        let slot = &mut self.buf[prop + 12..prop + 12 + align4(len)];
+        slot.fill(0);
+        Ok(&mut slot[..len])
+    }
+
+    fn find_property(&self, node: usize, name: &str) -> Result<Option<usize>, FdtError> {
+        let mut offset = self.next_token(node)?.1;
+        loop {
+            let (token, next) = self.next_token(offset)?;
+            match token {
+                FDT_PROP => {
+                    let name_offset = self.read_u32(offset + 8)? as usize;
+                    if self.c_str(self.header(HDR_OFF_DT_STRINGS) + name_offset)? == name.as_bytes() {
+                        return Ok(Some(offset));
+                    }
+                }
+                FDT_NOP => {}
+                _ => return Ok(None),
+            }
+            offset = next;
+        }
+    }
+
+    // 节点对应的 FDT_END_NODE 的偏移
+    fn end_of_node(&self, node: usize) -> Result<usize, FdtError> {
+        let mut offset = self.next_token(node)?.1;
+        let mut depth = 0;
+        loop {
+            let (token, next) = self.next_token(offset)?;
+            match token {
+                FDT_BEGIN_NODE => depth += 1,
+                FDT_END_NODE if depth == 0 => return Ok(offset),
+                FDT_END_NODE => depth -= 1,
+                FDT_END => return Err(FdtError::BadLayout),
+                _ => {}
+            }
+            offset = next;
+        }
+    }
+
+    fn find_or_add_string(&mut self, name: &str) -> Result<usize, FdtError> {
+        let strings = self.header(HDR_OFF_DT_STRINGS);
+        let size = self.header(HDR_SIZE_DT_STRINGS);
+        let table = &self.buf[strings..strings + size];
+        let mut start = 0;
+        for (i, &byte) in table.iter().enumerate() {
+            if byte == 0 {
+                if &table[start..i] == name.as_bytes() {
+                    return Ok(start);
+                }
+                start = i + 1;
+            }
+        }
+        // 字符串块在末尾，直接追加
+        let total_size = self.total_size();
+        if total_size + name.len() + 1 > self.buf.len() {
+            return Err(FdtError::NoSpace);
+        }
+        self.buf[total_size..total_size + name.len()].copy_from_slice(name.as_bytes());
+        self.buf[total_size + name.len()] = 0;
+        self.set_header(HDR_SIZE_DT_STRINGS, size + name.len() + 1);
+        self.set_header(HDR_TOTALSIZE, total_size + name.len() + 1);
+        Ok(size)
+    }
+
+    // 在结构块的 offset 处删去 remove 个字节、腾出 insert 个字节
+    fn splice(&mut self, offset: usize, remove: usize, insert: usize) -> Result<(), FdtError> {
+        let total_size = self.total_size();
+        let new_total_size = total_size - remove + insert;
+        if new_total_size > self.buf.len() {
+            return Err(FdtError::NoSpace);
+        }
+        self.buf.copy_within(offset + remove..total_size, offset + insert);
+        let size_struct = self.header(HDR_SIZE_DT_STRUCT);
+        let off_strings = self.header(HDR_OFF_DT_STRINGS);
+        self.set_header(HDR_SIZE_DT_STRUCT, size_struct - remove + insert);
+        self.set_header(HDR_OFF_DT_STRINGS, off_strings - remove + insert);
+        self.set_header(HDR_TOTALSIZE, new_total_size);
+        Ok(())
+    }
+
+    // 返回 offset 处的标记以及下一个标记的偏移
+    fn next_token(&self, offset: usize) -> Result<(u32, usize), FdtError> {
+        let token = self.read_u32(offset)?;
+        let next = match token {
+            FDT_BEGIN_NODE => offset + 4 + align4(self.c_str(offset + 4)?.len() + 1),
+            FDT_PROP => offset + 12 + align4(self.read_u32(offset + 4)? as usize),
+            FDT_END_NODE | FDT_NOP | FDT_END => offset + 4,
+            _ => return Err(FdtError::BadLayout),
+        };
+        Ok((token, next))
+    }
+
+    fn c_str(&self, offset: usize) -> Result<&[u8], FdtError> {
+        let bytes = self.buf.get(offset..self.total_size()).ok_or(FdtError::Truncated)?;
+        let len = bytes.iter().position(|&b| b == 0).ok_or(FdtError::Truncated)?;
+        Ok(&bytes[..len])
+    }
+
+    fn read_u32(&self, offset: usize) -> Result<u32, FdtError> {
+        if offset + 4 > self.total_size() {
+            return Err(FdtError::Truncated);
+        }
+        Ok(be32(self.buf, offset))
+    }
+
+    fn write_u32(&mut self, offset: usize, value: u32) {
+        self.buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    fn header(&self, field: usize) -> usize {
+        be32(self.buf, field) as usize
+    }
+
+    fn set_header(&mut self, field: usize, value: usize) {
+        self.write_u32(field, value as u32)
+    }
+}
+
+#[inline]
+fn be32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+#[inline]
+fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+// "memory" 可以匹配 "memory@40000000"
+fn name_matches(node_name: &[u8], name: &str) -> bool {
+    let name = name.as_bytes();
+    node_name == name || (!name.contains(&b'@')
+        && node_name.len() > name.len()
+        && node_name.starts_with(name)
+        && node_name[name.len()] == b'@')
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::{check_header, Fdt, FdtError};
+    use std::{vec, vec::Vec};
+
+    // 按 dtc 的布局生成设备树：头部、空的保留内存表、结构块、字符串块
+    #[derive(Default)]
+    struct Builder {
+        structure: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl Builder {
+        fn begin_node(mut self, name: &str) -> Self {
+            self.token(super::FDT_BEGIN_NODE);
+            self.structure.extend_from_slice(name.as_bytes());
+            self.structure.push(0);
+            self.pad();
+            self
+        }
+
+        fn prop(mut self, name: &str, value: &[u8]) -> Self {
+            let name_offset = self.strings.len() as u32;
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+            self.token(super::FDT_PROP);
+            self.token(value.len() as u32);
+            self.token(name_offset);
+            self.structure.extend_from_slice(value);
+            self.pad();
+            self
+        }
+
+        fn end_node(mut self) -> Self {
+            self.token(super::FDT_END_NODE);
+            self
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            self.token(super::FDT_END);
+            let off_rsvmap = super::FDT_HEADER_SIZE;
+            let off_struct = off_rsvmap + 16;
+            let off_strings = off_struct + self.structure.len();
+            let total_size = off_strings + self.strings.len();
+            let header = [
+                super::FDT_MAGIC, total_size as u32, off_struct as u32, off_strings as u32, off_rsvmap as u32,
+                17, 16, 0, self.strings.len() as u32, self.structure.len() as u32,
+            ];
+            let mut blob: Vec<u8> = header.iter().flat_map(|word| word.to_be_bytes()).collect();
+            blob.extend_from_slice(&[0; 16]);
+            blob.extend_from_slice(&self.structure);
+            blob.extend_from_slice(&self.strings);
+            blob
+        }
+
+        fn token(&mut self, word: u32) {
+            self.structure.extend_from_slice(&word.to_be_bytes());
+        }
+
+        fn pad(&mut self) {
+            while self.structure.len() & 3 != 0 {
+                self.structure.push(0);
+            }
+        }
+    }
+
+    fn sample() -> Vec<u8> {
+        Builder::default()
+            .begin_node("")
+            .prop("#address-cells", &[0, 0, 0, 2])
+            .begin_node("memory@40000000")
+            .prop("device_type", b"memory\0")
+            .prop("reg", &[0, 0, 0, 0, 0x40, 0, 0, 0, 0, 0, 0, 0, 0x20, 0, 0, 0])
+            .end_node()
+            .begin_node("cpus")
+            .begin_node("cpu@0")
+            .prop("device_type", b"cpu\0")
+            .end_node()
+            .end_node()
+            .end_node()
+            .finish()
+    }
+
+    // 修改之后的设备树仍然合法，返回它的副本
+    fn reopen(buf: &[u8], total_size: usize) -> Vec<u8> {
+        assert_eq!(check_header(&buf[..total_size]), Ok(total_size));
+        buf[..total_size].to_vec()
+    }
+
+    fn strings_size(blob: &[u8]) -> usize {
+        super::be32(blob, super::HDR_SIZE_DT_STRINGS) as usize
+    }
+
+    #[test]
+    fn find_nodes_and_properties() {
+        let blob = sample();
+        let mut buf = vec![0; 4096];
+        let fdt = Fdt::open_into(&blob, &mut buf).unwrap();
+        let memory = fdt.find_node("/memory").unwrap();
+        assert_eq!(fdt.node_name(memory).unwrap(), b"memory@40000000");
+        assert_eq!(fdt.property(memory, "device_type"), Some(&b"memory\0"[..]));
+        assert_eq!(fdt.property_u32(fdt.root().unwrap(), "#address-cells"), Some(2));
+        assert!(fdt.find_node("/cpus/cpu@0").is_ok());
+        assert_eq!(fdt.find_node("/cpus/cpu@1"), Err(FdtError::NotFound));
+        assert_eq!(fdt.find_node("/mem"), Err(FdtError::NotFound));
+    }
+
+    #[test]
+    fn set_property_grows_and_shrinks() {
+        let blob = sample();
+        let mut buf = vec![0; 4096];
+        let mut fdt = Fdt::open_into(&blob, &mut buf).unwrap();
+        let memory = fdt.find_node("/memory").unwrap();
+        fdt.set_property_str(memory, "device_type", "a much longer memory type").unwrap();
+        let memory = fdt.find_node("/memory").unwrap();
+        fdt.set_property_cells(memory, "reg", &[0, 0x4000_0000, 0, 0x1000_0000, 0, 0x8000_0000, 0, 0x1000_0000]).unwrap();
+        assert_eq!(fdt.property(memory, "device_type"), Some(&b"a much longer memory type\0"[..]));
+        assert_eq!(fdt.property(memory, "reg").unwrap().len(), 32);
+        // 后面的节点随着一起移动
+        let cpu = fdt.find_node("/cpus/cpu@0").unwrap();
+        assert_eq!(fdt.property(cpu, "device_type"), Some(&b"cpu\0"[..]));
+        fdt.set_property(memory, "reg", &[1, 2, 3]).unwrap();
+        assert_eq!(fdt.property(memory, "reg"), Some(&[1, 2, 3][..]));
+        let total_size = fdt.total_size();
+        let edited = reopen(&buf, total_size);
+        let fdt = Fdt::open_into(&edited, &mut buf).unwrap();
+        assert!(fdt.find_node("/cpus/cpu@0").is_ok());
+        // reg 从 16 字节变成 4 字节，device_type 从 8 字节变成 28 字节
+        assert_eq!(total_size, blob.len() - 12 + 20);
+    }
+
+    #[test]
+    fn add_subnode_appends_child() {
+        let blob = sample();
+        let mut buf = vec![0; 4096];
+        let mut fdt = Fdt::open_into(&blob, &mut buf).unwrap();
+        let root = fdt.root().unwrap();
+        let chosen = fdt.add_subnode(root, "chosen").unwrap();
+        fdt.set_property_str(chosen, "bootargs", "console=ttyS0").unwrap();
+        let cpus = fdt.find_node("/cpus").unwrap();
+        let cpu = fdt.add_subnode(cpus, "cpu@1").unwrap();
+        fdt.set_property_str(cpu, "device_type", "cpu").unwrap();
+        let root = fdt.root().unwrap();
+        let names: Vec<_> = (0..).map_while(|i| fdt.child(root, i).unwrap()).map(|node| fdt.node_name(node).unwrap().to_vec()).collect();
+        assert_eq!(names, [&b"memory@40000000"[..], b"cpus", b"chosen"]);
+        let chosen = fdt.find_node("/chosen").unwrap();
+        assert_eq!(fdt.property(chosen, "bootargs"), Some(&b"console=ttyS0\0"[..]));
+        let cpu = fdt.find_node("/cpus/cpu@1").unwrap();
+        assert_eq!(fdt.property(cpu, "device_type"), Some(&b"cpu\0"[..]));
+        let total_size = fdt.total_size();
+        reopen(&buf, total_size);
+    }
+
+    #[test]
+    fn property_names_reuse_string_table() {
+        let blob = sample();
+        let mut buf = vec![0; 4096];
+        let mut fdt = Fdt::open_into(&blob, &mut buf).unwrap();
+        let root = fdt.root().unwrap();
+        let node = fdt.add_subnode(root, "reserved-memory").unwrap();
+        // 已有的名字不会再次加入字符串块
+        fdt.set_property_cells(node, "#address-cells", &[2]).unwrap();
+        fdt.set_property(node, "reg", &[]).unwrap();
+        let total_size = fdt.total_size();
+        let edited = reopen(&buf, total_size);
+        assert_eq!(strings_size(&edited), strings_size(&blob));
+        let mut fdt = Fdt::open_into(&edited, &mut buf).unwrap();
+        let node = fdt.find_node("/reserved-memory").unwrap();
+        fdt.set_property(node, "ranges", &[]).unwrap();
+        fdt.set_property(node, "ranges", &[]).unwrap();
+        let cpu = fdt.find_node("/cpus/cpu@0").unwrap();
+        fdt.set_property(cpu, "ranges", &[]).unwrap();
+        let total_size = fdt.total_size();
+        let edited = reopen(&buf, total_size);
+        assert_eq!(strings_size(&edited), strings_size(&blob) + "ranges".len() + 1);
+        // 名字是另一个名字的后缀时也不能误用
+        let mut fdt = Fdt::open_into(&edited, &mut buf).unwrap();
+        let cpu = fdt.find_node("/cpus/cpu@0").unwrap();
+        fdt.set_property(cpu, "type", &[]).unwrap();
+        assert!(fdt.property(cpu, "type").is_some());
+        assert_eq!(strings_size(&buf), strings_size(&edited) + "type".len() + 1);
+    }
+
+    #[test]
+    fn out_of_space_keeps_tree_valid() {
+        let blob = sample();
+        let mut buf = vec![0; blob.len() + 8];
+        let mut fdt = Fdt::open_into(&blob, &mut buf).unwrap();
+        let memory = fdt.find_node("/memory").unwrap();
+        assert_eq!(fdt.set_property(memory, "reg", &[0; 64]), Err(FdtError::NoSpace));
+        let root = fdt.root().unwrap();
+        assert_eq!(fdt.add_subnode(root, "reserved-memory"), Err(FdtError::NoSpace));
+        let total_size = fdt.total_size();
+        assert_eq!(reopen(&buf, total_size), blob);
+        let mut small = vec![0; blob.len() - 1];
+        assert_eq!(Fdt::open_into(&blob, &mut small).err(), Some(FdtError::NoSpace));
+    }
+
+    #[test]
+    fn check_header_rejects_bad_trees() {
+        let blob = sample();
+        assert_eq!(check_header(&blob), Ok(blob.len()));
+        assert_eq!(check_header(&blob[..blob.len() - 1]), Err(FdtError::Truncated));
+        assert_eq!(check_header(&blob[..20]), Err(FdtError::Truncated));
+        let mut bad = blob.clone();
+        bad[0] = 0;
+        assert_eq!(check_header(&bad), Err(FdtError::BadMagic));
+        let mut bad = blob.clone();
+        bad[super::HDR_VERSION + 3] = 16;
+        assert_eq!(check_header(&bad), Err(FdtError::BadVersion));
+        let mut bad = blob;
+        bad[super::HDR_OFF_DT_STRINGS + 3] -= 4;
+        assert_eq!(check_header(&bad), Err(FdtError::BadLayout));
+    }
+}
@@ -0,0 +1,137 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+
+    // 按共享的启动配置生成链接脚本
+    let linker_script = include_str!("linker-nezha.ld.in")
+        .replace("${FIRMWARE_BASE}", &format!("{:#x}", boot_config::FIRMWARE_BASE))
+        .replace("${IMAGE_HEADER_OFFSET}", &format!("{:#x}", boot_config::IMAGE_HEADER_OFFSET))
+        .replace("${IMAGE_HEADER_ADDRESS}", &format!("{:#x}", boot_config::FIRMWARE_BASE + boot_config::IMAGE_HEADER_OFFSET));
+    fs::File::create(out_dir.join("linker-nezha.ld"))
+        .unwrap()
+        .write_all(linker_script.as_bytes())
+        .unwrap();
+    println!("cargo:rustc-link-search={}", out_dir.display());
+
+    compile_device_tree(&out_dir);
+    generate_config(&out_dir);
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=linker-nezha.ld.in");
+}
+
+// 编译内置的设备树：dts/$NEZHA_BOARD.dts（默认为 sunxi），再依次合并
+// $NEZHA_DTS_OVERLAYS 中逗号分隔的源码覆盖层
+fn compile_device_tree(out_dir: &Path) {
+    println!("cargo:rerun-if-env-changed=NEZHA_BOARD");
+    println!("cargo:rerun-if-env-changed=NEZHA_DTS_OVERLAYS");
+    println!("cargo:rerun-if-changed=dts");
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let board = env::var("NEZHA_BOARD").unwrap_or_else(|_| "sunxi".to_string());
+    let base = manifest_dir.join("dts").join(format!("{}.dts", board));
+    let overlays: Vec<PathBuf> = env::var("NEZHA_DTS_OVERLAYS")
+        .map(|list| list.split(',').filter(|path| !path.is_empty()).map(|path| manifest_dir.join(path)).collect())
+        .unwrap_or_default();
+    let output = dts_compiler::compile(&base, &overlays)
+        .unwrap_or_else(|e| panic!("cannot compile device tree for board {}: {}", board, e));
+    for file in &output.files {
+        println!("cargo:rerun-if-changed={}", file.display());
+    }
+    fs::write(out_dir.join("device-tree.dtb"), &output.dtb).unwrap();
+}
+
+// 固件配置文件中的键，每一个都可以被环境变量 NEZHA_<KEY> 覆盖，键中的短横线换成下划线
+const CONFIG_KEYS: [&str; 5] = ["per-hart-stack-size", "heap-size", "harts", "log-level", "baud-rate"];
+const LOG_LEVELS: [(&str, &str); 6] = [
+    ("off", "Off"),
+    ("error", "Error"),
+    ("warn", "Warn"),
+    ("info", "Info"),
+    ("debug", "Debug"),
+    ("trace", "Trace"),
+];
+
+// 从 firmware.toml（或 $NEZHA_CONFIG 指定的文件）和 NEZHA_* 环境变量生成 src/config.rs 引入的 Config 常量
+fn generate_config(out_dir: &Path) {
+    println!("cargo:rerun-if-env-changed=NEZHA_CONFIG");
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let path = env::var("NEZHA_CONFIG").map(|path| manifest_dir.join(path)).unwrap_or_else(|_| manifest_dir.join("firmware.toml"));
+    println!("cargo:rerun-if-changed={}", path.display());
+    let source = fs::read_to_string(&path).unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e));
+    let mut table = match source.parse::<toml::Value>() {
+        Ok(toml::Value::Table(table)) => table,
+        Ok(_) => unreachable!("a TOML document is always a table"),
+        Err(e) => panic!("cannot parse {}: {}", path.display(), e),
+    };
+    if let Some(key) = table.keys().find(|key| !CONFIG_KEYS.contains(&key.as_str())) {
+        panic!("{}: unknown key `{}`, expected one of {:?}", path.display(), key, CONFIG_KEYS);
+    }
+    for key in CONFIG_KEYS.iter() {
+        let var = format!("NEZHA_{}", key.to_uppercase().replace('-', "_"));
+        println!("cargo:rerun-if-env-changed={}", var);
+        if let Ok(value) = env::var(&var) {
+            let value = match parse_int(&value) {
+                Some(value) => toml::Value::Integer(value as i64),
+                None => toml::Value::String(value),
+            };
+            table.insert(key.to_string(), value);
+        }
+    }
+    let int = |key: &str| table.get(key).map(|value| match value.as_integer() {
+        Some(value) if value >= 0 => value as usize,
+        _ => panic!("firmware config: `{}` must be a non-negative integer, found {}", key, value),
+    });
+    let per_hart_stack_size = int("per-hart-stack-size").unwrap_or(8 * 1024);
+    let heap_size = int("heap-size").unwrap_or(8 * 1024);
+    let harts = int("harts");
+    let baud_rate = int("baud-rate").unwrap_or(115_200);
+    let log_level = match table.get("log-level") {
+        None => "Info",
+        Some(value) => match LOG_LEVELS.iter().find(|(name, _)| value.as_str() == Some(name)) {
+            Some((_, level)) => level,
+            None => panic!("firmware config: `log-level` must be one of off, error, warn, info, debug, trace, found {}", value),
+        },
+    };
+    // 栈按 hartid 划分，每个栈都要让 sp 保持 16 字节对齐
+    if per_hart_stack_size == 0 || per_hart_stack_size % 16 != 0 {
+        panic!("firmware config: `per-hart-stack-size` must be a non-zero multiple of 16, found {:#x}", per_hart_stack_size);
+    }
+    if heap_size == 0 || heap_size % 16 != 0 {
+        panic!("firmware config: `heap-size` must be a non-zero multiple of 16, found {:#x}", heap_size);
+    }
+    // 分频按运行时 APB1 的实际频率计算，当前时钟得不到的波特率由 Serial::init 在运行时拒绝
+    if baud_rate == 0 || baud_rate > u32::MAX as usize {
+        panic!("firmware config: `baud-rate` must be a non-zero 32-bit integer, found {}", baud_rate);
+    }
+    // 上线的核记录在 usize 的位图中
+    if let Some(harts) = harts {
+        if harts == 0 || harts > 64 {
+            panic!("firmware config: `harts` must be between 1 and 64, found {}", harts);
+        }
+    }
+
+    let harts = harts.map_or_else(|| "None".to_string(), |harts| format!("Some({})", harts));
+    let config = format!(
+        "// 由 build.rs 从 {} 生成\n\
+        pub const CONFIG: Config = Config {{\n    \
+            per_hart_stack_size: {:#x},\n    \
+            heap_size: {:#x},\n    \
+            harts: {},\n    \
+            log_level: LogLevel::{},\n    \
+            baud_rate: {},\n\
+        }};\n",
+        path.display(), per_hart_stack_size, heap_size, harts, log_level, baud_rate
+    );
+    fs::write(out_dir.join("config.rs"), config).unwrap();
+}
+
+fn parse_int(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None => value.replace('_', "").parse().ok(),
+    }
+}
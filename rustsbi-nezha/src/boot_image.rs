@@ -0,0 +1,126 @@
+use boot_config::{ELF_STAGING_ADDRESS, FIRMWARE_BASE, IMAGE_HEADER_OFFSET, INITRD_ADDRESS, NEXT_STAGE_ADDRESS, image::{ImageEntry, ImageError, ImageHeader, IMAGE_HEADER_SIZE}};
+use core::convert::Infallible;
+use embedded_hal::serial::{Read, Write};
+use ymodem::Receiver;
+use crate::platform::{CurrentPlatform, Platform};
+
+/// 校验之后的合并镜像
+pub struct BootImage {
+    /// 下一阶段的入口地址
+    pub entry: usize,
+    /// 下一阶段在镜像中的大小
+    pub payload_size: usize,
+    /// 镜像中附带的设备树
+    pub device_tree: Option<&'static [u8]>,
+    /// 镜像中附带的 initrd 的起止地址
+    pub initrd: Option<(usize, usize)>,
+}
+
+/// 解析并校验 xtask 写入的镜像头部，把各段数据放到指定的地址。
+///
+/// 校验失败时打印错误信息，然后等待从串口上传下一阶段；没有打开 upload 特性时停机。
+pub fn load() -> BootImage {
+    match unsafe { try_load() } {
+        Ok(image) => image,
+        Err(e) => {
+            error!("================================================");
+            error!("BOOT IMAGE VERIFICATION FAILED");
+            error!("{}", e);
+            error!("header at {:#x}, re-fuse the image with `cargo xtask`", FIRMWARE_BASE + IMAGE_HEADER_OFFSET);
+            error!("================================================");
+            if cfg!(feature = "upload") {
+                upload()
+            } else {
+                loop {
+                    unsafe { riscv::asm::wfi() };
+                }
+            }
+        }
+    }
+}
+
+// 通过串口接收下一阶段，不能附带设备树和 initrd
+fn upload() -> BootImage {
+    info!("waiting for YMODEM upload on console, run `cargo xtask upload <file>`");
+    let buf = unsafe { core::slice::from_raw_parts_mut(NEXT_STAGE_ADDRESS as *mut u8, INITRD_ADDRESS - NEXT_STAGE_ADDRESS) };
+    let mut receiver = Receiver::new(Console(CurrentPlatform::console()), read_time, CurrentPlatform::TIMEBASE_FREQUENCY);
+    let size = loop {
+        match receiver.receive(buf) {
+            Ok(size) => break size,
+            Err(e) => warn!("upload failed: {}, waiting for upload again", e),
+        }
+    };
+    info!("received {:#x} bytes at {:#x}", size, NEXT_STAGE_ADDRESS);
+    // 和 xtask 合并镜像时一样，ELF 文件放到暂存区再加载，避免加载的段覆盖文件本身
+    let entry = if size >= 4 && buf[..4] == *b"\x7fELF" {
+        unsafe { core::ptr::copy(buf.as_ptr(), ELF_STAGING_ADDRESS as *mut u8, size) };
+        ELF_STAGING_ADDRESS
+    } else {
+        NEXT_STAGE_ADDRESS
+    };
+    unsafe { asm!("fence.i") };
+    BootImage { entry, payload_size: size, device_tree: None, initrd: None }
+}
+
+// 把平台的控制台串口交给 YMODEM 接收方
+struct Console<S>(S);
+
+impl<S: Read<u8, Error = Infallible> + Write<u8, Error = Infallible>> ymodem::Serial for Console<S> {
+    fn try_read(&mut self) -> Option<u8> {
+        self.0.try_read().ok()
+    }
+
+    fn write(&mut self, byte: u8) {
+        let _ = nb::block!(self.0.try_write(byte));
+        let _ = nb::block!(self.0.try_flush());
+    }
+}
+
+fn read_time() -> u64 {
+    crate::hal::clint::mtime::read()
+}
+
+unsafe fn try_load() -> Result<BootImage, ImageError> {
+    let header_bytes = core::slice::from_raw_parts((FIRMWARE_BASE + IMAGE_HEADER_OFFSET) as *const u8, IMAGE_HEADER_SIZE);
+    let header = ImageHeader::parse(header_bytes)?;
+    // 镜像覆盖的范围由最后一个条目决定，先全部校验再移动，避免校验到一半时覆盖了别的条目
+    let entries = [Some(("payload", header.payload)), header.device_tree.map(|e| ("device tree", e)), header.initrd.map(|e| ("initrd", e))];
+    let image_size = entries.iter().flatten()
+        .map(|(_, e)| e.offset as usize + e.size as usize)
+        .max().unwrap_or(0);
+    let image = core::slice::from_raw_parts(FIRMWARE_BASE as *const u8, image_size);
+    for (name, entry) in entries.iter().flatten() {
+        entry.verify(*name, image)?;
+        info!("{} at offset {:#x}, size {:#x}, crc32 {:#010x}", name, entry.offset, entry.size, entry.crc32);
+    }
+    // initrd 放在下一阶段后面，下一阶段清零 .bss 时可能覆盖它，所以必须移到加载地址。
+    // 设备树可以原地使用：device_tree::prepare 在加载下一阶段之前就把它复制走了
+    if let Some(initrd) = header.initrd {
+        if initrd.load_address == 0 {
+            return Err(ImageError::MissingLoadAddress("initrd"));
+        }
+    }
+    let initrd = header.initrd.map(|e| place(&e));
+    let device_tree = header.device_tree.map(|e| {
+        let (start, end) = place(&e);
+        core::slice::from_raw_parts(start as *const u8, end - start)
+    });
+    let (entry, _) = place(&header.payload);
+    // 下一阶段可能被移动过
+    asm!("fence.i");
+    Ok(BootImage { entry, payload_size: header.payload.size as usize, device_tree, initrd })
+}
+
+// 把条目移动到它的加载地址，返回数据的起止地址
+unsafe fn place(entry: &ImageEntry) -> (usize, usize) {
+    let src = FIRMWARE_BASE + entry.offset as usize;
+    let size = entry.size as usize;
+    let dst = match entry.load_address as usize {
+        0 => src,
+        address => address,
+    };
+    if dst != src {
+        core::ptr::copy(src as *const u8, dst as *mut u8, size);
+    }
+    (dst, dst + size)
+}
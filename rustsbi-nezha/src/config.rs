@@ -0,0 +1,33 @@
+//! 编译时配置，由 build.rs 根据 firmware.toml（或 NEZHA_CONFIG 指定的文件）和 NEZHA_* 环境变量生成。
+
+/// 日志级别，比配置的级别更详细的信息不输出
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+pub struct Config {
+    /// 每个核的 M 态栈大小
+    pub per_hart_stack_size: usize,
+    /// 固件的堆大小
+    pub heap_size: usize,
+    /// 为多少个核预留栈，None 表示平台支持的最多核数
+    pub harts: Option<usize>,
+    pub log_level: LogLevel,
+    /// 控制台串口的波特率
+    pub baud_rate: u32,
+}
+
+impl Config {
+    /// 是否输出这个级别的信息
+    pub const fn enabled(&self, level: LogLevel) -> bool {
+        level as usize <= self.log_level as usize
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/config.rs"));
@@ -0,0 +1,134 @@
+//! 控制台串口的中断接收。
+//!
+//! 打开接收中断之后，串口收到的字节由启动核的 M 态外部中断取出，放进环形缓冲区，
+//! 旧版 console_getchar 和 DBCN 的读取都从缓冲区中取，S 态不轮询时收到的字节也不会丢失。
+//! 缓冲区满时丢弃新收到的字节，和串口 FIFO 的溢出一起计数。
+//!
+//! 这个功能由 uart-rx-interrupt 特性打开，默认关闭。M 态中断先于 S 态处理，
+//! 在 S 态的 PLIC 上下文中也打开了串口中断的内核（比如 Linux 的 8250 驱动）会发现 FIFO 已被取空，
+//! 收不到任何输入。只有 S 态完全通过 SBI 使用控制台时才适合打开。
+use core::{convert::Infallible, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
+use embedded_hal::serial::{Read, Write};
+use riscv::register::mie;
+use spin::Mutex;
+
+use crate::{hal::{InterruptRx, Plic}, platform::{CurrentPlatform, Platform}};
+
+const BUFFER_SIZE: usize = 256;
+// 启动核 M 态的 PLIC 上下文。D1 和 QEMU virt 上每个核依次有 M 态和 S 态两个上下文
+const MACHINE_CONTEXT: usize = 0;
+
+struct RingBuffer {
+    data: [u8; BUFFER_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    // 缓冲区满时返回 false
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == BUFFER_SIZE {
+            return false;
+        }
+        self.data[(self.head + self.len) % BUFFER_SIZE] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.data[self.head];
+        self.head = (self.head + 1) % BUFFER_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+static RX_BUFFER: Mutex<RingBuffer> = Mutex::new(RingBuffer { data: [0; BUFFER_SIZE], head: 0, len: 0 });
+static RX_INTERRUPT: AtomicBool = AtomicBool::new(false);
+static OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// 在启动核上调用：通过 PLIC 把串口的接收中断交给这个核的 M 态
+pub fn init_rx_interrupt() {
+    if !cfg!(feature = "uart-rx-interrupt") {
+        return;
+    }
+    let plic = Plic::new(CurrentPlatform::PLIC_BASE);
+    plic.set_priority(CurrentPlatform::CONSOLE_IRQ, 1);
+    plic.set_threshold(MACHINE_CONTEXT, 0);
+    plic.enable(MACHINE_CONTEXT, CurrentPlatform::CONSOLE_IRQ);
+    CurrentPlatform::console().enable_rx_interrupt();
+    RX_INTERRUPT.store(true, Ordering::Release);
+    unsafe { mie::set_mext() };
+}
+
+/// M 态外部中断时调用，返回是否处理了串口的接收中断
+pub fn handle_external_interrupt() -> bool {
+    if !RX_INTERRUPT.load(Ordering::Acquire) {
+        return false;
+    }
+    let plic = Plic::new(CurrentPlatform::PLIC_BASE);
+    let irq = plic.claim(MACHINE_CONTEXT);
+    if irq == 0 {
+        return false;
+    }
+    if irq == CurrentPlatform::CONSOLE_IRQ {
+        drain_rx(&mut RX_BUFFER.lock());
+    }
+    plic.complete(MACHINE_CONTEXT, irq);
+    irq == CurrentPlatform::CONSOLE_IRQ
+}
+
+/// 接收时丢失的字节次数，包括串口 FIFO 溢出和缓冲区满
+pub fn overruns() -> usize {
+    OVERRUNS.load(Ordering::Relaxed)
+}
+
+// 取出串口 FIFO 中的所有字节
+fn drain_rx(buffer: &mut RingBuffer) {
+    let mut uart = CurrentPlatform::console();
+    loop {
+        let (byte, overrun) = uart.receive();
+        if overrun {
+            OVERRUNS.fetch_add(1, Ordering::Relaxed);
+        }
+        match byte {
+            Some(byte) if !buffer.push(byte) => {
+                OVERRUNS.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(_) => {}
+            None => break,
+        }
+    }
+}
+
+/// 固件给 S 态使用的控制台：写直接交给串口，打开接收中断之后从缓冲区读
+pub struct Console;
+
+impl Read<u8> for Console {
+    type Error = Infallible;
+
+    fn try_read(&mut self) -> nb::Result<u8, Self::Error> {
+        if !RX_INTERRUPT.load(Ordering::Acquire) {
+            return CurrentPlatform::console().try_read();
+        }
+        // 先取出还没有触发中断的字节，比如不满接收超时的几个字节
+        let mut buffer = RX_BUFFER.lock();
+        drain_rx(&mut buffer);
+        buffer.pop().ok_or(nb::Error::WouldBlock)
+    }
+}
+
+impl Write<u8> for Console {
+    type Error = Infallible;
+
+    fn try_write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        CurrentPlatform::console().try_write(word)
+    }
+
+    fn try_flush(&mut self) -> nb::Result<(), Self::Error> {
+        CurrentPlatform::console().try_flush()
+    }
+}
@@ -0,0 +1,136 @@
+//! SBI 调试控制台（DBCN）扩展。rustsbi 0.2.0-alpha.3 没有这个扩展，调用在交给 rustsbi 之前处理。
+//!
+//! 缓冲区是 S 态给出的物理地址。访问时临时关闭地址翻译，再通过 MPRV 以 S 态的权限访问，
+//! 这样 PMP 保护的固件内存和不存在的地址都会返回错误，而不是让固件出错。
+//!
+//! DBCN 是 SBI 2.0 中的扩展，Linux 只在规范版本不低于 2.0 时探测它，而 rustsbi 报告的是 0.2，
+//! 所以打开这个扩展时 BASE 的 get_spec_version 也在这里处理，报告 2.0。
+use embedded_hal::serial::{Read, Write};
+use rustsbi::SbiRet;
+
+use crate::{console::Console, feature, platform::{CurrentPlatform, Platform}, runtime::SupervisorContext};
+
+pub const EXTENSION_DBCN: usize = 0x4442_434e;
+
+const FUNCTION_CONSOLE_WRITE: usize = 0x0;
+const FUNCTION_CONSOLE_READ: usize = 0x1;
+const FUNCTION_CONSOLE_WRITE_BYTE: usize = 0x2;
+
+const EXTENSION_BASE: usize = 0x10;
+const FUNCTION_BASE_GET_SPEC_VERSION: usize = 0x0;
+const FUNCTION_BASE_PROBE_EXTENSION: usize = 0x3;
+
+// 主版本号在第 24 到 30 位，次版本号在低 24 位
+const SPEC_VERSION_2_0: usize = 2 << 24;
+
+const SBI_ERR_INVALID_PARAM: usize = -3isize as usize;
+
+// 一次从内存中取出的字节数，和 C906 串口的发送 FIFO 一样大
+const CHUNK_SIZE: usize = 64;
+
+/// 处理调试控制台扩展的调用，以及对它的探测；返回是否处理了这次调用
+pub fn emulate_sbi_call(ctx: &mut SupervisorContext) -> bool {
+    if !cfg!(feature = "sbi-dbcn") {
+        return false;
+    }
+    let ret = match (ctx.a7, ctx.a6) {
+        (EXTENSION_BASE, FUNCTION_BASE_GET_SPEC_VERSION) => SbiRet::ok(SPEC_VERSION_2_0),
+        (EXTENSION_BASE, FUNCTION_BASE_PROBE_EXTENSION) if ctx.a0 == EXTENSION_DBCN => SbiRet::ok(1),
+        (EXTENSION_DBCN, FUNCTION_CONSOLE_WRITE) => console_write(ctx.a0, ctx.a1, ctx.a2),
+        (EXTENSION_DBCN, FUNCTION_CONSOLE_READ) => console_read(ctx.a0, ctx.a1, ctx.a2),
+        (EXTENSION_DBCN, FUNCTION_CONSOLE_WRITE_BYTE) => {
+            let mut console = CurrentPlatform::console();
+            nb::block!(console.try_write(ctx.a0 as u8)).ok();
+            SbiRet::ok(0)
+        }
+        (EXTENSION_DBCN, _) => SbiRet::not_supported(),
+        _ => return false,
+    };
+    ctx.a0 = ret.error;
+    ctx.a1 = ret.value;
+    true
+}
+
+// 写出缓冲区中的字节，返回写出的字节数。中途遇到不能访问的地址时只写出之前的部分
+fn console_write(num_bytes: usize, base_lo: usize, base_hi: usize) -> SbiRet {
+    let base = match buffer_base(num_bytes, base_lo, base_hi) {
+        Some(base) => base,
+        None => return invalid_param(),
+    };
+    let mut console = CurrentPlatform::console();
+    let mut written = 0;
+    while written < num_bytes {
+        let mut chunk = [0u8; CHUNK_SIZE];
+        let len = CHUNK_SIZE.min(num_bytes - written);
+        let copied = with_physical_access(|| {
+            for (i, byte) in chunk[..len].iter_mut().enumerate() {
+                match unsafe { feature::load_u8(base + written + i, feature::MSTATUS_MPRV) } {
+                    Ok(data) => *byte = data,
+                    Err(()) => return i,
+                }
+            }
+            len
+        });
+        for &byte in chunk[..copied].iter() {
+            nb::block!(console.try_write(byte)).ok();
+        }
+        written += copied;
+        if copied < len {
+            break;
+        }
+    }
+    if written == 0 && num_bytes != 0 {
+        return invalid_param();
+    }
+    SbiRet::ok(written)
+}
+
+// 读取已经收到的字节，不等待；返回读取的字节数。打开接收中断时从接收缓冲区读
+fn console_read(num_bytes: usize, base_lo: usize, base_hi: usize) -> SbiRet {
+    let base = match buffer_base(num_bytes, base_lo, base_hi) {
+        Some(base) => base,
+        None => return invalid_param(),
+    };
+    // 先把缓冲区的第一个字节原样写回，确认可以写入，避免从串口取出的字节无处可放
+    let writable = with_physical_access(|| unsafe {
+        feature::load_u8(base, feature::MSTATUS_MPRV).and_then(|data| feature::store_u8(base, data))
+    });
+    if num_bytes != 0 && writable.is_err() {
+        return invalid_param();
+    }
+    let mut console = Console;
+    let mut read = 0;
+    while read < num_bytes {
+        let byte = match console.try_read() {
+            Ok(byte) => byte,
+            Err(_) => break,
+        };
+        if with_physical_access(|| unsafe { feature::store_u8(base + read, byte) }).is_err() {
+            break;
+        }
+        read += 1;
+    }
+    SbiRet::ok(read)
+}
+
+// 64 位平台上物理地址的高位必须为 0，缓冲区也不能越过地址空间的末尾
+fn buffer_base(num_bytes: usize, base_lo: usize, base_hi: usize) -> Option<usize> {
+    if base_hi != 0 || base_lo.checked_add(num_bytes).is_none() {
+        return None;
+    }
+    Some(base_lo)
+}
+
+// 临时关闭 S 态的地址翻译，让 MPRV 访问使用物理地址。
+// Bare 模式的访存不经过 TLB，恢复原来的 satp 之后也不需要刷新
+fn with_physical_access<T>(f: impl FnOnce() -> T) -> T {
+    let satp: usize;
+    unsafe { asm!("csrrw {}, satp, zero", out(reg) satp) };
+    let ans = f();
+    unsafe { asm!("csrw satp, {}", in(reg) satp) };
+    ans
+}
+
+fn invalid_param() -> SbiRet {
+    SbiRet { error: SBI_ERR_INVALID_PARAM, value: 0 }
+}
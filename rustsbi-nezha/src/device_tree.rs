@@ -0,0 +1,252 @@
+use alloc::{format, string::String, vec::Vec};
+use riscv::register::misa::{self, MXL};
+use boot_config::{DEVICE_TREE_ADDRESS, DEVICE_TREE_MAX_SIZE, FIRMWARE_BASE, FIRMWARE_SIZE};
+use fdt_editor::{self as fdt, Fdt, FdtError};
+
+use crate::{hal, hsm, platform::{CurrentPlatform, Platform}};
+
+const DRAM_BASE: usize = CurrentPlatform::DRAM_BASE;
+const DRAM_MAX_SIZE: usize = CurrentPlatform::DRAM_MAX_SIZE;
+
+// CLINT 节点的兼容字符串
+const CLINT_COMPATIBLE: [&str; 3] = ["riscv,clint0", "sifive,clint0", "thead,c900-clint"];
+
+// 修正时新增的节点和属性需要的空间
+const FIXUP_HEADROOM: usize = 0x1000; // 4KiB
+// 上一阶段传入的设备树的大小上限。更大的设备树复制之后放不下修正，不能交给下一阶段
+const PREVIOUS_STAGE_TREE_MAX_SIZE: usize = DEVICE_TREE_MAX_SIZE - FIXUP_HEADROOM;
+
+/// 选择交给下一阶段的设备树：上一阶段在 a1 中传入了合法的设备树时使用它，否则使用默认的设备树
+pub fn select(opaque: usize, default: &'static [u8]) -> &'static [u8] {
+    match unsafe { previous_stage_tree(opaque) } {
+        Ok(blob) => {
+            info!("using device tree from previous stage at {:#x}, size {:#x}", opaque, blob.len());
+            blob
+        }
+        Err(FdtError::NoSpace) => {
+            error!("device tree from previous stage at {:#x} is larger than {:#x}, using default device tree",
+                opaque, PREVIOUS_STAGE_TREE_MAX_SIZE);
+            default
+        }
+        Err(e) => {
+            warn!("no valid device tree from previous stage (a1 = {:#x}, {:?}), using default device tree", opaque, e);
+            default
+        }
+    }
+}
+
+unsafe fn previous_stage_tree(opaque: usize) -> Result<&'static [u8], FdtError> {
+    // 设备树必须 8 字节对齐，并且整个位于 DRAM 中
+    let in_dram = |start: usize, len: usize| {
+        start >= DRAM_BASE && len <= DRAM_MAX_SIZE && start - DRAM_BASE <= DRAM_MAX_SIZE - len
+    };
+    if opaque % 8 != 0 || !in_dram(opaque, fdt::FDT_HEADER_SIZE) {
+        return Err(FdtError::BadMagic);
+    }
+    let header = core::slice::from_raw_parts(opaque as *const u8, fdt::FDT_HEADER_SIZE);
+    let total_size = fdt::peek_total_size(header)?;
+    if !in_dram(opaque, total_size) {
+        return Err(FdtError::BadLayout);
+    }
+    if total_size > PREVIOUS_STAGE_TREE_MAX_SIZE {
+        return Err(FdtError::NoSpace);
+    }
+    let blob = core::slice::from_raw_parts(opaque as *const u8, total_size);
+    fdt::check_header(blob)?;
+    Ok(blob)
+}
+
+/// 把设备树复制到可写的内存中并做修正，返回交给下一阶段的设备树地址。
+///
+/// initrd 是合并镜像中附带的 initrd 的起止地址。修正失败时原样传递 blob。
+pub fn prepare(blob: &[u8], initrd: Option<(usize, usize)>) -> usize {
+    let start = blob.as_ptr() as usize;
+    if start < DEVICE_TREE_ADDRESS + DEVICE_TREE_MAX_SIZE && DEVICE_TREE_ADDRESS < start + blob.len() {
+        warn!("device tree at {:#x} overlaps fixup area, passing it unmodified", start);
+        return start;
+    }
+    let buf = unsafe { core::slice::from_raw_parts_mut(DEVICE_TREE_ADDRESS as *mut u8, DEVICE_TREE_MAX_SIZE) };
+    let ans = Fdt::open_into(blob, buf).and_then(|mut fdt| {
+        detect_clint(&fdt);
+        apply_fixups(&mut fdt, initrd);
+        Ok(fdt.total_size())
+    });
+    match ans {
+        Ok(size) => {
+            info!("device tree at {:#x}, size {:#x}", DEVICE_TREE_ADDRESS, size);
+            DEVICE_TREE_ADDRESS
+        }
+        Err(e) => {
+            // 没有修正的设备树中没有固件的保留内存，S 态访问固件所在的内存会出错
+            error!("device tree fixup failed: {:?}, passing it unmodified", e);
+            blob.as_ptr() as usize
+        }
+    }
+}
+
+// 设备树中有 CLINT 节点时，改用其中的基地址；寄存器的排列仍然由平台配置给出
+fn detect_clint(fdt: &Fdt) {
+    match find_clint(fdt) {
+        Ok(Some(base)) if base != hal::clint::clint().base() => {
+            info!("CLINT at {:#x} from device tree", base);
+            hal::clint::set_base(base);
+        }
+        Ok(_) => {}
+        Err(e) => warn!("cannot read CLINT from device tree: {:?}", e),
+    }
+}
+
+fn find_clint(fdt: &Fdt) -> Result<Option<usize>, FdtError> {
+    let soc = match fdt.find_node("/soc") {
+        Ok(node) => node,
+        Err(FdtError::NotFound) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let address_cells = fdt.property_u32(soc, "#address-cells").unwrap_or(2) as usize;
+    let mut index = 0;
+    while let Some(node) = fdt.child(soc, index)? {
+        index += 1;
+        let compatible = match fdt.property(node, "compatible") {
+            Some(compatible) => compatible,
+            None => continue,
+        };
+        if !compatible.split(|&b| b == 0).any(|name| CLINT_COMPATIBLE.iter().any(|c| c.as_bytes() == name)) {
+            continue;
+        }
+        let reg = fdt.property(node, "reg").ok_or(FdtError::NotFound)?;
+        if address_cells == 0 || address_cells > 2 || reg.len() < 4 * address_cells {
+            return Err(FdtError::BadLayout);
+        }
+        let mut base = 0u64;
+        for cell in reg[..4 * address_cells].chunks(4) {
+            base = base << 32 | u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]) as u64;
+        }
+        return Ok(Some(base as usize));
+    }
+    Ok(None)
+}
+
+// 各个修正互不依赖，一个失败时仍然做其余的修正。每次修改之后设备树都是完整的
+fn apply_fixups(fdt: &mut Fdt, initrd: Option<(usize, usize)>) {
+    let report = |name: &str, ans: Result<(), FdtError>| {
+        if let Err(e) = ans {
+            warn!("device tree fixup {} failed: {:?}", name, e);
+        }
+    };
+    report("reserved-memory", fixup_reserved_memory(fdt));
+    report("memory", fixup_memory(fdt));
+    report("cpus", fixup_cpus(fdt));
+    if let Some((start, end)) = initrd {
+        report("initrd", fixup_initrd(fdt, start, end));
+    }
+}
+
+// 在 /reserved-memory 中加入固件所在的区域
+fn fixup_reserved_memory(fdt: &mut Fdt) -> Result<(), FdtError> {
+    let root = fdt.root()?;
+    let (address_cells, size_cells) = root_cells(fdt)?;
+    let reserved = match fdt.subnode(root, "reserved-memory") {
+        Ok(node) => node,
+        Err(FdtError::NotFound) => {
+            let node = fdt.add_subnode(root, "reserved-memory")?;
+            fdt.set_property_cells(node, "#address-cells", &[address_cells])?;
+            fdt.set_property_cells(node, "#size-cells", &[size_cells])?;
+            fdt.set_property(node, "ranges", &[])?;
+            node
+        }
+        Err(e) => return Err(e),
+    };
+    // 上一阶段可能已经加入了同名的节点，这时改写它的属性
+    let name = format!("mmode_resv0@{:x}", FIRMWARE_BASE);
+    let node = match fdt.subnode(reserved, &name) {
+        Ok(node) => node,
+        Err(FdtError::NotFound) => fdt.add_subnode(reserved, &name)?,
+        Err(e) => return Err(e),
+    };
+    let reg = encode_reg(address_cells, size_cells, FIRMWARE_BASE, FIRMWARE_SIZE);
+    fdt.set_property_cells(node, "reg", &reg)?;
+    fdt.set_property(node, "no-map", &[])
+}
+
+// 把 /memory 的大小改成探测到的 DRAM 大小，平台不能探测时保留设备树中的值
+fn fixup_memory(fdt: &mut Fdt) -> Result<(), FdtError> {
+    let (address_cells, size_cells) = root_cells(fdt)?;
+    let dram_size = match CurrentPlatform::dram_size() {
+        Some(size) => size,
+        None => return Ok(()),
+    };
+    info!("DRAM size: {} MiB", dram_size / 1024 / 1024);
+    let memory = fdt.find_node("/memory")?;
+    let reg = encode_reg(address_cells, size_cells, DRAM_BASE, dram_size);
+    fdt.set_property_cells(memory, "reg", &reg)
+}
+
+// 填写 riscv,isa，禁用没有上线的核
+fn fixup_cpus(fdt: &mut Fdt) -> Result<(), FdtError> {
+    let isa = isa_string().ok_or(FdtError::NotFound)?;
+    let cpus = fdt.find_node("/cpus")?;
+    let mut index = 0;
+    while let Some(cpu) = fdt.child(cpus, index)? {
+        index += 1;
+        if fdt.property(cpu, "device_type") != Some(&b"cpu\0"[..]) {
+            continue;
+        }
+        fdt.set_property_str(cpu, "riscv,isa", &isa)?;
+        let hartid = fdt.property_u32(cpu, "reg").ok_or(FdtError::NotFound)? as usize;
+        if !hsm::is_online(hartid) {
+            warn!("hart {} is not online, disable it in device tree", hartid);
+            fdt.set_property_str(cpu, "status", "disabled")?;
+        }
+    }
+    Ok(())
+}
+
+// 在 /chosen 中写入 initrd 的位置
+fn fixup_initrd(fdt: &mut Fdt, start: usize, end: usize) -> Result<(), FdtError> {
+    let root = fdt.root()?;
+    let chosen = match fdt.subnode(root, "chosen") {
+        Ok(node) => node,
+        Err(FdtError::NotFound) => fdt.add_subnode(root, "chosen")?,
+        Err(e) => return Err(e),
+    };
+    fdt.set_property_cells(chosen, "linux,initrd-start", &encode_u64(start as u64))?;
+    fdt.set_property_cells(chosen, "linux,initrd-end", &encode_u64(end as u64))
+}
+
+fn root_cells(fdt: &Fdt) -> Result<(u32, u32), FdtError> {
+    let root = fdt.root()?;
+    // 规范规定的默认值
+    let address_cells = fdt.property_u32(root, "#address-cells").unwrap_or(2);
+    let size_cells = fdt.property_u32(root, "#size-cells").unwrap_or(1);
+    Ok((address_cells, size_cells))
+}
+
+fn encode_reg(address_cells: u32, size_cells: u32, address: usize, size: usize) -> Vec<u32> {
+    let mut reg = Vec::new();
+    for &(value, cells) in [(address as u64, address_cells), (size as u64, size_cells)].iter() {
+        for i in (0..cells).rev() {
+            reg.push(if i < 2 { (value >> (32 * i)) as u32 } else { 0 });
+        }
+    }
+    reg
+}
+
+fn encode_u64(value: u64) -> [u32; 2] {
+    [(value >> 32) as u32, value as u32]
+}
+
+// 按规范的顺序由 misa 生成 ISA 字符串，S、U、X 等不属于 ISA 字符串
+fn isa_string() -> Option<String> {
+    let isa = misa::read()?;
+    let mut ans = String::from(match isa.mxl() {
+        MXL::XLEN32 => "rv32",
+        MXL::XLEN64 => "rv64",
+        MXL::XLEN128 => "rv128",
+    });
+    for ext in "IEMAFDQLCBJTPVH".chars() {
+        if isa.has_extension(ext) {
+            ans.push(ext.to_ascii_lowercase());
+        }
+    }
+    Some(ans)
+}
@@ -0,0 +1,162 @@
+//! 加载 ELF64 格式的下一阶段，按 PT_LOAD 段的物理地址放置
+use core::fmt;
+use boot_config::{DEVICE_TREE_ADDRESS, DEVICE_TREE_MAX_SIZE, FIRMWARE_BASE, FIRMWARE_SIZE};
+
+use crate::platform::{CurrentPlatform, Platform};
+
+const DRAM_BASE: usize = CurrentPlatform::DRAM_BASE;
+const DRAM_MAX_SIZE: usize = CurrentPlatform::DRAM_MAX_SIZE;
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+#[derive(Debug)]
+pub enum ElfError {
+    BadHeader(&'static str),
+    SegmentOutOfFile(usize),
+    SegmentOutOfDram(usize),
+    SegmentOverlaps { index: usize, name: &'static str },
+    EntryNotLoaded(usize),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfError::BadHeader(reason) => write!(f, "bad ELF header: {}", reason),
+            ElfError::SegmentOutOfFile(index) => write!(f, "segment {} is out of file", index),
+            ElfError::SegmentOutOfDram(index) => write!(f, "segment {} is out of DRAM", index),
+            ElfError::SegmentOverlaps { index, name } => write!(f, "segment {} overlaps {}", index, name),
+            ElfError::EntryNotLoaded(entry) => write!(f, "entry {:#x} is not in any segment", entry),
+        }
+    }
+}
+
+/// 下一阶段是 ELF 文件时加载它，返回物理的入口地址；不是 ELF 文件时返回 None。
+///
+/// entry 和 size 是 ELF 文件在内存中的位置，加载出错时停在这里，不会进入下一阶段。
+pub fn prepare(entry: usize, size: usize, initrd: Option<(usize, usize)>) -> Option<usize> {
+    let file = unsafe { core::slice::from_raw_parts(entry as *const u8, size) };
+    if size < 4 || file[..4] != ELF_MAGIC {
+        return None;
+    }
+    match unsafe { load(file, initrd) } {
+        Ok(entry) => Some(entry),
+        Err(e) => {
+            error!("cannot load ELF payload: {}, refusing to boot", e);
+            loop {
+                unsafe { riscv::asm::wfi() };
+            }
+        }
+    }
+}
+
+struct Segment {
+    offset: usize,
+    vaddr: usize,
+    paddr: usize,
+    filesz: usize,
+    memsz: usize,
+}
+
+unsafe fn load(file: &[u8], initrd: Option<(usize, usize)>) -> Result<usize, ElfError> {
+    if file.len() < EHDR_SIZE {
+        return Err(ElfError::BadHeader("truncated"));
+    }
+    if file[4] != ELFCLASS64 || file[5] != ELFDATA2LSB {
+        return Err(ElfError::BadHeader("not a little-endian ELF64 file"));
+    }
+    if read_u16(file, 16) != ET_EXEC {
+        return Err(ElfError::BadHeader("not an executable"));
+    }
+    if read_u16(file, 18) != EM_RISCV {
+        return Err(ElfError::BadHeader("not a RISC-V file"));
+    }
+    let e_entry = read_u64(file, 24) as usize;
+    let phoff = read_u64(file, 32) as usize;
+    let phentsize = read_u16(file, 54) as usize;
+    let phnum = read_u16(file, 56) as usize;
+    if phentsize != PHDR_SIZE || phoff.checked_add(phnum * PHDR_SIZE).map_or(true, |end| end > file.len()) {
+        return Err(ElfError::BadHeader("bad program headers"));
+    }
+    let segment = |index: usize| {
+        let ph = &file[phoff + index * PHDR_SIZE..][..PHDR_SIZE];
+        if read_u32(ph, 0) != PT_LOAD {
+            return None;
+        }
+        Some(Segment {
+            offset: read_u64(ph, 8) as usize,
+            vaddr: read_u64(ph, 16) as usize,
+            paddr: read_u64(ph, 24) as usize,
+            filesz: read_u64(ph, 32) as usize,
+            memsz: read_u64(ph, 40) as usize,
+        })
+    };
+    // 先检查所有的段，确认无误之后再复制，避免加载到一半才发现错误
+    let file_start = file.as_ptr() as usize;
+    let mut reserved = [
+        ("firmware", FIRMWARE_BASE, FIRMWARE_BASE + FIRMWARE_SIZE),
+        ("device tree", DEVICE_TREE_ADDRESS, DEVICE_TREE_ADDRESS + DEVICE_TREE_MAX_SIZE),
+        ("ELF file", file_start, file_start + file.len()),
+        ("initrd", 0, 0),
+    ];
+    if let Some((start, end)) = initrd {
+        reserved[3] = ("initrd", start, end);
+    }
+    let mut entry = None;
+    for index in 0..phnum {
+        let seg = match segment(index) {
+            Some(seg) => seg,
+            None => continue,
+        };
+        if seg.filesz > seg.memsz || seg.offset.checked_add(seg.filesz).map_or(true, |end| end > file.len()) {
+            return Err(ElfError::SegmentOutOfFile(index));
+        }
+        let end = seg.paddr.checked_add(seg.memsz).ok_or(ElfError::SegmentOutOfDram(index))?;
+        if seg.paddr < DRAM_BASE || end > DRAM_BASE + DRAM_MAX_SIZE {
+            return Err(ElfError::SegmentOutOfDram(index));
+        }
+        for &(name, start, region_end) in reserved.iter() {
+            if seg.paddr < region_end && start < end {
+                return Err(ElfError::SegmentOverlaps { index, name });
+            }
+        }
+        // 入口地址是虚拟地址，按所在的段换算成物理地址
+        if e_entry >= seg.vaddr && e_entry - seg.vaddr < seg.memsz {
+            entry = Some(e_entry - seg.vaddr + seg.paddr);
+        }
+    }
+    let entry = entry.ok_or(ElfError::EntryNotLoaded(e_entry))?;
+    for index in 0..phnum {
+        if let Some(seg) = segment(index) {
+            info!("ELF segment {}: {:#x}..{:#x}", index, seg.paddr, seg.paddr + seg.memsz);
+            let dst = seg.paddr as *mut u8;
+            core::ptr::copy_nonoverlapping(file.as_ptr().add(seg.offset), dst, seg.filesz);
+            core::ptr::write_bytes(dst.add(seg.filesz), 0, seg.memsz - seg.filesz);
+        }
+    }
+    asm!("fence.i");
+    Ok(entry)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(buf)
+}
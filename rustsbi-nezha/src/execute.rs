@@ -4,14 +4,21 @@ use core::{
 };
 use riscv::register::scause::{Trap, Exception};
 use crate::{runtime::{MachineTrap, Runtime, SupervisorContext}};
-use crate::feature;
+use crate::{console, dbcn, feature, hsm, ipi, pmu::{self, FirmwareEvent}, stats::{self, Emulated}, vendor};
 
 pub fn execute_supervisor(supervisor_mepc: usize, a0: usize, a1: usize) -> ! {
+    let hartid = riscv::register::mhartid::read();
     let mut rt = Runtime::new_sbi_supervisor(supervisor_mepc, a0, a1);
     loop {
-        match Pin::new(&mut rt).resume(()) {
+        let state = Pin::new(&mut rt).resume(());
+        if let GeneratorState::Yielded(trap) = &state {
+            stats::record_trap(hartid, trap);
+        }
+        match state {
             GeneratorState::Yielded(MachineTrap::SbiCall()) => {
                 let ctx = rt.context_mut();
+                stats::record_sbi_call(hartid, ctx.a7, ctx.a6);
+                trace!("sbi call extension {:#x} function {:#x}", ctx.a7, ctx.a6);
                 if emulate_sbi_call(ctx) {
                     continue;
                 }
@@ -21,11 +28,16 @@ pub fn execute_supervisor(supervisor_mepc: usize, a0: usize, a1: usize) -> ! {
                 ctx.a0 = ans.error;
                 ctx.a1 = ans.value;
                 ctx.mepc = ctx.mepc.wrapping_add(4);
+                // hart_stop 和非保持挂起需要从新的地址重新进入 S 态
+                if let Some((start_addr, opaque)) = hsm::pending_transition(hartid) {
+                    rt = Runtime::new_sbi_supervisor(start_addr, hartid, opaque);
+                }
             },
             GeneratorState::Yielded(MachineTrap::IllegalInstruction()) => {
                 let ctx = rt.context_mut();
                 let ins = unsafe { get_vaddr_u32(ctx.mepc) } as usize;
-                if !emulate_illegal_instruction(ctx, ins) {
+                if !emulate_illegal_instruction(hartid, ctx, ins) {
+                    pmu::record(FirmwareEvent::IllegalInstruction);
                     unsafe {
                         if feature::should_transfer_trap(ctx) {
                             feature::do_transfer_trap(ctx, Trap::Exception(Exception::IllegalInstruction))
@@ -35,15 +47,22 @@ pub fn execute_supervisor(supervisor_mepc: usize, a0: usize, a1: usize) -> ! {
                     }
                 }
             },
-            GeneratorState::Yielded(MachineTrap::ExternalInterrupt()) => unsafe {
-                let ctx = rt.context_mut();
-                feature::call_supervisor_interrupt(ctx)
+            GeneratorState::Yielded(MachineTrap::ExternalInterrupt()) => {
+                // 串口的接收中断由固件处理，其余的交给 S 态注册的处理函数
+                if !console::handle_external_interrupt() {
+                    let ctx = rt.context_mut();
+                    unsafe { feature::call_supervisor_interrupt(ctx) }
+                }
             },
             GeneratorState::Yielded(MachineTrap::MachineTimer()) => {
                 feature::forward_supervisor_timer()
             },
             GeneratorState::Yielded(MachineTrap::MachineSoft()) => {
-                feature::forward_supervisor_soft()
+                // 先处理远程 fence，剩下的才是发给 S 态的软件中断
+                if ipi::handle_machine_soft(hartid) {
+                    pmu::record(FirmwareEvent::IpiReceived);
+                    feature::forward_supervisor_soft()
+                }
             },
             GeneratorState::Yielded(MachineTrap::InstructionFault(_addr)) => {
                 let ctx = rt.context_mut();
@@ -59,8 +78,9 @@ pub fn execute_supervisor(supervisor_mepc: usize, a0: usize, a1: usize) -> ! {
             },
             GeneratorState::Yielded(MachineTrap::LoadFault(_addr)) => {
                 let ctx = rt.context_mut();
+                pmu::record(FirmwareEvent::AccessLoad);
                 unsafe {
-                        feature::do_transfer_trap(ctx, Trap::Exception(Exception::LoadFault))
+                    feature::do_transfer_trap(ctx, Trap::Exception(Exception::LoadFault))
                 }
             },
             GeneratorState::Yielded(MachineTrap::LoadPageFault(_addr)) => {
@@ -77,10 +97,29 @@ pub fn execute_supervisor(supervisor_mepc: usize, a0: usize, a1: usize) -> ! {
             },
             GeneratorState::Yielded(MachineTrap::StoreFault(_addr)) => {
                 let ctx = rt.context_mut();
+                pmu::record(FirmwareEvent::AccessStore);
                 unsafe {
                     feature::do_transfer_trap(ctx, Trap::Exception(Exception::StoreFault))
                 }
             },
+            GeneratorState::Yielded(MachineTrap::LoadMisaligned(addr)) => {
+                let ctx = rt.context_mut();
+                if feature::emulate_misaligned_load(ctx, addr) {
+                    stats::record_emulated(hartid, Emulated::MisalignedLoad);
+                    pmu::record(FirmwareEvent::MisalignedLoad);
+                } else {
+                    fail_misaligned(ctx, Exception::LoadMisaligned, addr)
+                }
+            },
+            GeneratorState::Yielded(MachineTrap::StoreMisaligned(addr)) => {
+                let ctx = rt.context_mut();
+                if feature::emulate_misaligned_store(ctx, addr) {
+                    stats::record_emulated(hartid, Emulated::MisalignedStore);
+                    pmu::record(FirmwareEvent::MisalignedStore);
+                } else {
+                    fail_misaligned(ctx, Exception::StoreMisaligned, addr)
+                }
+            },
             GeneratorState::Complete(()) => unreachable!(),
         }
     }
@@ -109,11 +148,23 @@ fn emulate_sbi_call(ctx: &mut SupervisorContext) -> bool {
     if feature::emulate_sbi_rustsbi_nezha_sext(ctx) {
         return true;
     }
-    false
+    // 在 rustsbi 之前处理的调用，处理之后跳过 ecall 指令
+    let handled = vendor::emulate_sbi_call(ctx)
+        || pmu::emulate_sbi_call(ctx)
+        || dbcn::emulate_sbi_call(ctx);
+    if handled {
+        ctx.mepc = ctx.mepc.wrapping_add(4);
+    }
+    handled
 }
 
-fn emulate_illegal_instruction(ctx: &mut SupervisorContext, ins: usize) -> bool {
+fn emulate_illegal_instruction(hartid: usize, ctx: &mut SupervisorContext, ins: usize) -> bool {
     if feature::emulate_rdtime(ctx, ins) {
+        stats::record_emulated(hartid, Emulated::Rdtime);
+        return true;
+    }
+    if feature::emulate_counter_read(ctx, ins) {
+        stats::record_emulated(hartid, Emulated::CounterRead);
         return true;
     }
     false
@@ -123,3 +174,14 @@ fn emulate_illegal_instruction(ctx: &mut SupervisorContext, ins: usize) -> bool
 fn fail_illegal_instruction(ctx: &mut SupervisorContext, ins: usize) -> ! {
     panic!("invalid instruction from machine level, mepc: {:016x?}, instruction: {:016x?}, context: {:016x?}", ctx.mepc, ins, ctx);
 }
+
+// 无法模拟的非对齐访存（比如原子指令），交给 S 层处理
+fn fail_misaligned(ctx: &mut SupervisorContext, exception: Exception, addr: usize) {
+    unsafe {
+        if feature::should_transfer_trap(ctx) {
+            feature::do_transfer_trap(ctx, Trap::Exception(exception))
+        } else {
+            panic!("misaligned access from machine level, mepc: {:016x?}, address: {:016x?}, context: {:016x?}", ctx.mepc, addr, ctx);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use crate::{pmu, runtime::SupervisorContext};
+use super::set_register_xi;
+
+/// 模拟 S 态读取没有在 mcounteren 中打开的 cycle、instret 和 hpmcounter，time 由 emulate_rdtime 处理
+#[inline]
+pub fn emulate_counter_read(ctx: &mut SupervisorContext, ins: usize) -> bool {
+    // rdcycle 等指令是 rs1 为 x0 的 csrrs 指令
+    if ins & 0x000F_F07F != 0x0000_2073 {
+        return false;
+    }
+    let csr = ins >> 20;
+    if !(0xC00..=0xC1F).contains(&csr) {
+        return false;
+    }
+    let value = match pmu::read_hardware_counter(csr - 0xC00) {
+        Some(value) => value,
+        None => return false, // 没有实现的计数器，按非法指令处理
+    };
+    let rd = ((ins >> 7) & 0b1_1111) as u8;
+    set_register_xi(ctx, rd, value as usize);
+    ctx.mepc = ctx.mepc.wrapping_add(4);
+    true
+}
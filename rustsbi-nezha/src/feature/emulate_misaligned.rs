@@ -0,0 +1,286 @@
+use crate::runtime::SupervisorContext;
+use super::{get_register_xi, set_register_xi};
+use riscv::register::{
+    mcause, mstatus::{self, FS}, scause::{Trap, Exception}
+};
+
+pub const MSTATUS_MPRV: usize = 1 << 17;
+const MSTATUS_MXR: usize = 1 << 19;
+
+// 被模拟的访存指令
+#[derive(Debug, Clone, Copy)]
+enum Access {
+    Load { rd: u8, len: usize, signed: bool, fp: bool },
+    Store { rs2: u8, len: usize, fp: bool },
+}
+
+/// 模拟非对齐读。返回 false 表示无法识别这条指令，由调用者决定如何处理
+pub fn emulate_misaligned_load(ctx: &mut SupervisorContext, addr: usize) -> bool {
+    emulate_misaligned(ctx, addr, false)
+}
+
+/// 模拟非对齐写。返回 false 表示无法识别这条指令，由调用者决定如何处理
+pub fn emulate_misaligned_store(ctx: &mut SupervisorContext, addr: usize) -> bool {
+    emulate_misaligned(ctx, addr, true)
+}
+
+fn emulate_misaligned(ctx: &mut SupervisorContext, addr: usize, is_store: bool) -> bool {
+    let (ins, ins_len) = match fetch_instruction(ctx.mepc) {
+        Ok(ans) => ans,
+        Err(cause) => {
+            // 取指令时就出错了，交给 S 层处理
+            unsafe { super::do_transfer_trap(ctx, cause) };
+            return true;
+        }
+    };
+    let access = match decode(ins, ins_len) {
+        Some(access) => access,
+        None => return false,
+    };
+    let ans = match access {
+        Access::Load { rd, len, signed, fp } if !is_store => {
+            let mut buf = [0u8; 8];
+            let mut ans = Ok(());
+            for i in 0..len {
+                match unsafe { load_u8(addr.wrapping_add(i), MSTATUS_MPRV) } {
+                    Ok(byte) => buf[i] = byte,
+                    Err(()) => { ans = Err(load_fault_cause()); break }
+                }
+            }
+            ans.map(|_| {
+                let raw = u64::from_le_bytes(buf);
+                if fp {
+                    unsafe { set_fp_register(rd, raw, len) };
+                    set_fs_dirty(ctx);
+                } else {
+                    let shift = 64 - 8 * len as u32;
+                    let data = if signed {
+                        ((raw << shift) as i64 >> shift) as u64
+                    } else {
+                        raw
+                    };
+                    set_register_xi(ctx, rd, data as usize);
+                }
+            })
+        },
+        Access::Store { rs2, len, fp } if is_store => {
+            let data = if fp {
+                unsafe { get_fp_register(rs2, len) }
+            } else {
+                get_register_xi(ctx, rs2) as u64
+            };
+            let buf = data.to_le_bytes();
+            let mut ans = Ok(());
+            for i in 0..len {
+                if unsafe { store_u8(addr.wrapping_add(i), buf[i]) }.is_err() {
+                    ans = Err(store_fault_cause());
+                    break
+                }
+            }
+            ans
+        },
+        // 异常类型和指令不一致，比如原子指令
+        _ => return false,
+    };
+    match ans {
+        Ok(()) => ctx.mepc = ctx.mepc.wrapping_add(ins_len),
+        // 模拟的访存本身出错，才转发给 S 层；mtval 已经是出错的字节地址
+        Err(cause) => unsafe { super::do_transfer_trap(ctx, cause) },
+    }
+    true
+}
+
+fn decode(ins: u32, ins_len: usize) -> Option<Access> {
+    if ins_len == 4 {
+        let opcode = ins & 0x7f;
+        let funct3 = (ins >> 12) & 0b111;
+        let rd = ((ins >> 7) & 0b1_1111) as u8;
+        let rs2 = ((ins >> 20) & 0b1_1111) as u8;
+        match (opcode, funct3) {
+            (0b000_0011, 0b001) => Some(Access::Load { rd, len: 2, signed: true, fp: false }), // lh
+            (0b000_0011, 0b010) => Some(Access::Load { rd, len: 4, signed: true, fp: false }), // lw
+            (0b000_0011, 0b011) => Some(Access::Load { rd, len: 8, signed: false, fp: false }), // ld
+            (0b000_0011, 0b101) => Some(Access::Load { rd, len: 2, signed: false, fp: false }), // lhu
+            (0b000_0011, 0b110) => Some(Access::Load { rd, len: 4, signed: false, fp: false }), // lwu
+            (0b000_0111, 0b010) => Some(Access::Load { rd, len: 4, signed: false, fp: true }), // flw
+            (0b000_0111, 0b011) => Some(Access::Load { rd, len: 8, signed: false, fp: true }), // fld
+            (0b010_0011, 0b001) => Some(Access::Store { rs2, len: 2, fp: false }), // sh
+            (0b010_0011, 0b010) => Some(Access::Store { rs2, len: 4, fp: false }), // sw
+            (0b010_0011, 0b011) => Some(Access::Store { rs2, len: 8, fp: false }), // sd
+            (0b010_0111, 0b010) => Some(Access::Store { rs2, len: 4, fp: true }), // fsw
+            (0b010_0111, 0b011) => Some(Access::Store { rs2, len: 8, fp: true }), // fsd
+            _ => None,
+        }
+    } else {
+        let quadrant = ins & 0b11;
+        let funct3 = (ins >> 13) & 0b111;
+        // 压缩指令中的 rd'/rs2' 只能表示 x8..x15 或 f8..f15
+        let rd_prime = (((ins >> 2) & 0b111) + 8) as u8;
+        let rd_full = ((ins >> 7) & 0b1_1111) as u8;
+        let rs2_full = ((ins >> 2) & 0b1_1111) as u8;
+        match (quadrant, funct3) {
+            (0b00, 0b001) => Some(Access::Load { rd: rd_prime, len: 8, signed: false, fp: true }), // c.fld
+            (0b00, 0b010) => Some(Access::Load { rd: rd_prime, len: 4, signed: true, fp: false }), // c.lw
+            (0b00, 0b011) => Some(Access::Load { rd: rd_prime, len: 8, signed: false, fp: false }), // c.ld
+            (0b00, 0b101) => Some(Access::Store { rs2: rd_prime, len: 8, fp: true }), // c.fsd
+            (0b00, 0b110) => Some(Access::Store { rs2: rd_prime, len: 4, fp: false }), // c.sw
+            (0b00, 0b111) => Some(Access::Store { rs2: rd_prime, len: 8, fp: false }), // c.sd
+            (0b10, 0b001) => Some(Access::Load { rd: rd_full, len: 8, signed: false, fp: true }), // c.fldsp
+            (0b10, 0b010) if rd_full != 0 => Some(Access::Load { rd: rd_full, len: 4, signed: true, fp: false }), // c.lwsp
+            (0b10, 0b011) if rd_full != 0 => Some(Access::Load { rd: rd_full, len: 8, signed: false, fp: false }), // c.ldsp
+            (0b10, 0b101) => Some(Access::Store { rs2: rs2_full, len: 8, fp: true }), // c.fsdsp
+            (0b10, 0b110) => Some(Access::Store { rs2: rs2_full, len: 4, fp: false }), // c.swsp
+            (0b10, 0b111) => Some(Access::Store { rs2: rs2_full, len: 8, fp: false }), // c.sdsp
+            _ => None,
+        }
+    }
+}
+
+// 返回指令内容和指令长度
+fn fetch_instruction(mepc: usize) -> Result<(u32, usize), Trap> {
+    let mut ins = 0u32;
+    let mut len = 2;
+    let mut i = 0;
+    while i < len {
+        match unsafe { load_u8(mepc.wrapping_add(i), MSTATUS_MPRV | MSTATUS_MXR) } {
+            Ok(byte) => ins |= (byte as u32) << (8 * i),
+            Err(()) => return Err(match load_fault_cause() {
+                Trap::Exception(Exception::LoadPageFault) => Trap::Exception(Exception::InstructionPageFault),
+                _ => Trap::Exception(Exception::InstructionFault),
+            }),
+        }
+        i += 1;
+        if i == 1 && ins & 0b11 == 0b11 {
+            len = 4;
+        }
+    }
+    Ok((ins, len))
+}
+
+fn load_fault_cause() -> Trap {
+    match mcause::read().cause() {
+        mcause::Trap::Exception(mcause::Exception::LoadPageFault) => Trap::Exception(Exception::LoadPageFault),
+        _ => Trap::Exception(Exception::LoadFault),
+    }
+}
+
+fn store_fault_cause() -> Trap {
+    match mcause::read().cause() {
+        mcause::Trap::Exception(mcause::Exception::StorePageFault) => Trap::Exception(Exception::StorePageFault),
+        _ => Trap::Exception(Exception::StoreFault),
+    }
+}
+
+/// 以 S 层（mstatus.MPP）的权限读一个字节。访存出错时，临时的 mtvec 会跳过这条指令，
+/// 出错原因和地址保留在 mcause 和 mtval 中
+#[inline(never)]
+pub unsafe fn load_u8(vaddr: usize, mstatus_bits: usize) -> Result<u8, ()> {
+    let ans: usize;
+    let err: usize;
+    asm!("
+        la      {tmp}, 1f
+        csrrw   {mtvec}, mtvec, {tmp}
+        csrrs   {mstatus}, mstatus, {bits}
+        li      {err}, 0
+        .option push
+        .option norvc
+        lbu     {ans}, 0({vaddr})
+        .option pop
+        j       2f
+        .align  2
+    1:  csrr    {tmp}, mepc
+        addi    {tmp}, {tmp}, 4
+        csrw    mepc, {tmp}
+        li      {err}, 1
+        mret
+    2:  csrw    mstatus, {mstatus}
+        csrw    mtvec, {mtvec}
+    ",
+        vaddr = in(reg) vaddr, bits = in(reg) mstatus_bits,
+        ans = out(reg) ans, err = out(reg) err,
+        tmp = out(reg) _, mtvec = out(reg) _, mstatus = out(reg) _,
+    );
+    if err == 0 { Ok(ans as u8) } else { Err(()) }
+}
+
+/// 以 S 层的权限写一个字节，出错处理同 load_u8
+#[inline(never)]
+pub unsafe fn store_u8(vaddr: usize, data: u8) -> Result<(), ()> {
+    let err: usize;
+    asm!("
+        la      {tmp}, 1f
+        csrrw   {mtvec}, mtvec, {tmp}
+        li      {tmp}, (1 << 17)
+        csrrs   {mstatus}, mstatus, {tmp}
+        li      {err}, 0
+        .option push
+        .option norvc
+        sb      {data}, 0({vaddr})
+        .option pop
+        j       2f
+        .align  2
+    1:  csrr    {tmp}, mepc
+        addi    {tmp}, {tmp}, 4
+        csrw    mepc, {tmp}
+        li      {err}, 1
+        mret
+    2:  csrw    mstatus, {mstatus}
+        csrw    mtvec, {mtvec}
+    ",
+        vaddr = in(reg) vaddr, data = in(reg) data as usize,
+        err = out(reg) err,
+        tmp = out(reg) _, mtvec = out(reg) _, mstatus = out(reg) _,
+    );
+    if err == 0 { Ok(()) } else { Err(()) }
+}
+
+fn set_fs_dirty(ctx: &mut SupervisorContext) {
+    unsafe { mstatus::set_fs(FS::Dirty) };
+    ctx.mstatus = mstatus::read();
+}
+
+// 目标平台是 riscv64imac，汇编器不认识浮点指令，这里直接写出 fmv 指令的编码，
+// 整数寄存器固定使用 a0 (x10)
+const FMV_W_X: u32 = 0xf000_0053 | (10 << 15);
+const FMV_D_X: u32 = 0xf200_0053 | (10 << 15);
+const FMV_X_W: u32 = 0xe000_0053 | (10 << 7);
+const FMV_X_D: u32 = 0xe200_0053 | (10 << 7);
+
+unsafe fn set_fp_register(i: u8, data: u64, len: usize) {
+    macro_rules! fmv_f {
+        ($op: expr; $($n: literal),+) => {
+            match i {
+                $($n => asm!(".word {}", const $op | ($n << 7), in("a0") data),)+
+                _ => unreachable!(),
+            }
+        };
+    }
+    // fmv.w.x 会自动做 NaN-boxing
+    if len == 4 {
+        fmv_f!(FMV_W_X; 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+            16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31)
+    } else {
+        fmv_f!(FMV_D_X; 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+            16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31)
+    }
+}
+
+unsafe fn get_fp_register(i: u8, len: usize) -> u64 {
+    let data: u64;
+    macro_rules! fmv_x {
+        ($op: expr; $($n: literal),+) => {
+            match i {
+                $($n => asm!(".word {}", const $op | ($n << 15), out("a0") data),)+
+                _ => unreachable!(),
+            }
+        };
+    }
+    if len == 4 {
+        fmv_x!(FMV_X_W; 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+            16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31)
+    } else {
+        fmv_x!(FMV_X_D; 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+            16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31)
+    }
+    data
+}
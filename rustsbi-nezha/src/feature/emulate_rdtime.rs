@@ -6,8 +6,7 @@ pub fn emulate_rdtime(ctx: &mut SupervisorContext, ins: usize) -> bool {
     if ins & 0xFFFFF07F == 0xC0102073 {
         // rdtime is actually a csrrw instruction
         let rd = ((ins >> 7) & 0b1_1111) as u8;
-        let mtime:u64;
-        unsafe {asm!("csrr {}, time", out(reg) mtime);}
+        let mtime = crate::hal::clint::mtime::read();
         let time_usize = mtime as usize;
         set_register_xi(ctx, rd, time_usize);
         ctx.mepc = ctx.mepc.wrapping_add(4); // skip current instruction 
@@ -18,7 +17,17 @@ pub fn emulate_rdtime(ctx: &mut SupervisorContext, ins: usize) -> bool {
 }
 
 #[inline]
-fn set_register_xi(ctx: &mut SupervisorContext, i: u8, data: usize) {
+pub(super) fn get_register_xi(ctx: &SupervisorContext, i: u8) -> usize {
+    let registers = unsafe { &*(ctx as *const _ as *const [usize; 31]) };
+    assert!(i <= 31, "i should be valid register source");
+    if i == 0 { // x0, always zero
+        return 0
+    }
+    registers[(i - 1) as usize]
+}
+
+#[inline]
+pub(super) fn set_register_xi(ctx: &mut SupervisorContext, i: u8, data: usize) {
     let registers = unsafe { &mut *(ctx as *mut _ as *mut [usize; 31]) };
     assert!(i <= 31, "i should be valid register target");
     if i == 0 { // x0, don't modify
@@ -1,6 +1,10 @@
 mod supervisor_interrupt;
 mod transfer_trap;
 mod emulate_rdtime;
+mod emulate_misaligned;
+mod emulate_counter;
 pub use supervisor_interrupt::*;
 pub use transfer_trap::*;
-pub use emulate_rdtime::*;
\ No newline at end of file
+pub use emulate_rdtime::*;
+pub use emulate_misaligned::*;
+pub use emulate_counter::*;
\ No newline at end of file
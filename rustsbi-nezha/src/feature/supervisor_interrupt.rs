@@ -76,15 +76,20 @@ pub fn forward_supervisor_timer() {
     // Forward to S-level timer interrupt
     unsafe {
         mip::set_stimer(); // set S-timer interrupt flag
-        mie::clear_mext(); // Ref: rustsbi Pull request #5
+        // Ref: rustsbi Pull request #5; only when an S-level handler was registered,
+        // the console receive interrupt must stay enabled otherwise
+        if DEVINTRENTRY != 0 {
+            mie::clear_mext();
+        }
         mie::clear_mtimer(); // mask M-timer interrupt
     }
 }
 
 pub fn forward_supervisor_soft() {
     // Forward to S-level software interrupt
+    // MSIP has already been cleared by the handler, so M-soft stays enabled
+    // for later remote fence requests
     unsafe {
         mip::set_ssoft(); // set S-soft interrupt flag
-        mie::clear_msoft(); // mask M-soft interrupt
     }
 }
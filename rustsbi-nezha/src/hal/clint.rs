@@ -1,20 +1,42 @@
+//! 当前平台的 CLINT。寄存器的排列由平台配置给出，基地址默认也来自平台配置，
+//! 设备树中有 CLINT 节点时由 set_base 改为设备树中的地址。
+use core::sync::atomic::{AtomicUsize, Ordering};
+use ::clint::Clint;
+use crate::platform::{CurrentPlatform, Platform};
+
+static CLINT_BASE: AtomicUsize = AtomicUsize::new(CurrentPlatform::CLINT_BASE);
+
+/// 当前使用的 CLINT
+pub fn clint() -> Clint {
+    unsafe { Clint::new(CLINT_BASE.load(Ordering::Relaxed), CurrentPlatform::CLINT_LAYOUT) }
+}
+
+/// 改用设备树中的基地址，启动核在其它核使用 CLINT 之前调用
+pub fn set_base(base: usize) {
+    CLINT_BASE.store(base, Ordering::Relaxed)
+}
+
 pub mod mtimecmp{
-    use crate::{hal::{pac_encoding::{CLINT_BASE, MTIMECMPL}, write_reg}};
-    pub fn write(word:u64) {
-        unsafe { 
-            let mask = u64::MAX;
-            write_reg(CLINT_BASE, MTIMECMPL, (word & mask) as u32);
-            write_reg(CLINT_BASE, MTIMECMPL + 4, (word >> 32) as u32);
-        }
+    /// 设置核 hartid 的时钟比较值
+    pub fn write(hartid:usize, word:u64) {
+        super::clint().write_mtimecmp(hartid, word)
     }
 }
 pub mod msip{
-    use crate::{hal::{pac_encoding::{CLINT_BASE, MSIP0}, write_reg}};
-
-    pub fn set_ipi(_word:usize){
-        unsafe { write_reg(CLINT_BASE, MSIP0, 1u64)}
+    pub fn set_ipi(hartid:usize){
+        super::clint().set_msip(hartid)
     }
-    pub fn clear_ipi(_word:usize) {
-        unsafe { write_reg(CLINT_BASE, MSIP0, 0)}
+    pub fn clear_ipi(hartid:usize) {
+        super::clint().clear_msip(hartid)
     }
-}
\ No newline at end of file
+}
+pub mod mtime{
+    /// 读取 mtime；CLINT 中没有 MTIME 时读取 time 寄存器
+    pub fn read() -> u64 {
+        super::clint().read_mtime().unwrap_or_else(|| {
+            let time: u64;
+            unsafe { asm!("csrr {}, time", out(reg) time) };
+            time
+        })
+    }
+}
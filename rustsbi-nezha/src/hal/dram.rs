@@ -0,0 +1,43 @@
+use core::ptr::{read_volatile, write_volatile};
+
+const PROBE_PATTERN_0: usize = 0x5555_aaaa_3c3c_c3c3;
+const PROBE_PATTERN_1: usize = 0xaaaa_5555_c3c3_3c3c;
+const PROBE_MIN_SIZE: usize = 64 * 1024 * 1024; // 64MiB
+
+static mut PROBE: usize = 0;
+
+/// 探测 DRAM 的实际大小。
+///
+/// 超出实际容量的地址会回绕到 DRAM 的开头，所以从最小容量开始，
+/// 检查 PROBE + size 是否是 PROBE 的别名。被改写的内存会被恢复。
+pub fn probe_dram_size(base: usize, max_size: usize) -> usize {
+    let probe = unsafe { &mut PROBE as *mut usize };
+    let mut size = PROBE_MIN_SIZE;
+    // PROBE 位于 DRAM 开头的固件中，probe + size 不会超出 DRAM 的地址范围
+    while size < max_size && probe as usize + size < base + max_size {
+        let alias = (probe as usize + size) as *mut usize;
+        let aliased = unsafe {
+            let saved = read_volatile(alias);
+            write_volatile(probe, PROBE_PATTERN_0);
+            flush_dcache();
+            write_volatile(alias, PROBE_PATTERN_1);
+            flush_dcache();
+            let aliased = read_volatile(probe) == PROBE_PATTERN_1;
+            write_volatile(alias, saved);
+            flush_dcache();
+            aliased
+        };
+        if aliased {
+            return size;
+        }
+        size <<= 1;
+    }
+    max_size
+}
+
+// 别名地址在缓存中是不同的行，必须写回并无效化数据缓存才能看到回绕
+#[inline]
+unsafe fn flush_dcache() {
+    // T-HEAD 扩展指令 dcache.ciall 和 sync
+    asm!(".word 0x0030000b", ".word 0x0180000b");
+}
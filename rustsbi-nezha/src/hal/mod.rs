@@ -1,9 +1,36 @@
+#[cfg(feature = "d1")]
 pub mod serial;
 pub mod clint;
+pub mod plic;
+#[cfg(feature = "d1")]
 pub mod pac_encoding;
+#[cfg(feature = "d1")]
+pub mod watchdog;
+#[cfg(feature = "d1")]
+pub mod dram;
+#[cfg(feature = "qemu-virt")]
+pub mod ns16550a;
+#[cfg(feature = "qemu-virt")]
+pub mod sifive_test;
 use core::ptr::{read_volatile, write_volatile};
+#[cfg(feature = "d1")]
 pub use serial::Serial;
 pub use clint::msip;
+pub use plic::Plic;
+#[cfg(feature = "d1")]
+pub use watchdog::Watchdog;
+#[cfg(feature = "qemu-virt")]
+pub use ns16550a::Ns16550a;
+#[cfg(feature = "qemu-virt")]
+pub use sifive_test::SifiveTest;
+/// 可以用中断接收的串口
+pub trait InterruptRx {
+    /// 打开接收数据和接收超时中断
+    fn enable_rx_interrupt(&mut self);
+    /// 读一次 LSR：返回收到的字节，以及接收 FIFO 是否溢出过
+    fn receive(&mut self) -> (Option<u8>, bool);
+}
+
 #[inline]
 pub unsafe fn write_reg<T>(addr: usize, offset: usize, val: T) {
     write_volatile((addr + offset) as *mut T, val);
@@ -12,4 +39,4 @@ pub unsafe fn write_reg<T>(addr: usize, offset: usize, val: T) {
 #[inline]
 pub unsafe fn read_reg<T>(addr: usize, offset: usize) -> T {
     read_volatile((addr + offset) as *const T)
-}
\ No newline at end of file
+}
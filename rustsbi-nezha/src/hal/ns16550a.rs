@@ -0,0 +1,80 @@
+use core::convert::Infallible;
+use embedded_hal::serial::{Read, Write};
+
+use super::{read_reg, write_reg, InterruptRx};
+
+const UART_RBR: usize = 0;
+const UART_THR: usize = 0;
+const UART_IER: usize = 1;
+const UART_MCR: usize = 4;
+const UART_LSR: usize = 5;
+// LSR：接收缓冲区有数据
+const UART_LSR_DR: u8 = 1 << 0;
+// LSR：接收溢出
+const UART_LSR_OE: u8 = 1 << 1;
+// IER：接收数据可用中断
+const UART_IER_ERBFI: u8 = 1 << 0;
+// MCR：OUT2 打开串口到中断控制器的中断线
+const UART_MCR_OUT2: u8 = 1 << 3;
+// LSR：发送保持寄存器为空
+const UART_LSR_THRE: u8 = 1 << 5;
+
+/// 兼容 ns16550a 的串口，寄存器之间的间隔是 1 << shift 字节
+pub struct Ns16550a {
+    base: usize,
+    shift: usize,
+}
+
+impl Ns16550a {
+    pub fn new(base: usize, shift: usize) -> Self {
+        Self { base, shift }
+    }
+
+    fn read(&self, reg: usize) -> u8 {
+        unsafe { read_reg::<u8>(self.base, reg << self.shift) }
+    }
+
+    fn write(&self, reg: usize, val: u8) {
+        unsafe { write_reg::<u8>(self.base, reg << self.shift, val) }
+    }
+}
+
+impl InterruptRx for Ns16550a {
+    fn enable_rx_interrupt(&mut self) {
+        self.write(UART_MCR, self.read(UART_MCR) | UART_MCR_OUT2);
+        self.write(UART_IER, self.read(UART_IER) | UART_IER_ERBFI);
+    }
+
+    fn receive(&mut self) -> (Option<u8>, bool) {
+        let lsr = self.read(UART_LSR);
+        let byte = if lsr & UART_LSR_DR != 0 { Some(self.read(UART_RBR)) } else { None };
+        (byte, lsr & UART_LSR_OE != 0)
+    }
+}
+
+impl Read<u8> for Ns16550a {
+    type Error = Infallible;
+
+    fn try_read(&mut self) -> nb::Result<u8, Self::Error> {
+        if self.read(UART_LSR) & UART_LSR_DR != 0 {
+            Ok(self.read(UART_RBR))
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl Write<u8> for Ns16550a {
+    type Error = Infallible;
+
+    fn try_write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        while self.read(UART_LSR) & UART_LSR_THRE == 0 {}
+        self.write(UART_THR, word);
+        Ok(())
+    }
+
+    fn try_flush(&mut self) -> nb::Result<(), Self::Error> {
+        while self.read(UART_LSR) & UART_LSR_THRE == 0 {}
+        Ok(())
+    }
+}
@@ -1,11 +1,33 @@
 pub const UART0_BASE:usize= 0x0250_0000;
+pub const UART_STRIDE:usize = 0x400;
+pub const UART_COUNT:usize = 6;
 pub const UART_THR:usize = 0;
 pub const UART_RBR:usize= 0;
+pub const UART_DLL:usize = 0;
+pub const UART_DLH:usize = 0x04;
+pub const UART_IER:usize = 0x04;
+pub const UART_FCR:usize = 0x08;
+pub const UART_LCR:usize = 0x0c;
+pub const UART_MCR:usize = 0x10;
 pub const UART_LSR:usize =  0x14;
 pub const UART_USR:usize= 0x7c;
+pub const UART_HALT:usize = 0xa4;
+
+pub const CCU_BASE:usize = 0x0200_1000;
+pub const CCU_PLL_PERI0_CTRL:usize = 0x020;
+pub const CCU_PSI_CFG:usize = 0x510;
+pub const CCU_APB1_CFG:usize = 0x524;
+pub const CCU_UART_BGR:usize = 0x90c;
 
 pub const CLINT_BASE:usize = 0x0400_0000;
-pub const MSIP0:usize = 0;
-pub const MTIMECMPL:usize = 0x4000;
-//pub const MTIMECMPH:usize = 0x4004;
-//pub const SSIP:usize = 0xC000;
\ No newline at end of file
+pub const PLIC_BASE:usize = 0x1000_0000;
+
+pub const WDT_BASE:usize = 0x0601_1000;
+pub const WDT_IRQ_EN:usize = 0x00;
+pub const WDT_SOFT_RST:usize = 0x08;
+pub const WDT_CTRL:usize = 0x10;
+pub const WDT_CFG:usize = 0x14;
+pub const WDT_MODE:usize = 0x18;
+
+pub const DRAM_BASE:usize = 0x4000_0000;
+pub const DRAM_MAX_SIZE:usize = 0x8000_0000;
@@ -0,0 +1,44 @@
+use super::{read_reg, write_reg};
+
+const PRIORITY: usize = 0x0;
+const ENABLE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const THRESHOLD: usize = 0x20_0000;
+const CLAIM: usize = 0x20_0004;
+const CONTEXT_STRIDE: usize = 0x1000;
+
+/// 标准布局的 PLIC，context 是中断目标（某个核的 M 态或 S 态）的编号
+pub struct Plic {
+    base: usize,
+}
+
+impl Plic {
+    pub fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    pub fn set_priority(&self, irq: u32, priority: u32) {
+        unsafe { write_reg(self.base, PRIORITY + 4 * irq as usize, priority) }
+    }
+
+    pub fn enable(&self, context: usize, irq: u32) {
+        let offset = ENABLE + ENABLE_STRIDE * context + 4 * (irq as usize / 32);
+        unsafe {
+            let bits: u32 = read_reg(self.base, offset);
+            write_reg(self.base, offset, bits | 1 << (irq % 32));
+        }
+    }
+
+    pub fn set_threshold(&self, context: usize, threshold: u32) {
+        unsafe { write_reg(self.base, THRESHOLD + CONTEXT_STRIDE * context, threshold) }
+    }
+
+    /// 取出优先级最高的待处理中断，没有时返回 0
+    pub fn claim(&self, context: usize) -> u32 {
+        unsafe { read_reg(self.base, CLAIM + CONTEXT_STRIDE * context) }
+    }
+
+    pub fn complete(&self, context: usize, irq: u32) {
+        unsafe { write_reg(self.base, CLAIM + CONTEXT_STRIDE * context, irq) }
+    }
+}
@@ -1,10 +1,96 @@
-use core::convert::Infallible;
+use core::{convert::Infallible, fmt};
 use embedded_hal::serial::{Read,Write};
 // use crate::pac::{UART_RBR, UART_THR, UART_USR};
 
-use super::{pac_encoding::{UART_LSR, UART_RBR, UART_THR, UART_USR}, read_reg, write_reg};
-//const SUNXI_UART_USR_NF:u32 = 0x02;
-const SUNXI_UART_USR_RFNE:u32 = 0x04;
+use super::{pac_encoding::{
+    CCU_APB1_CFG, CCU_BASE, CCU_PLL_PERI0_CTRL, CCU_PSI_CFG, CCU_UART_BGR, UART0_BASE, UART_COUNT, UART_DLH, UART_DLL, UART_FCR, UART_HALT, UART_IER,
+    UART_LCR, UART_LSR, UART_MCR, UART_RBR, UART_STRIDE, UART_THR, UART_USR,
+}, read_reg, write_reg, InterruptRx};
+// USR：发送 FIFO 未满
+const SUNXI_UART_USR_TFNF:u32 = 0x02;
+// USR：发送 FIFO 为空
+const SUNXI_UART_USR_TFE:u32 = 0x04;
+// IER：接收数据可用中断
+const SUNXI_UART_IER_ERBFI:u32 = 0x01;
+// LSR：接收数据就绪、溢出
+const SUNXI_UART_LSR_DR:u32 = 0x01;
+const SUNXI_UART_LSR_OE:u32 = 0x02;
+// LCR：8 位数据、无校验、1 位停止位，DLAB 打开时访问分频寄存器
+const SUNXI_UART_LCR_8N1:u32 = 0x03;
+const SUNXI_UART_LCR_DLAB:u32 = 0x80;
+// FCR：打开 FIFO 并清空收发 FIFO
+const SUNXI_UART_FCR_FIFO_RESET:u32 = 0x07;
+// MCR：DTR、RTS
+const SUNXI_UART_MCR_DTR_RTS:u32 = 0x03;
+// HALT：忙时也可以修改线路设置，写 1 让修改生效，完成后硬件清零
+const SUNXI_UART_HALT_CHCFG_AT_BUSY:u32 = 0x02;
+const SUNXI_UART_HALT_CHANGE_UPDATE:u32 = 0x04;
+// CCU 中 UART_BGR 的门控位和复位位
+const CCU_UART_GATING_SHIFT:usize = 0;
+const CCU_UART_RST_SHIFT:usize = 16;
+// APB1_CFG 和 PSI_CFG：时钟源在 25:24 位，N 在 9:8 位（2^N 分频）；APB1 的 M 在 4:0 位，PSI 的 M 在 1:0 位
+const CCU_CLK_SRC_SHIFT:usize = 24;
+const CCU_FACTOR_N_SHIFT:usize = 8;
+const CCU_APB1_FACTOR_M_MASK:u32 = 0x1f;
+const CCU_PSI_FACTOR_M_MASK:u32 = 0x03;
+// PLL_PERI0_CTRL：N 在 15:8 位，M 在第 1 位，P0 在 18:16 位
+const CCU_PLL_PERI0_N_SHIFT:usize = 8;
+const CCU_PLL_PERI0_M_SHIFT:usize = 1;
+const CCU_PLL_PERI0_P0_SHIFT:usize = 16;
+const HOSC_FREQUENCY:u64 = 24_000_000;
+const RTC_32K_FREQUENCY:u64 = 32_768;
+const RC_16M_FREQUENCY:u64 = 16_000_000;
+
+/// 当前的 APB1 时钟无法得到要求的波特率
+#[derive(Debug, Clone, Copy)]
+pub struct BaudRateError {
+    pub apb1_frequency: u32,
+    pub baud_rate: u32,
+}
+
+impl fmt::Display for BaudRateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "baud rate {} is out of range for APB1 clock {} Hz", self.baud_rate, self.apb1_frequency)
+    }
+}
+
+// PLL_PERI(1X) = 24MHz * N / M / P0 / 2
+fn pll_peri_1x_frequency() -> u64 {
+    let ctrl = unsafe { read_reg::<u32>(CCU_BASE, CCU_PLL_PERI0_CTRL) };
+    let n = ((ctrl >> CCU_PLL_PERI0_N_SHIFT) & 0xff) as u64 + 1;
+    let m = ((ctrl >> CCU_PLL_PERI0_M_SHIFT) & 0x1) as u64 + 1;
+    let p0 = ((ctrl >> CCU_PLL_PERI0_P0_SHIFT) & 0x7) as u64 + 1;
+    HOSC_FREQUENCY * n / m / p0 / 2
+}
+
+// 按 CCU 中的设置计算总线时钟：sources[时钟源] / 2^N / (M + 1)
+fn bus_frequency(cfg:u32, m_mask:u32, sources:[u64; 4]) -> u64 {
+    let source = sources[((cfg >> CCU_CLK_SRC_SHIFT) & 0x3) as usize];
+    (source >> ((cfg >> CCU_FACTOR_N_SHIFT) & 0x3)) / ((cfg & m_mask) + 1) as u64
+}
+
+/// 串口的时钟来自 APB1，频率由前一阶段在 CCU 中的设置决定
+pub fn apb1_frequency() -> u32 {
+    let (psi_cfg, apb1_cfg) = unsafe { (read_reg::<u32>(CCU_BASE, CCU_PSI_CFG), read_reg::<u32>(CCU_BASE, CCU_APB1_CFG)) };
+    let pll_peri = pll_peri_1x_frequency();
+    // 2 号时钟源：PSI 的是内部的 RC16M，APB1 的是 PSI
+    let psi = bus_frequency(psi_cfg, CCU_PSI_FACTOR_M_MASK, [HOSC_FREQUENCY, RTC_32K_FREQUENCY, RC_16M_FREQUENCY, pll_peri]);
+    bus_frequency(apb1_cfg, CCU_APB1_FACTOR_M_MASK, [HOSC_FREQUENCY, RTC_32K_FREQUENCY, psi, pll_peri]) as u32
+}
+
+// 16 倍过采样，四舍五入；DLL 和 DLH 一共 16 位
+fn divisor(apb1_frequency:u32, baud_rate:u32) -> Result<u32, BaudRateError> {
+    let error = BaudRateError { apb1_frequency, baud_rate };
+    if baud_rate == 0 {
+        return Err(error);
+    }
+    let divisor = (apb1_frequency as u64 + 8 * baud_rate as u64) / (16 * baud_rate as u64);
+    if divisor == 0 || divisor > 0xffff {
+        return Err(error);
+    }
+    Ok(divisor as u32)
+}
+
 pub struct Serial{
     uart:usize
 }
@@ -12,6 +98,67 @@ impl Serial{
     pub fn new(base:usize) -> Self{
         Self{ uart: base }
     }
+
+    /// 第 index 个串口，0 到 5 对应设备树中的 uart0 到 uart5
+    pub fn uart(index:usize) -> Self{
+        assert!(index < UART_COUNT, "sunxi uart index should be less than {}", UART_COUNT);
+        Self::new(UART0_BASE + index * UART_STRIDE)
+    }
+
+    /// 完整地初始化第 index 个串口：CCU 中的时钟门控和复位、波特率、8N1 和 FIFO。
+    /// 引脚复用和板子有关，不在这里设置；APB1 时钟保持前一阶段的设置，分频按它计算。
+    /// 当前时钟得不到这个波特率时返回错误，不改动串口
+    pub fn init(index:usize, baud_rate:u32) -> Result<Self, BaudRateError>{
+        let mut serial = Self::uart(index);
+        let divisor = divisor(apb1_frequency(), baud_rate)?;
+        unsafe {
+            let bgr = read_reg::<u32>(CCU_BASE, CCU_UART_BGR);
+            let bits = (1 << (CCU_UART_GATING_SHIFT + index)) | (1 << (CCU_UART_RST_SHIFT + index));
+            // 已经在使用的串口先发完 FIFO 中的数据，再复位
+            if bgr & bits == bits {
+                nb::block!(serial.try_flush()).ok();
+            }
+            write_reg::<u32>(CCU_BASE, CCU_UART_BGR, bgr & !bits);
+            write_reg::<u32>(CCU_BASE, CCU_UART_BGR, bgr | bits);
+        }
+        serial.set_line(divisor);
+        Ok(serial)
+    }
+
+    // 设置波特率分频和 8N1，打开 FIFO，关闭所有中断
+    fn set_line(&mut self, divisor:u32) {
+        unsafe {
+            write_reg::<u32>(self.uart, UART_IER, 0);
+            write_reg::<u32>(self.uart, UART_HALT, SUNXI_UART_HALT_CHCFG_AT_BUSY);
+            write_reg::<u32>(self.uart, UART_LCR, SUNXI_UART_LCR_DLAB | SUNXI_UART_LCR_8N1);
+            write_reg::<u32>(self.uart, UART_DLL, divisor & 0xff);
+            write_reg::<u32>(self.uart, UART_DLH, (divisor >> 8) & 0xff);
+            write_reg::<u32>(self.uart, UART_LCR, SUNXI_UART_LCR_8N1);
+            write_reg::<u32>(self.uart, UART_HALT, SUNXI_UART_HALT_CHCFG_AT_BUSY | SUNXI_UART_HALT_CHANGE_UPDATE);
+            while read_reg::<u32>(self.uart, UART_HALT) & SUNXI_UART_HALT_CHANGE_UPDATE != 0 {}
+            write_reg::<u32>(self.uart, UART_HALT, 0);
+            write_reg::<u32>(self.uart, UART_FCR, SUNXI_UART_FCR_FIFO_RESET);
+            write_reg::<u32>(self.uart, UART_MCR, SUNXI_UART_MCR_DTR_RTS);
+        }
+    }
+}
+impl InterruptRx for Serial {
+    fn enable_rx_interrupt(&mut self) {
+        unsafe {
+            let ier = read_reg::<u32>(self.uart, UART_IER);
+            write_reg::<u32>(self.uart, UART_IER, ier | SUNXI_UART_IER_ERBFI)
+        }
+    }
+
+    fn receive(&mut self) -> (Option<u8>, bool) {
+        let lsr = unsafe { read_reg::<u32>(self.uart, UART_LSR) };
+        let byte = if lsr & SUNXI_UART_LSR_DR != 0 {
+            Some(unsafe { (read_reg::<u32>(self.uart, UART_RBR) & 0xff) as u8 })
+        } else {
+            None
+        };
+        (byte, lsr & SUNXI_UART_LSR_OE != 0)
+    }
 }
 impl Read<u8> for Serial {
     type Error = Infallible;
@@ -26,14 +173,15 @@ impl Read<u8> for Serial {
 impl Write<u8> for Serial{
     type Error = Infallible;
 
+    // 只等待 FIFO 有空位，连续写入的字节可以填满 FIFO
     fn try_write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
-        while unsafe {read_reg::<u32>(self.uart, UART_USR) & SUNXI_UART_USR_RFNE} == 0{}
+        while unsafe {read_reg::<u32>(self.uart, UART_USR) & SUNXI_UART_USR_TFNF} == 0{}
         unsafe {write_reg::<u32>(self.uart, UART_THR, word as u32)}
         Ok(())
     }
 
     fn try_flush(&mut self) -> nb::Result<(), Self::Error> {
-        while unsafe {read_reg::<u32>(self.uart, UART_USR) & SUNXI_UART_USR_RFNE} == 0{}
+        while unsafe {read_reg::<u32>(self.uart, UART_USR) & SUNXI_UART_USR_TFE} == 0{}
         Ok(())
     }
 }
\ No newline at end of file
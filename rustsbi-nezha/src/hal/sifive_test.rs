@@ -0,0 +1,37 @@
+use super::write_reg;
+
+const FINISHER_FAIL: u32 = 0x3333;
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_RESET: u32 = 0x7777;
+
+/// sifive,test0，QEMU virt 用它来关机和重启
+pub struct SifiveTest {
+    base: usize
+}
+
+impl SifiveTest {
+    pub fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    /// 正常关机，QEMU 以 0 退出
+    pub fn pass(&self) -> ! {
+        self.finish(FINISHER_PASS)
+    }
+
+    /// 以失败关机，QEMU 的退出码为 code
+    pub fn fail(&self, code: u16) -> ! {
+        self.finish(((code as u32) << 16) | FINISHER_FAIL)
+    }
+
+    pub fn reset(&self) -> ! {
+        self.finish(FINISHER_RESET)
+    }
+
+    fn finish(&self, value: u32) -> ! {
+        unsafe { write_reg::<u32>(self.base, 0, value) };
+        loop {
+            unsafe { riscv::asm::wfi() };
+        }
+    }
+}
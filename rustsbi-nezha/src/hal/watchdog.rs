@@ -0,0 +1,38 @@
+use super::{pac_encoding::{WDT_CFG, WDT_CTRL, WDT_IRQ_EN, WDT_MODE, WDT_SOFT_RST}, write_reg};
+
+// 写 CFG、MODE、SOFT_RST 寄存器时高 16 位必须是这个值
+const SUNXI_WDT_KEY: u32 = 0x16aa << 16;
+// CTRL 寄存器重新装载计数值
+const SUNXI_WDT_CTRL_RESTART: u32 = (0x0a57 << 1) | (1 << 0);
+// CFG 寄存器：超时后复位整个系统
+const SUNXI_WDT_CFG_SYSTEM_RESET: u32 = 0x1;
+// MODE 寄存器：超时间隔为 0.5s，使能看门狗
+const SUNXI_WDT_MODE_INTV_HALF_SECOND: u32 = 0x0 << 4;
+const SUNXI_WDT_MODE_EN: u32 = 1 << 0;
+const SUNXI_WDT_SOFT_RST_EN: u32 = 1 << 0;
+
+/// allwinner,sun20i-wdt 看门狗
+pub struct Watchdog {
+    base: usize
+}
+
+impl Watchdog {
+    pub fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    /// 通过看门狗复位整个系统，不会返回
+    pub fn reset_system(&self) -> ! {
+        unsafe {
+            write_reg::<u32>(self.base, WDT_IRQ_EN, 0);
+            write_reg::<u32>(self.base, WDT_CFG, SUNXI_WDT_KEY | SUNXI_WDT_CFG_SYSTEM_RESET);
+            write_reg::<u32>(self.base, WDT_MODE, SUNXI_WDT_KEY | SUNXI_WDT_MODE_INTV_HALF_SECOND | SUNXI_WDT_MODE_EN);
+            write_reg::<u32>(self.base, WDT_CTRL, SUNXI_WDT_CTRL_RESTART);
+            // 软复位立即生效；如果没有生效，最迟 0.5s 后看门狗超时复位
+            write_reg::<u32>(self.base, WDT_SOFT_RST, SUNXI_WDT_KEY | SUNXI_WDT_SOFT_RST_EN);
+        }
+        loop {
+            unsafe { riscv::asm::wfi() };
+        }
+    }
+}
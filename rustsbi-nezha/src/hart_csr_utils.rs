@@ -1,6 +1,5 @@
-use alloc::vec::Vec;
+use alloc::{format, string::String, vec::Vec};
 use riscv::register::{medeleg, mideleg, misa::{self, MXL}, pmpaddr0, pmpaddr1, pmpaddr10, pmpaddr11, pmpaddr12, pmpaddr13, pmpaddr14, pmpaddr15, pmpaddr2, pmpaddr3, pmpaddr4, pmpaddr5, pmpaddr6, pmpaddr7, pmpaddr8, pmpaddr9, pmpcfg0, pmpcfg2};
-use rustsbi::{print, println};
 pub const PMP_COUNT:usize = 16;
 pub const PMP_SHIFT:usize = 2;
 pub const PMP_R:usize = 0x01;
@@ -91,8 +90,8 @@ pub fn print_hart_pmp(){
             }else{
                 0
             };
-            if (port & PMP_A_TOR) == PMP_A_TOR{
-                print!("PMP{}\t 0x{:x} - 0x{:x} (A",i,match i {
+            let mut line = if (port & PMP_A_TOR) == PMP_A_TOR{
+                format!("PMP{}\t 0x{:x} - 0x{:x} (A",i,match i {
                     0 => 0,
                     1 => pmpaddr0::read(),
                     2 => pmpaddr1::read(),
@@ -112,22 +111,22 @@ pub fn print_hart_pmp(){
                     _ => 0
                 } << PMP_SHIFT,addr)
             } else {
-                print!("PMP{}\t: 0x{:>08x} - 0x{:>08x} (A",i,addr,addr + size - 1);
-            }
+                format!("PMP{}\t: 0x{:>08x} - 0x{:>08x} (A",i,addr,addr + size - 1)
+            };
             
             if (port & PMP_L) != 0{
-                print!(",L");
+                line.push_str(",L");
             }
             if (port & PMP_R) != 0{
-                print!(",R");
+                line.push_str(",R");
             }
             if (port & PMP_W) != 0{
-                print!(",W");
+                line.push_str(",W");
             }
             if (port & PMP_X) != 0{
-                print!(",X");
+                line.push_str(",X");
             }
-            print!(")\r\n")
+            debug!("{})", line)
         }
     }
 }
@@ -141,13 +140,13 @@ fn print_misa() {
             MXL::XLEN64 => "RV64",
             MXL::XLEN128 => "RV128",
         };
-        print!("[rustsbi] misa: {}", mxl_str);
+        let mut line = String::from(mxl_str);
         for ext in 'A'..='Z' {
             if isa.has_extension(ext) {
-                print!("{}", ext);
+                line.push(ext);
             }
         }
-        println!("");
+        debug!("misa: {}", line);
     }
 }
 
@@ -173,7 +172,7 @@ fn print_mideleg() {
     if mideleg.sext() {
         delegs.push("sext")
     }
-    println!("[rustsbi] mideleg: {} ({:#x})", delegs.join(", "), mideleg.bits());
+    debug!("mideleg: {} ({:#x})", delegs.join(", "), mideleg.bits());
 }
 
 #[inline]
@@ -222,5 +221,5 @@ fn print_medeleg() {
     if medeleg.store_page_fault() {
         delegs.push("spage")
     }
-    println!("[rustsbi] medeleg: {} ({:#x})", delegs.join(", "), medeleg.bits());
+    debug!("medeleg: {} ({:#x})", delegs.join(", "), medeleg.bits());
 }
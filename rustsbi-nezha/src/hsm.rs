@@ -0,0 +1,193 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use riscv::{asm::wfi, register::{mie, mip}};
+use rustsbi::SbiRet;
+use spin::Mutex;
+
+use crate::{console, hal::{clint::mtime, msip}, ipi, NUM_HARTS};
+
+pub const HART_STATE_STARTED: usize = 0;
+pub const HART_STATE_STOPPED: usize = 1;
+pub const HART_STATE_START_PENDING: usize = 2;
+pub const HART_STATE_STOP_PENDING: usize = 3;
+pub const HART_STATE_SUSPENDED: usize = 4;
+pub const HART_STATE_SUSPEND_PENDING: usize = 5;
+
+const SUSPEND_TYPE_RETENTIVE: u32 = 0x0000_0000;
+const SUSPEND_TYPE_NON_RETENTIVE: u32 = 0x8000_0000;
+const SUSPEND_TYPE_PLATFORM_RETENTIVE: u32 = 0x1000_0000;
+const SUSPEND_TYPE_PLATFORM_NON_RETENTIVE: u32 = 0x9000_0000;
+
+const SBI_ERR_FAILED: usize = -1isize as usize;
+const SBI_ERR_INVALID_PARAM: usize = -3isize as usize;
+const SBI_ERR_ALREADY_AVAILABLE: usize = -6isize as usize;
+
+struct HartContext {
+    state: usize,
+    start_addr: usize,
+    opaque: usize,
+    retentive: bool,
+}
+
+const HART_CONTEXT_INIT: Mutex<HartContext> = Mutex::new(HartContext {
+    state: HART_STATE_STOPPED,
+    start_addr: 0,
+    opaque: 0,
+    retentive: false,
+});
+
+static HARTS: [Mutex<HartContext>; NUM_HARTS] = [HART_CONTEXT_INIT; NUM_HARTS];
+
+// 进入过 rust_main 的核。放在 .data 中，不会被启动核的 init_bss 清零
+#[link_section = ".data"]
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// 每个核进入 rust_main 时调用
+pub fn mark_online(hartid: usize) {
+    ONLINE_HARTS.fetch_or(1 << hartid, Ordering::SeqCst);
+}
+
+/// 该核是否进入过固件。没有上线的核在设备树中会被禁用
+pub fn is_online(hartid: usize) -> bool {
+    hartid < NUM_HARTS && ONLINE_HARTS.load(Ordering::SeqCst) & (1 << hartid) != 0
+}
+
+/// 等待所有的核进入固件，最多等待 timeout 个 time 周期
+pub fn wait_for_online_harts(timeout: u64) {
+    // NUM_HARTS 可以等于 usize 的位数，不能用 (1 << NUM_HARTS) - 1
+    let all = usize::MAX >> (core::mem::size_of::<usize>() * 8 - NUM_HARTS);
+    let start = mtime::read();
+    while ONLINE_HARTS.load(Ordering::SeqCst) & all != all {
+        if mtime::read().wrapping_sub(start) >= timeout {
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// 启动核在进入 S 态之前调用，标记自身已启动
+pub fn set_boot_hart_started(hartid: usize) {
+    HARTS[hartid].lock().state = HART_STATE_STARTED;
+}
+
+/// 从核在 M 态 WFI 等待，直到其它核调用 hart_start；返回 (start_addr, opaque)
+pub fn wait_for_start(hartid: usize) -> (usize, usize) {
+    unsafe {
+        mie::clear_mtimer();
+        mie::set_msoft();
+    }
+    loop {
+        // 先等待软件中断再访问共享状态，避免启动核初始化 .data 时被打扰
+        unsafe { wfi() };
+        if !mip::read().msoft() {
+            // 停止的启动核仍然负责接收控制台的数据
+            console::handle_external_interrupt();
+            continue;
+        }
+        // 停止的核也要完成远程 fence，否则发送的核会一直等待
+        msip::clear_ipi(hartid);
+        ipi::handle_rfences(hartid);
+        let mut hart = HARTS[hartid].lock();
+        if hart.state == HART_STATE_START_PENDING {
+            hart.state = HART_STATE_STARTED;
+            return (hart.start_addr, hart.opaque);
+        }
+    }
+}
+
+/// SBI 调用返回后由 execute_supervisor 检查：若本核请求了停止或挂起，在这里完成状态转移。
+///
+/// 返回 Some((addr, opaque)) 时，调用者应当从 addr 重新进入 S 态，a0 为 hartid，a1 为 opaque；
+/// 返回 None 时按原上下文继续执行。
+pub fn pending_transition(hartid: usize) -> Option<(usize, usize)> {
+    let mut hart = HARTS[hartid].lock();
+    match hart.state {
+        HART_STATE_STOP_PENDING => {
+            hart.state = HART_STATE_STOPPED;
+            drop(hart);
+            unsafe {
+                mip::clear_ssoft();
+                mip::clear_stimer();
+            }
+            Some(wait_for_start(hartid))
+        }
+        HART_STATE_SUSPEND_PENDING => {
+            hart.state = HART_STATE_SUSPENDED;
+            let retentive = hart.retentive;
+            drop(hart);
+            // 任意一个已使能的中断都可以唤醒挂起的核
+            unsafe { mie::set_msoft() };
+            while mip::read().bits() & mie::read().bits() == 0 {
+                unsafe { wfi() };
+            }
+            let mut hart = HARTS[hartid].lock();
+            hart.state = HART_STATE_STARTED;
+            if retentive {
+                None
+            } else {
+                Some((hart.start_addr, hart.opaque))
+            }
+        }
+        _ => None,
+    }
+}
+
+pub struct Hsm;
+
+impl rustsbi::Hsm for Hsm {
+    fn hart_start(&mut self, hartid: usize, start_addr: usize, opaque: usize) -> SbiRet {
+        // 没有进入过固件的核不会响应 MSIP，启动它只会让调用者一直等待
+        if !is_online(hartid) {
+            return SbiRet { error: SBI_ERR_INVALID_PARAM, value: 0 };
+        }
+        let mut hart = HARTS[hartid].lock();
+        if hart.state != HART_STATE_STOPPED {
+            return SbiRet { error: SBI_ERR_ALREADY_AVAILABLE, value: 0 };
+        }
+        hart.start_addr = start_addr;
+        hart.opaque = opaque;
+        hart.state = HART_STATE_START_PENDING;
+        drop(hart);
+        msip::set_ipi(hartid);
+        SbiRet::ok(0)
+    }
+
+    fn hart_stop(&mut self, hartid: usize) -> SbiRet {
+        let mut hart = HARTS[hartid].lock();
+        if hart.state != HART_STATE_STARTED {
+            return SbiRet { error: SBI_ERR_FAILED, value: 0 };
+        }
+        // 真正的停止发生在 ecall 返回之后，见 pending_transition
+        hart.state = HART_STATE_STOP_PENDING;
+        SbiRet::ok(0)
+    }
+
+    fn hart_get_status(&self, hartid: usize) -> SbiRet {
+        if !is_online(hartid) {
+            return SbiRet { error: SBI_ERR_INVALID_PARAM, value: 0 };
+        }
+        SbiRet::ok(HARTS[hartid].lock().state)
+    }
+
+    fn hart_suspend(&mut self, suspend_type: u32, resume_addr: usize, opaque: usize) -> SbiRet {
+        let retentive = match suspend_type {
+            SUSPEND_TYPE_RETENTIVE => true,
+            SUSPEND_TYPE_NON_RETENTIVE => false,
+            // 保留的挂起类型
+            t if t < SUSPEND_TYPE_PLATFORM_RETENTIVE
+                || (t > SUSPEND_TYPE_NON_RETENTIVE && t < SUSPEND_TYPE_PLATFORM_NON_RETENTIVE) => {
+                return SbiRet { error: SBI_ERR_INVALID_PARAM, value: 0 };
+            }
+            // 平台相关的挂起类型，哪吒上没有
+            _ => return SbiRet::not_supported(),
+        };
+        let hartid = riscv::register::mhartid::read();
+        let mut hart = HARTS[hartid].lock();
+        hart.retentive = retentive;
+        if !retentive {
+            hart.start_addr = resume_addr;
+            hart.opaque = opaque;
+        }
+        hart.state = HART_STATE_SUSPEND_PENDING;
+        SbiRet::ok(0)
+    }
+}
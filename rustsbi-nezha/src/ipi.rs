@@ -0,0 +1,193 @@
+use core::{iter::StepBy, ops::Range, sync::atomic::{AtomicUsize, Ordering}};
+use rustsbi::{HartMask, SbiRet};
+use spin::Mutex;
+
+use crate::{hal::msip, hsm, pmu::{self, FirmwareEvent}, NUM_HARTS};
+
+// 每个核的远程 fence 队列长度，队列满时退化为一次完整的刷新。
+// 发送的核要等所有目标核完成之后才返回，所以每个发送者在一个队列中最多只有一个请求
+const RFENCE_QUEUE_LEN: usize = 8;
+// 超过这个页数的 sfence.vma 请求直接刷新整个 TLB
+const SFENCE_VMA_PAGE_LIMIT: usize = 64;
+const PAGE_SIZE: usize = 4096;
+
+#[derive(Clone, Copy, Debug)]
+enum RfenceRequest {
+    FenceI,
+    SfenceVma { start_addr: usize, size: usize },
+    SfenceVmaAsid { start_addr: usize, size: usize, asid: usize },
+}
+
+impl RfenceRequest {
+    fn sent_event(&self) -> FirmwareEvent {
+        match self {
+            RfenceRequest::FenceI => FirmwareEvent::FenceISent,
+            RfenceRequest::SfenceVma { .. } => FirmwareEvent::SfenceVmaSent,
+            RfenceRequest::SfenceVmaAsid { .. } => FirmwareEvent::SfenceVmaAsidSent,
+        }
+    }
+
+    fn received_event(&self) -> FirmwareEvent {
+        match self {
+            RfenceRequest::FenceI => FirmwareEvent::FenceIReceived,
+            RfenceRequest::SfenceVma { .. } => FirmwareEvent::SfenceVmaReceived,
+            RfenceRequest::SfenceVmaAsid { .. } => FirmwareEvent::SfenceVmaAsidReceived,
+        }
+    }
+}
+
+// 通过 CLINT MSIP 发给某个核的消息
+struct Mailbox {
+    // 请求和发送请求的核
+    fences: [Option<(RfenceRequest, usize)>; RFENCE_QUEUE_LEN],
+    len: usize,
+    // 队列满时放不下的请求的发送者，按位表示
+    overflow: usize,
+    supervisor_soft: bool,
+}
+
+impl Mailbox {
+    fn push(&mut self, request: RfenceRequest, sender: usize) {
+        if self.len == RFENCE_QUEUE_LEN {
+            self.overflow |= 1 << sender;
+        } else {
+            self.fences[self.len] = Some((request, sender));
+            self.len += 1;
+        }
+    }
+}
+
+const MAILBOX_INIT: Mutex<Mailbox> = Mutex::new(Mailbox {
+    fences: [None; RFENCE_QUEUE_LEN],
+    len: 0,
+    overflow: 0,
+    supervisor_soft: false,
+});
+
+static MAILBOXES: [Mutex<Mailbox>; NUM_HARTS] = [MAILBOX_INIT; NUM_HARTS];
+
+// 每个核发出的远程 fence 中还没有完成的目标核个数
+const PENDING_INIT: AtomicUsize = AtomicUsize::new(0);
+static PENDING: [AtomicUsize; NUM_HARTS] = [PENDING_INIT; NUM_HARTS];
+
+/// 向目标核发送 S 态软件中断，由目标核的 MachineSoft 处理转发
+pub fn send_supervisor_soft(hartid: usize) {
+    MAILBOXES[hartid].lock().supervisor_soft = true;
+    msip::set_ipi(hartid);
+}
+
+/// 在 MachineSoft 中断中调用：执行所有排队的远程 fence。
+///
+/// 返回值表示是否还需要向 S 态转发软件中断。
+pub fn handle_machine_soft(hartid: usize) -> bool {
+    // 先清除 MSIP 再取队列，之后到达的请求会重新触发中断
+    msip::clear_ipi(hartid);
+    handle_rfences(hartid);
+    let mut mailbox = MAILBOXES[hartid].lock();
+    let supervisor_soft = mailbox.supervisor_soft;
+    mailbox.supervisor_soft = false;
+    supervisor_soft
+}
+
+/// 执行发给这个核的远程 fence，并通知发送的核；发给 S 态的软件中断留在队列中
+pub fn handle_rfences(hartid: usize) {
+    let mut mailbox = MAILBOXES[hartid].lock();
+    let fences = mailbox.fences;
+    let len = mailbox.len;
+    let overflow = mailbox.overflow;
+    mailbox.len = 0;
+    mailbox.overflow = 0;
+    drop(mailbox);
+    if overflow != 0 {
+        fence_i();
+        unsafe { riscv::asm::sfence_vma_all() };
+        for sender in (0..NUM_HARTS).filter(|sender| overflow & (1 << sender) != 0) {
+            PENDING[sender].fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+    for &(request, sender) in fences[..len].iter().flatten() {
+        // 有请求放不下时已经刷新了全部，这里只需要通知发送者
+        if overflow == 0 {
+            do_rfence(request);
+        } else {
+            pmu::record(request.received_event());
+        }
+        PENDING[sender].fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// 规范要求所有目标核完成 fence 之后才返回。等待时处理发给自己的请求，两个核互相发送时不会死锁
+fn send_rfence(hart_mask: HartMask, request: RfenceRequest) -> SbiRet {
+    let current = riscv::register::mhartid::read();
+    for hartid in 0..NUM_HARTS {
+        // 没有进入过固件的核不会处理请求
+        if !hart_mask.has_bit(hartid) || !hsm::is_online(hartid) {
+            continue;
+        }
+        pmu::record(request.sent_event());
+        if hartid == current {
+            do_rfence(request);
+        } else {
+            PENDING[current].fetch_add(1, Ordering::AcqRel);
+            MAILBOXES[hartid].lock().push(request, current);
+            msip::set_ipi(hartid);
+        }
+    }
+    while PENDING[current].load(Ordering::Acquire) != 0 {
+        handle_rfences(current);
+        core::hint::spin_loop();
+    }
+    SbiRet::ok(0)
+}
+
+fn do_rfence(request: RfenceRequest) {
+    pmu::record(request.received_event());
+    match request {
+        RfenceRequest::FenceI => fence_i(),
+        RfenceRequest::SfenceVma { start_addr, size } => match flush_pages(start_addr, size) {
+            Some(pages) => for addr in pages {
+                unsafe { asm!("sfence.vma {}, zero", in(reg) addr) };
+            },
+            None => unsafe { riscv::asm::sfence_vma_all() },
+        },
+        RfenceRequest::SfenceVmaAsid { start_addr, size, asid } => match flush_pages(start_addr, size) {
+            Some(pages) => for addr in pages {
+                unsafe { riscv::asm::sfence_vma(asid, addr) };
+            },
+            None => unsafe { asm!("sfence.vma zero, {}", in(reg) asid) },
+        },
+    }
+}
+
+// 要刷新的各页的起始地址，从 start_addr 所在的页开始，到 start_addr + size 为止。
+// 规范规定 start_addr 和 size 都为 0，或 size 为 usize::MAX 时刷新全部地址；
+// 页数太多或地址溢出时也刷新全部地址，返回 None
+#[inline]
+fn flush_pages(start_addr: usize, size: usize) -> Option<StepBy<Range<usize>>> {
+    if (start_addr == 0 && size == 0) || size == usize::MAX || size / PAGE_SIZE > SFENCE_VMA_PAGE_LIMIT {
+        return None;
+    }
+    let end = start_addr.checked_add(size)?;
+    Some(((start_addr & !(PAGE_SIZE - 1))..end).step_by(PAGE_SIZE))
+}
+
+#[inline]
+fn fence_i() {
+    unsafe { asm!("fence.i") };
+}
+
+pub struct Rfence;
+
+impl rustsbi::Rfence for Rfence {
+    fn remote_fence_i(&mut self, hart_mask: HartMask) -> SbiRet {
+        send_rfence(hart_mask, RfenceRequest::FenceI)
+    }
+
+    fn remote_sfence_vma(&mut self, hart_mask: HartMask, start_addr: usize, size: usize) -> SbiRet {
+        send_rfence(hart_mask, RfenceRequest::SfenceVma { start_addr, size })
+    }
+
+    fn remote_sfence_vma_asid(&mut self, hart_mask: HartMask, start_addr: usize, size: usize, asid: usize) -> SbiRet {
+        send_rfence(hart_mask, RfenceRequest::SfenceVmaAsid { start_addr, size, asid })
+    }
+}
@@ -0,0 +1,124 @@
+//! RISC-V Linux 内核的 Image 格式，见 Linux 源码中的 Documentation/riscv/boot-image-header.rst
+use core::fmt;
+use boot_config::{DEVICE_TREE_ADDRESS, DEVICE_TREE_MAX_SIZE, FIRMWARE_BASE, FIRMWARE_SIZE};
+
+use crate::platform::{CurrentPlatform, Platform};
+
+const DRAM_BASE: usize = CurrentPlatform::DRAM_BASE;
+const DRAM_MAX_SIZE: usize = CurrentPlatform::DRAM_MAX_SIZE;
+
+const HEADER_SIZE: usize = 64;
+const OFFSET_TEXT_OFFSET: usize = 8;
+const OFFSET_IMAGE_SIZE: usize = 16;
+const OFFSET_MAGIC2: usize = 56;
+const MAGIC2: [u8; 4] = *b"RSC\x05";
+
+// RV64 内核要求加载到 2MiB 对齐的地址加上 text_offset
+const KERNEL_ALIGN: usize = 0x20_0000;
+
+// 固件自身占用的内存，内核不能放在这里
+const FIRMWARE_END: usize = FIRMWARE_BASE + FIRMWARE_SIZE;
+
+/// Image 头部中和加载有关的字段
+#[derive(Debug, Clone, Copy)]
+pub struct LinuxImage {
+    text_offset: usize,
+    /// 内核运行时占用的内存大小，包括 .bss
+    image_size: usize,
+}
+
+#[derive(Debug)]
+pub enum LinuxImageError {
+    NoSpace { size: usize },
+    Overlaps { name: &'static str, start: usize, end: usize },
+}
+
+impl fmt::Display for LinuxImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinuxImageError::NoSpace { size } => write!(f, "no room in DRAM for kernel of size {:#x}", size),
+            LinuxImageError::Overlaps { name, start, end } =>
+                write!(f, "kernel memory overlaps {} at {:#x}..{:#x}", name, start, end),
+        }
+    }
+}
+
+/// 下一阶段是 Linux 的 Image 时把它移动到要求的地址，返回新的入口地址；否则原样返回 entry。
+///
+/// 按照 Linux 的启动约定，进入内核时 a0 为当前的 hartid，a1 为设备树的物理地址，
+/// 由 execute::execute_supervisor 的参数给出。
+pub fn prepare(entry: usize, size: usize, initrd: Option<(usize, usize)>) -> usize {
+    let payload = unsafe { core::slice::from_raw_parts(entry as *const u8, size) };
+    let image = match probe(payload) {
+        Some(image) => image,
+        None => return entry,
+    };
+    match image.relocate(payload, initrd) {
+        Ok(entry) => entry,
+        Err(e) => {
+            error!("cannot load Linux Image: {}, refusing to boot", e);
+            loop {
+                unsafe { riscv::asm::wfi() };
+            }
+        }
+    }
+}
+
+/// 检查下一阶段是不是 Linux 的 Image
+pub fn probe(payload: &[u8]) -> Option<LinuxImage> {
+    if payload.len() < HEADER_SIZE || payload[OFFSET_MAGIC2..OFFSET_MAGIC2 + 4] != MAGIC2 {
+        return None;
+    }
+    let text_offset = read_u64(payload, OFFSET_TEXT_OFFSET) as usize;
+    // 早期的内核 image_size 为 0，这时只能按文件大小估计
+    let image_size = match read_u64(payload, OFFSET_IMAGE_SIZE) as usize {
+        0 => payload.len(),
+        size => size.max(payload.len()),
+    };
+    Some(LinuxImage { text_offset, image_size })
+}
+
+impl LinuxImage {
+    /// 把内核移动到它要求的地址，返回入口地址。
+    ///
+    /// 内核占用的内存不能和固件、修正后的设备树以及 initrd 重叠；移动之后原来的位置可以被覆盖，
+    /// 所以要在设备树复制走之后再调用。
+    pub fn relocate(&self, payload: &[u8], initrd: Option<(usize, usize)>) -> Result<usize, LinuxImageError> {
+        let load_address = self.load_address().ok_or(LinuxImageError::NoSpace { size: self.image_size })?;
+        let end = load_address + self.image_size;
+        let mut reserved = [("device tree", DEVICE_TREE_ADDRESS, DEVICE_TREE_ADDRESS + DEVICE_TREE_MAX_SIZE), ("initrd", 0, 0)];
+        if let Some((start, end)) = initrd {
+            reserved[1] = ("initrd", start, end);
+        }
+        for &(name, start, region_end) in reserved.iter() {
+            if start < end && load_address < region_end {
+                return Err(LinuxImageError::Overlaps { name, start, end: region_end });
+            }
+        }
+        info!("Linux Image: text_offset {:#x}, image_size {:#x}, load at {:#x}", self.text_offset, self.image_size, load_address);
+        if payload.as_ptr() as usize != load_address {
+            unsafe { core::ptr::copy(payload.as_ptr(), load_address as *mut u8, payload.len()) };
+            unsafe { asm!("fence.i") };
+        }
+        Ok(load_address)
+    }
+
+    // 从 DRAM 开头起，第一个能放下内核又不和固件重叠的位置
+    fn load_address(&self) -> Option<usize> {
+        let mut base = (DRAM_BASE + KERNEL_ALIGN - 1) & !(KERNEL_ALIGN - 1);
+        while base.checked_add(self.text_offset)? < FIRMWARE_END {
+            base += KERNEL_ALIGN;
+        }
+        let load_address = base + self.text_offset;
+        if load_address.checked_add(self.image_size)? > DRAM_BASE + DRAM_MAX_SIZE {
+            return None;
+        }
+        Some(load_address)
+    }
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(buf)
+}
@@ -0,0 +1,89 @@
+//! 固件的日志输出，每条日志带有 hartid 和 time 寄存器的时间。
+//!
+//! 比编译时配置的级别更详细的日志在编译时去掉；运行时的级别可以通过厂商扩展调低或恢复，
+//! 但不能超过编译时的级别。各个核的日志整行输出，不会交错。
+use core::{fmt, sync::atomic::{AtomicUsize, Ordering}};
+
+use crate::{config::{CONFIG, LogLevel}, platform::{CurrentPlatform, Platform}};
+
+const LEVELS: [LogLevel; 6] = [LogLevel::Off, LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug, LogLevel::Trace];
+
+// 运行时的日志级别。放在 .data 中，不会被 init_bss 清零
+#[link_section = ".data"]
+static LEVEL: AtomicUsize = AtomicUsize::new(CONFIG.log_level as usize);
+// 正在输出日志的核，没有核在输出时是 usize::MAX
+#[link_section = ".data"]
+static OWNER: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+macro_rules! log {
+    ($level: expr, $($arg: tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::print($level, format_args!($($arg)+))
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg: tt)+) => { log!($crate::config::LogLevel::Error, $($arg)+) };
+}
+
+macro_rules! warn {
+    ($($arg: tt)+) => { log!($crate::config::LogLevel::Warn, $($arg)+) };
+}
+
+macro_rules! info {
+    ($($arg: tt)+) => { log!($crate::config::LogLevel::Info, $($arg)+) };
+}
+
+macro_rules! debug {
+    ($($arg: tt)+) => { log!($crate::config::LogLevel::Debug, $($arg)+) };
+}
+
+macro_rules! trace {
+    ($($arg: tt)+) => { log!($crate::config::LogLevel::Trace, $($arg)+) };
+}
+
+/// 这个级别的日志是否输出
+#[inline]
+pub fn enabled(level: LogLevel) -> bool {
+    CONFIG.enabled(level) && level as usize <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// 当前的运行时级别
+pub fn level() -> LogLevel {
+    LEVELS[LEVEL.load(Ordering::Relaxed)]
+}
+
+/// 设置运行时级别，超过编译时级别的部分无效；返回实际生效的级别，level 不是合法的级别时返回 None
+pub fn set_level(level: usize) -> Option<LogLevel> {
+    let level = (*LEVELS.get(level)?).min(CONFIG.log_level);
+    LEVEL.store(level as usize, Ordering::Relaxed);
+    Some(level)
+}
+
+/// 输出一条日志，不检查级别
+pub fn print(level: LogLevel, args: fmt::Arguments) {
+    let hartid = riscv::register::mhartid::read();
+    let time = crate::hal::clint::mtime::read();
+    let seconds = time / CurrentPlatform::TIMEBASE_FREQUENCY;
+    let micros = time % CurrentPlatform::TIMEBASE_FREQUENCY * 1_000_000 / CurrentPlatform::TIMEBASE_FREQUENCY;
+    let name = match level {
+        LogLevel::Off => "",
+        LogLevel::Error => "ERROR",
+        LogLevel::Warn => "WARN",
+        LogLevel::Info => "INFO",
+        LogLevel::Debug => "DEBUG",
+        LogLevel::Trace => "TRACE",
+    };
+    // 同一个核重入时（比如输出时 panic）直接输出，不等待自己
+    let reentrant = OWNER.load(Ordering::Acquire) == hartid;
+    if !reentrant {
+        while OWNER.compare_exchange_weak(usize::MAX, hartid, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            core::hint::spin_loop();
+        }
+    }
+    rustsbi::println!("[rustsbi {:>5}.{:06} hart {}] {:<5} {}", seconds, micros, hartid, name, args);
+    if !reentrant {
+        OWNER.store(usize::MAX, Ordering::Release);
+    }
+}
@@ -4,53 +4,106 @@
 #![feature(generator_trait)]
 #![feature(naked_functions)]
 #![feature(default_alloc_error_handler)]
+mod config;
+#[macro_use]
+mod log;
 mod hal;
 mod feature;
 mod runtime;
 mod peripheral;
 mod execute;
 mod hart_csr_utils;
-use core::{panic::PanicInfo};
+mod hsm;
+mod ipi;
+mod device_tree;
+mod boot_image;
+mod linux_image;
+mod elf_loader;
+mod platform;
+mod stats;
+mod pmu;
+mod dbcn;
+mod console;
+mod vendor;
+use core::{panic::PanicInfo, sync::atomic::{AtomicBool, Ordering}};
 use buddy_system_allocator::LockedHeap;
-use rustsbi::println;
 
-use crate::{hal::write_reg, hart_csr_utils::print_hart_pmp};
+use boot_config::{FIRMWARE_BASE, FIRMWARE_SIZE, IMAGE_HEADER_OFFSET};
+use crate::{config::{CONFIG, LogLevel}, hart_csr_utils::print_hart_pmp, platform::{CurrentPlatform, Platform}};
 extern crate alloc;
 extern crate bitflags;
-const PER_HART_STACK_SIZE: usize = 8 * 1024; // 8KiB
-const SBI_STACK_SIZE: usize = 2 * PER_HART_STACK_SIZE;
+const NUM_HARTS: usize = match CONFIG.harts {
+    Some(harts) => harts,
+    None => CurrentPlatform::MAX_HARTS,
+};
+const PER_HART_STACK_SIZE: usize = CONFIG.per_hart_stack_size;
+const SBI_STACK_SIZE: usize = NUM_HARTS * PER_HART_STACK_SIZE;
 #[link_section = ".bss.uninit"]
 static mut SBI_STACK: [u8; SBI_STACK_SIZE] = [0; SBI_STACK_SIZE];
 
-const SBI_HEAP_SIZE: usize = 8 * 1024; // 8KiB
+const SBI_HEAP_SIZE: usize = CONFIG.heap_size;
 #[link_section = ".bss.uninit"]
 static mut HEAP_SPACE: [u8; SBI_HEAP_SIZE] = [0; SBI_HEAP_SIZE];
 #[global_allocator]
 static SBI_HEAP: LockedHeap<32> = LockedHeap::empty();
-static DEVICE_TREE_BINARY: &[u8] = include_bytes!("../sunxi.dtb");
-extern "C" fn rust_main() -> ! {
+// build.rs 从 dts 目录中的源文件编译
+static DEVICE_TREE_BINARY: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/device-tree.dtb"));
+// 放在 .data 中，不会被 init_bss 清零
+#[link_section = ".data"]
+static BSS_READY: AtomicBool = AtomicBool::new(false);
+// a0 是 entry 写入的 hartid，a1 保留上一阶段传入的值，可能是设备树的地址
+extern "C" fn rust_main(_hartid: usize, opaque: usize) -> ! {
     let hartid = riscv::register::mhartid::read();
     if hartid == 0 {
         init_bss();
+        BSS_READY.store(true, Ordering::Release);
+    } else {
+        // QEMU 等平台上所有的核同时启动，要等启动核清零 .bss 之后才能使用其中的数据
+        while !BSS_READY.load(Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
     }
+    hsm::mark_online(hartid);
     init_pmp();
     runtime::init();
+    pmu::init_hart();
     if hartid == 0 {
+        CurrentPlatform::init_console();
         init_heap();
-        init_plic(); 
+        CurrentPlatform::init_plic();
         peripheral::init_peripheral();
-        println!("[rustsbi] RustSBI version {}", rustsbi::VERSION);
-        println!("{}", rustsbi::LOGO);
-        println!("[rustsbi] Platform Name: {}","T-HEAD Xuantie Platform");
-        println!("[rustsbi] Implementation: RustSBI-NeZha Version {}", env!("CARGO_PKG_VERSION"));   
+        info!("RustSBI version {}", rustsbi::VERSION);
+        for line in rustsbi::LOGO.lines() {
+            info!("{}", line);
+        }
+        info!("Platform Name: {}", CurrentPlatform::NAME);
+        info!("Implementation: RustSBI-NeZha Version {}", env!("CARGO_PKG_VERSION"));
+        check_firmware_layout();
     }
     delegate_interrupt_exception();
     if hartid == 0 {
         hart_csr_utils::print_hart_csrs();
-        println!("[rustsbi] enter supervisor 0x40020000");
+        // 从核可能比启动核晚进入固件。设备树中禁用哪些核、HSM 能启动哪些核，都要在这之后才能确定
+        hsm::wait_for_online_harts(CurrentPlatform::TIMEBASE_FREQUENCY / 100);
+        let image = boot_image::load();
+        // 合并镜像中附带的设备树优先于内置的设备树
+        let default_dtb = image.device_tree.unwrap_or(DEVICE_TREE_BINARY);
+        let dtb = device_tree::prepare(device_tree::select(opaque, default_dtb), image.initrd);
+        // 加载 ELF 或移动内核可能覆盖镜像中附带的设备树，所以放在设备树复制走之后
+        let entry = match elf_loader::prepare(image.entry, image.payload_size, image.initrd) {
+            Some(entry) => entry,
+            None => linux_image::prepare(image.entry, image.payload_size, image.initrd),
+        };
+        info!("enter {:?} {:#x}", boot_config::NEXT_STAGE_PRIVILEGE, entry);
         print_hart_pmp();
+        hsm::set_boot_hart_started(hartid);
+        console::init_rx_interrupt();
+        execute::execute_supervisor(entry, hartid, dtb)
+    } else {
+        // 从核停在 M 态，等待 S 态通过 HSM 扩展启动
+        let (start_addr, opaque) = hsm::wait_for_start(hartid);
+        execute::execute_supervisor(start_addr, hartid, opaque)
     }
-    execute::execute_supervisor(0x4002_0000, hartid,DEVICE_TREE_BINARY.as_ptr() as usize)
 }
 
 fn init_bss() {
@@ -67,24 +120,32 @@ fn init_bss() {
     }
 }
 
+// 固件镜像（包括 .bss 中的栈和堆）不能和合并镜像的头部重叠
+fn check_firmware_layout() {
+    extern "C" {
+        static stext: u32;
+        static ebss: u32;
+    }
+    let (start, end) = unsafe { (&stext as *const _ as usize, &ebss as *const _ as usize) };
+    let header = FIRMWARE_BASE + IMAGE_HEADER_OFFSET;
+    if end > header {
+        error!("firmware image {:#x}..{:#x} overlaps boot image header at {:#x}, refusing to boot", start, end, header);
+        loop {
+            unsafe { riscv::asm::wfi() };
+        }
+    }
+}
+
+// 固件所在的内存对 S 态不可访问，其余的 DRAM 和它下面的外设可以读写执行
 fn init_pmp(){
     use riscv::register::*;
-    let cfg = 0b000011110000111100001111usize;
+    let cfg = 0b000011110000100000001111usize;
     pmpcfg0::write(0);
     pmpcfg2::write(0);
     pmpcfg0::write(cfg);
-    pmpaddr0::write(0x40000000usize >> 2);
-    pmpaddr1::write(0x40200000usize >> 2);
-    pmpaddr2::write(0x80000000usize >> 2);
-    pmpaddr3::write(0xc0000000usize >> 2);
-}
-
-fn init_plic(){
-    unsafe{
-        let mut addr: usize;
-        asm!("csrr {}, 0xfc1", out(reg) addr);
-        write_reg(addr, 0x001ffffc, 0x1)
-    }
+    pmpaddr0::write(FIRMWARE_BASE >> 2);
+    pmpaddr1::write((FIRMWARE_BASE + FIRMWARE_SIZE) >> 2);
+    pmpaddr2::write((CurrentPlatform::DRAM_BASE + CurrentPlatform::DRAM_MAX_SIZE) >> 2);
 }
 
 fn delegate_interrupt_exception() {
@@ -96,6 +157,7 @@ fn delegate_interrupt_exception() {
         medeleg::set_instruction_misaligned();
         medeleg::set_breakpoint();
         medeleg::set_user_env_call();
+        // 非对齐读写不委托，由 feature::emulate_misaligned 在 M 层模拟
         mie::set_msoft();
     }
 }
@@ -111,13 +173,13 @@ fn init_heap() {
 #[cfg_attr(not(test), panic_handler)]
 #[allow(unused)]
 fn panic(info: &PanicInfo) -> ! {
-    let hart_id = riscv::register::mhartid::read();
-    // 输出的信息大概是“[rustsbi-panic] hart 0 panicked at ...”
-    println!("[rustsbi-panic] hart {} {}", hart_id, info);
-    println!("[rustsbi-panic] system shutdown scheduled due to RustSBI panic");
+    // 不受日志级别限制，输出的信息大概是“[rustsbi ... hart 0] ERROR panicked at ...”
+    log::print(LogLevel::Error, format_args!("{}", info));
+    stats::dump();
+    log::print(LogLevel::Error, format_args!("system reboot scheduled due to RustSBI panic"));
     use rustsbi::Reset;
     peripheral::Reset.system_reset(
-        rustsbi::reset::RESET_TYPE_SHUTDOWN,
+        rustsbi::reset::RESET_TYPE_COLD_REBOOT,
         rustsbi::reset::RESET_REASON_SYSTEM_FAILURE
     );
     loop { }
@@ -128,19 +190,28 @@ fn panic(info: &PanicInfo) -> ! {
 #[export_name = "_start"]
 unsafe extern "C" fn entry() -> ! {
     asm!(
+    // 0. park harts the firmware has no stack for
+    "
+    csrr    a0, mhartid
+    li      t0, {num_harts}
+    bltu    a0, t0, 2f
+1:  wfi
+    j       1b
+2:
+    ",
     // 1. set sp
     // sp = bootstack + (hartid + 1) * HART_STACK_SIZE
     "
     la      sp, {stack}
     li      t0, {per_hart_stack_size}
-    csrr    a0, mhartid
     addi    t1, a0, 1
-1:  add     sp, sp, t0
+3:  add     sp, sp, t0
     addi    t1, t1, -1
-    bnez    t1, 1b
+    bnez    t1, 3b
     ",
     // 2. jump to rust_main (absolute address)
     "j      {rust_main}", 
+    num_harts = const NUM_HARTS,
     per_hart_stack_size = const PER_HART_STACK_SIZE,
     stack = sym SBI_STACK, 
     rust_main = sym rust_main,
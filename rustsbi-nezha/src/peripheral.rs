@@ -1,25 +1,32 @@
 use riscv::register::mip;
-use rustsbi::println;
-
-use crate::hal::{Serial, msip, pac_encoding::UART0_BASE};
+use crate::{NUM_HARTS, console::Console, hsm::Hsm, ipi::{self, Rfence}, platform::{CurrentPlatform, Platform}, pmu::{self, FirmwareEvent}};
 
+// 可选的扩展由 cargo 特性控制，没有注册的扩展的代码会在链接时去掉
 pub fn init_peripheral() {
-    rustsbi::legacy_stdio::init_legacy_stdio_embedded_hal(Serial::new(UART0_BASE));
+    rustsbi::legacy_stdio::init_legacy_stdio_embedded_hal(Console);
     rustsbi::init_timer(Timer);
-    rustsbi::init_reset(Reset);
     rustsbi::init_ipi(Ipi);
+    if cfg!(feature = "sbi-srst") {
+        rustsbi::init_reset(Reset);
+    }
+    if cfg!(feature = "sbi-hsm") {
+        rustsbi::init_hsm(Hsm);
+    }
+    if cfg!(feature = "sbi-rfence") {
+        rustsbi::init_rfence(Rfence);
+    }
 }
 struct Ipi;
 
 impl rustsbi::Ipi for Ipi {
     fn max_hart_id(&self) -> usize {
-        1
+        NUM_HARTS - 1
     }
     fn send_ipi_many(&mut self, hart_mask: rustsbi::HartMask) -> rustsbi::SbiRet {
         for i in 0..=self.max_hart_id() {
             if hart_mask.has_bit(i) {
-                msip::set_ipi(i);
-                msip::clear_ipi(i);
+                ipi::send_supervisor_soft(i);
+                pmu::record(FirmwareEvent::IpiSent);
             }
         }
         rustsbi::SbiRet::ok(0)
@@ -29,9 +36,9 @@ struct Timer;
 impl rustsbi::Timer for Timer {
     fn set_timer(&mut self, stime_value: u64) {
         // This function must clear the pending timer interrupt bit as well.
-        println!("[rustsbi] setTimer");
         use crate::hal::clint::mtimecmp;
-        mtimecmp::write(stime_value);
+        pmu::record(FirmwareEvent::SetTimer);
+        mtimecmp::write(riscv::register::mhartid::read(), stime_value);
         unsafe { 
             mip::clear_mtimer();
             mip::set_mtimer() 
@@ -40,9 +47,29 @@ impl rustsbi::Timer for Timer {
 }
 pub struct Reset;
 
+const SBI_ERR_INVALID_PARAM: usize = -3isize as usize;
+
 impl rustsbi::Reset for Reset {
     fn system_reset(&self, reset_type: usize, reset_reason: usize) -> rustsbi::SbiRet {
-        println!("[rustsbi] reset triggered! todo: shutdown all harts on Nezha; program halt. Type: {}, reason: {}", reset_type, reset_reason);
-        loop {}
+        use rustsbi::reset::{RESET_REASON_SYSTEM_FAILURE, RESET_TYPE_COLD_REBOOT, RESET_TYPE_SHUTDOWN, RESET_TYPE_WARM_REBOOT};
+        // 0x2..=0xDFFFFFFF 是保留的复位原因
+        if reset_reason > RESET_REASON_SYSTEM_FAILURE && reset_reason < 0xE000_0000 {
+            return rustsbi::SbiRet { error: SBI_ERR_INVALID_PARAM, value: 0 };
+        }
+        match reset_type {
+            RESET_TYPE_COLD_REBOOT | RESET_TYPE_WARM_REBOOT => {
+                info!("reset triggered! Type: {}, reason: {}", reset_type, reset_reason);
+                CurrentPlatform::reboot()
+            }
+            RESET_TYPE_SHUTDOWN => {
+                info!("shutdown triggered! Reason: {}", reset_reason);
+                CurrentPlatform::shutdown(reset_reason == RESET_REASON_SYSTEM_FAILURE);
+                // 平台不支持关机
+                rustsbi::SbiRet::not_supported()
+            }
+            // 0x3..=0xEFFFFFFF 是保留的复位类型
+            t if t < 0xF000_0000 => rustsbi::SbiRet { error: SBI_ERR_INVALID_PARAM, value: 0 },
+            _ => rustsbi::SbiRet::not_supported(),
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,76 @@
+use crate::hal::{
+    dram::probe_dram_size, pac_encoding::{CLINT_BASE, DRAM_BASE, DRAM_MAX_SIZE, PLIC_BASE, UART0_BASE, WDT_BASE},
+    write_reg, Serial, Watchdog,
+};
+use crate::config::CONFIG;
+use super::{Platform, PmuEvent};
+
+/// 全志 D1（哪吒开发板），玄铁 C906
+pub struct D1;
+
+impl Platform for D1 {
+    const NAME: &'static str = "T-HEAD Xuantie Platform";
+    const MAX_HARTS: usize = 2;
+    const DRAM_BASE: usize = DRAM_BASE;
+    const DRAM_MAX_SIZE: usize = DRAM_MAX_SIZE;
+    const CLINT_BASE: usize = CLINT_BASE;
+    const CLINT_LAYOUT: clint::Layout = clint::Layout::THEAD;
+    const PLIC_BASE: usize = PLIC_BASE;
+    const CONSOLE_IRQ: u32 = 18;
+    const TIMEBASE_FREQUENCY: u64 = 24_000_000;
+    // C906 的 mhpmcounter3 到 mhpmcounter17 可以计数，玄铁的事件 n 固定由 mhpmcounter n+2 计数
+    const PMU_HPM_COUNTERS: usize = 15;
+    const PMU_EVENTS: &'static [PmuEvent] = &[
+        // CACHE_REFERENCES、CACHE_MISSES：L1 ICache 访问和缺失
+        PmuEvent { event_idx: 0x00003, selector: 0x01, counters: 1 << 3 },
+        PmuEvent { event_idx: 0x00004, selector: 0x02, counters: 1 << 4 },
+        // BRANCH_INSTRUCTIONS、BRANCH_MISSES：条件分支指令和预测失败
+        PmuEvent { event_idx: 0x00005, selector: 0x07, counters: 1 << 9 },
+        PmuEvent { event_idx: 0x00006, selector: 0x06, counters: 1 << 8 },
+        // L1D 读访问、读缺失、写访问、写缺失
+        PmuEvent { event_idx: 0x10000, selector: 0x0c, counters: 1 << 14 },
+        PmuEvent { event_idx: 0x10001, selector: 0x0d, counters: 1 << 15 },
+        PmuEvent { event_idx: 0x10002, selector: 0x0e, counters: 1 << 16 },
+        PmuEvent { event_idx: 0x10003, selector: 0x0f, counters: 1 << 17 },
+        // L1I 读访问、读缺失
+        PmuEvent { event_idx: 0x10008, selector: 0x01, counters: 1 << 3 },
+        PmuEvent { event_idx: 0x10009, selector: 0x02, counters: 1 << 4 },
+        // DTLB、ITLB 读缺失：D-UTLB 和 I-UTLB 缺失
+        PmuEvent { event_idx: 0x10019, selector: 0x04, counters: 1 << 6 },
+        PmuEvent { event_idx: 0x10021, selector: 0x03, counters: 1 << 5 },
+    ];
+    const PMU_RAW_COUNTERS: u32 = 0x0003_fff8;
+
+    type Console = Serial;
+
+    // 按配置的波特率重新初始化 UART0；APB1 的时钟得不到这个波特率时保留 boot0 的设置
+    fn init_console() {
+        if let Err(e) = Serial::init(0, CONFIG.baud_rate) {
+            warn!("{}, keeping the serial settings from boot0", e);
+        }
+    }
+
+    fn console() -> Serial {
+        Serial::new(UART0_BASE)
+    }
+
+    fn init_plic() {
+        unsafe {
+            // mapbaddr 中是 PLIC 的基地址；打开 S 态访问 PLIC 的权限
+            let mut addr: usize;
+            asm!("csrr {}, 0xfc1", out(reg) addr);
+            write_reg(addr, 0x001ffffc, 0x1)
+        }
+    }
+
+    fn dram_size() -> Option<usize> {
+        Some(probe_dram_size(DRAM_BASE, DRAM_MAX_SIZE))
+    }
+
+    fn reboot() -> ! {
+        Watchdog::new(WDT_BASE).reset_system()
+    }
+
+    // 哪吒没有可以由软件控制的电源，无法关机
+    fn shutdown(_system_failure: bool) {}
+}
@@ -0,0 +1,73 @@
+//! 平台相关的部分：控制台、CLINT、PLIC、复位和内存布局。
+//!
+//! 目标平台由 cargo 特性选择，其余代码通过 CurrentPlatform 使用当前平台。
+use core::convert::Infallible;
+use embedded_hal::serial::{Read, Write};
+
+use crate::hal::InterruptRx;
+
+#[cfg(feature = "d1")]
+mod d1;
+#[cfg(feature = "qemu-virt")]
+mod qemu_virt;
+
+#[cfg(all(feature = "d1", feature = "qemu-virt"))]
+compile_error!("features `d1` and `qemu-virt` cannot be enabled at the same time");
+
+#[cfg(all(feature = "d1", not(feature = "qemu-virt")))]
+pub use d1::D1 as CurrentPlatform;
+#[cfg(all(feature = "qemu-virt", not(feature = "d1")))]
+pub use qemu_virt::QemuVirt as CurrentPlatform;
+
+/// 可以用硬件计数器计数的 SBI PMU 事件
+pub struct PmuEvent {
+    /// SBI PMU 的事件号，类型 0（通用硬件事件）或 1（缓存事件）
+    pub event_idx: usize,
+    /// 写入 mhpmevent 的值
+    pub selector: usize,
+    /// 可以计数这个事件的计数器，第 n 位对应 mhpmcounter n
+    pub counters: u32,
+}
+
+pub trait Platform {
+    /// 启动时打印的平台名称
+    const NAME: &'static str;
+    /// 固件支持的最多的核数，hartid 必须小于这个值
+    const MAX_HARTS: usize;
+    /// DRAM 的起始地址
+    const DRAM_BASE: usize;
+    /// DRAM 可能的最大容量，实际的容量由 dram_size 或设备树给出
+    const DRAM_MAX_SIZE: usize;
+    /// CLINT（或 ACLINT 的 MSWI 和 MTIMER）的基地址，设备树中有 CLINT 节点时以设备树为准
+    const CLINT_BASE: usize;
+    /// CLINT 中各组寄存器的偏移
+    const CLINT_LAYOUT: clint::Layout;
+    /// PLIC 的基地址
+    const PLIC_BASE: usize;
+    /// 控制台串口在 PLIC 上的中断号
+    const CONSOLE_IRQ: u32;
+    /// time 寄存器的频率
+    const TIMEBASE_FREQUENCY: u64;
+    /// 实现了的 mhpmcounter 个数，从 mhpmcounter3 开始
+    const PMU_HPM_COUNTERS: usize;
+    /// 通用硬件事件和缓存事件到 mhpmevent 的映射，cycle 和 instret 不需要列出
+    const PMU_EVENTS: &'static [PmuEvent];
+    /// 原始事件（类型 2）可以使用的计数器，事件数据直接写入 mhpmevent
+    const PMU_RAW_COUNTERS: u32;
+
+    /// 控制台使用的串口
+    type Console: Read<u8, Error = Infallible> + Write<u8, Error = Infallible> + InterruptRx + Send + 'static;
+
+    /// 初始化控制台串口，启动核在第一次输出之前调用
+    fn init_console();
+    /// 控制台串口，可以多次调用，返回的都是同一个串口
+    fn console() -> Self::Console;
+    /// 初始化 PLIC，让 S 态可以使用它
+    fn init_plic();
+    /// 探测实际的 DRAM 容量，不能探测时返回 None，这时使用设备树中的值
+    fn dram_size() -> Option<usize>;
+    /// 复位整个系统
+    fn reboot() -> !;
+    /// 关机，平台不支持关机时直接返回
+    fn shutdown(system_failure: bool);
+}
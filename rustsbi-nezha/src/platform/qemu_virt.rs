@@ -0,0 +1,55 @@
+use crate::hal::{Ns16550a, SifiveTest};
+use super::{Platform, PmuEvent};
+
+const UART0_BASE: usize = 0x1000_0000;
+const TEST_BASE: usize = 0x0010_0000;
+
+/// QEMU 的 virt 机器
+pub struct QemuVirt;
+
+impl Platform for QemuVirt {
+    const NAME: &'static str = "QEMU virt";
+    const MAX_HARTS: usize = 2;
+    const DRAM_BASE: usize = 0x8000_0000;
+    const DRAM_MAX_SIZE: usize = 0x8000_0000;
+    const CLINT_BASE: usize = 0x0200_0000;
+    const CLINT_LAYOUT: clint::Layout = clint::Layout::SIFIVE;
+    const PLIC_BASE: usize = 0x0c00_0000;
+    const CONSOLE_IRQ: u32 = 10;
+    const TIMEBASE_FREQUENCY: u64 = 10_000_000;
+    // QEMU 的 mhpmcounter 不计数，只有 cycle 和 instret
+    const PMU_HPM_COUNTERS: usize = 0;
+    const PMU_EVENTS: &'static [PmuEvent] = &[];
+    const PMU_RAW_COUNTERS: u32 = 0;
+
+    type Console = Ns16550a;
+
+    // QEMU 的 ns16550a 不需要设置波特率
+    fn init_console() {}
+
+    // QEMU 的 ns16550a 寄存器间隔为 1 字节
+    fn console() -> Ns16550a {
+        Ns16550a::new(UART0_BASE, 0)
+    }
+
+    // QEMU 的 PLIC 不需要初始化
+    fn init_plic() {}
+
+    // 超出内存的地址不会回绕而是访问错误，不能探测，使用 QEMU 生成的设备树中的值
+    fn dram_size() -> Option<usize> {
+        None
+    }
+
+    fn reboot() -> ! {
+        SifiveTest::new(TEST_BASE).reset()
+    }
+
+    fn shutdown(system_failure: bool) {
+        let test = SifiveTest::new(TEST_BASE);
+        if system_failure {
+            test.fail(1)
+        } else {
+            test.pass()
+        }
+    }
+}
@@ -0,0 +1,360 @@
+//! SBI PMU 扩展。rustsbi 0.2.0-alpha.3 没有 PMU 的接口，调用在交给 rustsbi 之前处理。
+//!
+//! 计数器编号和 CSR 编号对应：0 是 cycle，1 是 time（不能配置），2 是 instret，3 开始是平台实现的
+//! mhpmcounter，之后是固件计数器。硬件计数器由 mcountinhibit 启停，配置之后打开 mcounteren 中对应的位，
+//! S 态可以直接读取；没有打开的计数器由 feature::emulate_counter_read 模拟读取。
+//! 所有操作都只影响调用的核。
+use rustsbi::SbiRet;
+use spin::Mutex;
+
+use crate::{platform::{CurrentPlatform, Platform}, runtime::SupervisorContext, NUM_HARTS};
+
+pub const EXTENSION_PMU: usize = 0x0050_4d55;
+
+const FUNCTION_NUM_COUNTERS: usize = 0x0;
+const FUNCTION_COUNTER_GET_INFO: usize = 0x1;
+const FUNCTION_COUNTER_CONFIG_MATCHING: usize = 0x2;
+const FUNCTION_COUNTER_START: usize = 0x3;
+const FUNCTION_COUNTER_STOP: usize = 0x4;
+const FUNCTION_COUNTER_FW_READ: usize = 0x5;
+
+const EXTENSION_BASE: usize = 0x10;
+const FUNCTION_BASE_PROBE_EXTENSION: usize = 0x3;
+
+const CONFIG_FLAG_SKIP_MATCH: usize = 1 << 0;
+const CONFIG_FLAG_CLEAR_VALUE: usize = 1 << 1;
+const CONFIG_FLAG_AUTO_START: usize = 1 << 2;
+const START_FLAG_SET_INIT_VALUE: usize = 1 << 0;
+const STOP_FLAG_RESET: usize = 1 << 0;
+
+const EVENT_TYPE_HARDWARE: usize = 0;
+const EVENT_TYPE_CACHE: usize = 1;
+const EVENT_TYPE_RAW: usize = 2;
+const EVENT_TYPE_FIRMWARE: usize = 15;
+const EVENT_CPU_CYCLES: usize = 1;
+const EVENT_INSTRUCTIONS: usize = 2;
+
+const SBI_ERR_INVALID_PARAM: usize = -3isize as usize;
+const SBI_ERR_ALREADY_STARTED: usize = -7isize as usize;
+const SBI_ERR_ALREADY_STOPPED: usize = -8isize as usize;
+
+const COUNTER_CYCLE: usize = 0;
+const COUNTER_INSTRET: usize = 2;
+const HARDWARE_COUNTERS: usize = 3 + CurrentPlatform::PMU_HPM_COUNTERS;
+const FIRMWARE_COUNTERS: usize = 16;
+// 计数器用 usize 的位图表示，平台的 mhpmcounter 加上固件计数器不能超过 64 个
+const COUNTERS: usize = HARDWARE_COUNTERS + FIRMWARE_COUNTERS;
+const USIZE_BITS: usize = core::mem::size_of::<usize>() * 8;
+
+/// 固件事件，编号和 SBI 规范中的相同
+#[derive(Clone, Copy)]
+pub enum FirmwareEvent {
+    MisalignedLoad = 0,
+    MisalignedStore = 1,
+    AccessLoad = 2,
+    AccessStore = 3,
+    IllegalInstruction = 4,
+    SetTimer = 5,
+    IpiSent = 6,
+    IpiReceived = 7,
+    FenceISent = 8,
+    FenceIReceived = 9,
+    SfenceVmaSent = 10,
+    SfenceVmaReceived = 11,
+    SfenceVmaAsidSent = 12,
+    SfenceVmaAsidReceived = 13,
+}
+
+// 支持的固件事件个数，虚拟化相关的事件不支持
+const FIRMWARE_EVENTS: usize = 14;
+
+struct HartPmu {
+    // 每个计数器配置的事件号，None 表示空闲
+    events: [Option<usize>; COUNTERS],
+    started: usize,
+    firmware_values: [u64; FIRMWARE_COUNTERS],
+}
+
+const HART_PMU_INIT: Mutex<HartPmu> = Mutex::new(HartPmu {
+    events: [None; COUNTERS],
+    started: 0,
+    firmware_values: [0; FIRMWARE_COUNTERS],
+});
+
+static HARTS: [Mutex<HartPmu>; NUM_HARTS] = [HART_PMU_INIT; NUM_HARTS];
+
+/// 每个核启动时调用：停止所有的 mhpmcounter，S 态读取计数器都由固件模拟
+pub fn init_hart() {
+    if !cfg!(feature = "sbi-pmu") {
+        return;
+    }
+    let inhibit = bits(3, HARDWARE_COUNTERS);
+    unsafe {
+        asm!("csrw mcountinhibit, {}", in(reg) inhibit);
+        asm!("csrw mcounteren, zero");
+    }
+}
+
+/// 在调用的核上记录一次固件事件
+pub fn record(event: FirmwareEvent) {
+    if !cfg!(feature = "sbi-pmu") {
+        return;
+    }
+    let hartid = riscv::register::mhartid::read();
+    let mut pmu = HARTS[hartid].lock();
+    for index in HARDWARE_COUNTERS..COUNTERS {
+        if pmu.started & (1 << index) != 0 && pmu.events[index] == Some(firmware_event_idx(event as usize)) {
+            pmu.firmware_values[index - HARDWARE_COUNTERS] += 1;
+        }
+    }
+}
+
+/// 读取硬件计数器，index 是 cycle 开始的 CSR 编号；不存在的计数器返回 None
+pub fn read_hardware_counter(index: usize) -> Option<u64> {
+    if index == 1 || index >= HARDWARE_COUNTERS {
+        return None;
+    }
+    Some(read_counter_csr(index) as u64)
+}
+
+/// 处理 PMU 扩展的调用，以及对它的探测；返回是否处理了这次调用
+pub fn emulate_sbi_call(ctx: &mut SupervisorContext) -> bool {
+    if !cfg!(feature = "sbi-pmu") {
+        return false;
+    }
+    let hartid = riscv::register::mhartid::read();
+    let ret = match (ctx.a7, ctx.a6) {
+        (EXTENSION_BASE, FUNCTION_BASE_PROBE_EXTENSION) if ctx.a0 == EXTENSION_PMU => SbiRet::ok(1),
+        (EXTENSION_PMU, FUNCTION_NUM_COUNTERS) => SbiRet::ok(COUNTERS),
+        (EXTENSION_PMU, FUNCTION_COUNTER_GET_INFO) => counter_get_info(ctx.a0),
+        (EXTENSION_PMU, FUNCTION_COUNTER_CONFIG_MATCHING) => {
+            HARTS[hartid].lock().config_matching(ctx.a0, ctx.a1, ctx.a2, ctx.a3, ctx.a4)
+        }
+        (EXTENSION_PMU, FUNCTION_COUNTER_START) => HARTS[hartid].lock().start(ctx.a0, ctx.a1, ctx.a2, ctx.a3 as u64),
+        (EXTENSION_PMU, FUNCTION_COUNTER_STOP) => HARTS[hartid].lock().stop(ctx.a0, ctx.a1, ctx.a2),
+        (EXTENSION_PMU, FUNCTION_COUNTER_FW_READ) => HARTS[hartid].lock().firmware_read(ctx.a0),
+        (EXTENSION_PMU, _) => SbiRet::not_supported(),
+        _ => return false,
+    };
+    ctx.a0 = ret.error;
+    ctx.a1 = ret.value;
+    true
+}
+
+// 低 12 位是 CSR 编号，然后是宽度减一，最高位表示固件计数器
+fn counter_get_info(index: usize) -> SbiRet {
+    if index < HARDWARE_COUNTERS {
+        SbiRet::ok((0xc00 + index) | (63 << 12))
+    } else if index < COUNTERS {
+        SbiRet::ok(1 << (USIZE_BITS - 1))
+    } else {
+        invalid_param()
+    }
+}
+
+impl HartPmu {
+    fn config_matching(&mut self, base: usize, mask: usize, flags: usize, event_idx: usize, event_data: usize) -> SbiRet {
+        let requested = match counter_set(base, mask) {
+            Some(requested) if requested != 0 => requested,
+            _ => return invalid_param(),
+        };
+        let index = if flags & CONFIG_FLAG_SKIP_MATCH != 0 {
+            // 跳过匹配时使用集合中第一个已经配置过的计数器
+            let index = requested.trailing_zeros() as usize;
+            if self.events[index].is_none() {
+                return invalid_param();
+            }
+            index
+        } else {
+            let (candidates, selector) = match event_counters(event_idx, event_data) {
+                Some(found) => found,
+                None => return SbiRet::not_supported(),
+            };
+            let free = requested & candidates & !self.allocated();
+            if free == 0 {
+                return SbiRet::not_supported();
+            }
+            let index = free.trailing_zeros() as usize;
+            self.events[index] = Some(event_idx);
+            if let Some(selector) = selector {
+                write_event_csr(index, selector);
+            }
+            if index < HARDWARE_COUNTERS {
+                unsafe { asm!("csrs mcounteren, {}", in(reg) 1usize << index) };
+            }
+            index
+        };
+        if flags & CONFIG_FLAG_CLEAR_VALUE != 0 {
+            self.write(index, 0);
+        }
+        if flags & CONFIG_FLAG_AUTO_START != 0 {
+            self.started |= 1 << index;
+            if index < HARDWARE_COUNTERS {
+                unsafe { asm!("csrc mcountinhibit, {}", in(reg) 1usize << index) };
+            }
+        }
+        SbiRet::ok(index)
+    }
+
+    fn start(&mut self, base: usize, mask: usize, flags: usize, initial_value: u64) -> SbiRet {
+        let counters = match counter_set(base, mask) {
+            Some(counters) if counters & !self.allocated() == 0 => counters,
+            _ => return invalid_param(),
+        };
+        let already_started = counters & self.started;
+        for index in iter_bits(counters & !already_started) {
+            if flags & START_FLAG_SET_INIT_VALUE != 0 {
+                self.write(index, initial_value);
+            }
+            self.started |= 1 << index;
+        }
+        let hardware = counters & !already_started & bits(0, HARDWARE_COUNTERS);
+        unsafe { asm!("csrc mcountinhibit, {}", in(reg) hardware) };
+        if already_started != 0 {
+            SbiRet { error: SBI_ERR_ALREADY_STARTED, value: 0 }
+        } else {
+            SbiRet::ok(0)
+        }
+    }
+
+    fn stop(&mut self, base: usize, mask: usize, flags: usize) -> SbiRet {
+        let counters = match counter_set(base, mask) {
+            Some(counters) if counters & !self.allocated() == 0 => counters,
+            _ => return invalid_param(),
+        };
+        let already_stopped = counters & !self.started;
+        self.started &= !counters;
+        let hardware = counters & bits(0, HARDWARE_COUNTERS);
+        unsafe { asm!("csrs mcountinhibit, {}", in(reg) hardware) };
+        if flags & STOP_FLAG_RESET != 0 {
+            for index in iter_bits(counters) {
+                self.events[index] = None;
+            }
+            unsafe {
+                asm!("csrc mcounteren, {}", in(reg) hardware);
+                // cycle 和 instret 释放之后继续计数，rdcycle 和 rdinstret 仍然可用
+                asm!("csrc mcountinhibit, {}", in(reg) hardware & (1 << COUNTER_CYCLE | 1 << COUNTER_INSTRET));
+            }
+        }
+        if already_stopped != 0 {
+            SbiRet { error: SBI_ERR_ALREADY_STOPPED, value: 0 }
+        } else {
+            SbiRet::ok(0)
+        }
+    }
+
+    fn firmware_read(&self, index: usize) -> SbiRet {
+        if index < HARDWARE_COUNTERS || index >= COUNTERS || self.events[index].is_none() {
+            return invalid_param();
+        }
+        SbiRet::ok(self.firmware_values[index - HARDWARE_COUNTERS] as usize)
+    }
+
+    fn write(&mut self, index: usize, value: u64) {
+        if index < HARDWARE_COUNTERS {
+            write_counter_csr(index, value as usize);
+        } else {
+            self.firmware_values[index - HARDWARE_COUNTERS] = value;
+        }
+    }
+
+    fn allocated(&self) -> usize {
+        let mut allocated = 0;
+        for (index, event) in self.events.iter().enumerate() {
+            if event.is_some() {
+                allocated |= 1 << index;
+            }
+        }
+        allocated
+    }
+}
+
+// 可以计数这个事件的计数器，以及需要写入 mhpmevent 的值
+fn event_counters(event_idx: usize, event_data: usize) -> Option<(usize, Option<usize>)> {
+    let code = event_idx & 0xffff;
+    let hpm = bits(3, HARDWARE_COUNTERS);
+    match event_idx >> 16 {
+        EVENT_TYPE_HARDWARE if code == EVENT_CPU_CYCLES => Some((1 << COUNTER_CYCLE, None)),
+        EVENT_TYPE_HARDWARE if code == EVENT_INSTRUCTIONS => Some((1 << COUNTER_INSTRET, None)),
+        EVENT_TYPE_HARDWARE | EVENT_TYPE_CACHE => CurrentPlatform::PMU_EVENTS.iter()
+            .find(|event| event.event_idx == event_idx)
+            .map(|event| (event.counters as usize & hpm, Some(event.selector))),
+        EVENT_TYPE_RAW if CurrentPlatform::PMU_RAW_COUNTERS != 0 => {
+            Some((CurrentPlatform::PMU_RAW_COUNTERS as usize & hpm, Some(event_data)))
+        }
+        EVENT_TYPE_FIRMWARE if code < FIRMWARE_EVENTS => Some((bits(HARDWARE_COUNTERS, COUNTERS), None)),
+        _ => None,
+    }
+}
+
+fn firmware_event_idx(code: usize) -> usize {
+    EVENT_TYPE_FIRMWARE << 16 | code
+}
+
+// base 和 mask 表示的计数器集合，有不存在的计数器时返回 None
+fn counter_set(base: usize, mask: usize) -> Option<usize> {
+    if mask == 0 {
+        return Some(0);
+    }
+    let last = base.checked_add(USIZE_BITS - 1 - mask.leading_zeros() as usize)?;
+    if last >= COUNTERS {
+        return None;
+    }
+    Some(mask << base)
+}
+
+// 第 start 位到第 end 位（不含）为 1
+fn bits(start: usize, end: usize) -> usize {
+    let below_end = if end >= USIZE_BITS { usize::MAX } else { (1 << end) - 1 };
+    below_end & !((1 << start) - 1)
+}
+
+fn iter_bits(mut set: usize) -> impl Iterator<Item = usize> {
+    core::iter::from_fn(move || {
+        if set == 0 {
+            return None;
+        }
+        let index = set.trailing_zeros() as usize;
+        set &= set - 1;
+        Some(index)
+    })
+}
+
+fn invalid_param() -> SbiRet {
+    SbiRet { error: SBI_ERR_INVALID_PARAM, value: 0 }
+}
+
+// CSR 编号只能是立即数，按编号展开成 match
+macro_rules! counter_csrs {
+    ($($index: literal)*) => {
+        fn read_counter_csr(index: usize) -> usize {
+            let value: usize;
+            match index {
+                $($index => unsafe { asm!("csrr {}, {csr}", out(reg) value, csr = const 0xb00 + $index) },)*
+                _ => return 0,
+            }
+            value
+        }
+
+        fn write_counter_csr(index: usize, value: usize) {
+            match index {
+                $($index => unsafe { asm!("csrw {csr}, {}", in(reg) value, csr = const 0xb00 + $index) },)*
+                _ => {}
+            }
+        }
+    };
+}
+
+macro_rules! event_csrs {
+    ($($index: literal)*) => {
+        fn write_event_csr(index: usize, value: usize) {
+            match index {
+                $($index => unsafe { asm!("csrw {csr}, {}", in(reg) value, csr = const 0x320 + $index) },)*
+                _ => {}
+            }
+        }
+    };
+}
+
+counter_csrs!(0 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31);
+event_csrs!(3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31);
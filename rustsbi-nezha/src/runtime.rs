@@ -1,4 +1,5 @@
 use riscv::register::{mcause::{self, Trap, Exception, Interrupt}, mstatus::{self, Mstatus, MPP}, mtval, mtvec::{self, TrapMode}};
+use boot_config::Privilege;
 use core::{
     pin::Pin,
     ops::{Generator, GeneratorState},
@@ -27,7 +28,11 @@ impl Runtime {
     }
 
     fn reset(&mut self) {
-        unsafe { mstatus::set_mpp(MPP::Supervisor) };
+        let mpp = match boot_config::NEXT_STAGE_PRIVILEGE {
+            Privilege::Supervisor => MPP::Supervisor,
+            Privilege::User => MPP::User,
+        };
+        unsafe { mstatus::set_mpp(mpp) };
         self.context.mstatus = mstatus::read();
         self.context.machine_stack = 0x2333333366666666; // 将会被resume函数覆盖
     }
@@ -64,6 +69,8 @@ impl Generator for Runtime {
             Trap::Exception(Exception::InstructionPageFault) => MachineTrap::InstructionPageFault(mtval),
             Trap::Exception(Exception::LoadPageFault) => MachineTrap::LoadPageFault(mtval),
             Trap::Exception(Exception::StorePageFault) => MachineTrap::StorePageFault(mtval),
+            Trap::Exception(Exception::LoadMisaligned) => MachineTrap::LoadMisaligned(mtval),
+            Trap::Exception(Exception::StoreMisaligned) => MachineTrap::StoreMisaligned(mtval),
             e => panic!("unhandled exception: {:?}! mtval: {:#x?}, ctx: {:#x?}", e, mtval, self.context)
         };
         GeneratorState::Yielded(trap)
@@ -83,7 +90,9 @@ pub enum MachineTrap {
     StoreFault(usize),
     InstructionPageFault(usize),
     LoadPageFault(usize),
-    StorePageFault(usize)
+    StorePageFault(usize),
+    LoadMisaligned(usize),
+    StoreMisaligned(usize),
 }
 
 #[derive(Debug)]
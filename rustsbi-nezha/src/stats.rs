@@ -0,0 +1,217 @@
+//! 每个核进入 M 态的统计：各种陷入、SBI 调用（按扩展和函数）以及模拟的指令。
+//!
+//! S 态通过厂商扩展按 (类别, 编号, 函数号) 读取计数器，插入新的计数器不会改变已有计数器的编号：
+//! 异常和中断的编号是 mcause 中的异常码，模拟的指令按 Emulated 的值编号，SBI 调用的编号是扩展号和函数号。
+//! 计数器在内部按下标排列：先是 MachineTrap 的各个变体，然后是模拟的指令，最后是 SBI_EXTENSIONS
+//! 中各个扩展的各个函数，以及一个计入其它所有调用的计数器。
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{config::LogLevel, log, runtime::MachineTrap, NUM_HARTS};
+
+/// 计数器的类别
+pub const CLASS_EXCEPTION: usize = 0;
+pub const CLASS_INTERRUPT: usize = 1;
+pub const CLASS_EMULATED: usize = 2;
+pub const CLASS_SBI: usize = 3;
+/// SBI_EXTENSIONS 之外的 SBI 调用使用的扩展号
+pub const SBI_OTHER_EXTENSION: usize = usize::MAX;
+
+// 按 record_trap 中的顺序：(类别, mcause 中的异常码, 名称)
+const TRAPS: [(usize, usize, &str); 13] = [
+    (CLASS_EXCEPTION, 9, "sbi call"),
+    (CLASS_EXCEPTION, 2, "illegal instruction"),
+    (CLASS_INTERRUPT, 11, "external interrupt"),
+    (CLASS_INTERRUPT, 7, "machine timer"),
+    (CLASS_INTERRUPT, 3, "machine soft"),
+    (CLASS_EXCEPTION, 1, "instruction fault"),
+    (CLASS_EXCEPTION, 5, "load fault"),
+    (CLASS_EXCEPTION, 7, "store fault"),
+    (CLASS_EXCEPTION, 12, "instruction page fault"),
+    (CLASS_EXCEPTION, 13, "load page fault"),
+    (CLASS_EXCEPTION, 15, "store page fault"),
+    (CLASS_EXCEPTION, 4, "load misaligned"),
+    (CLASS_EXCEPTION, 6, "store misaligned"),
+];
+
+/// 在 M 态模拟的指令，值是计数器的编号，新的指令加在后面
+#[derive(Clone, Copy)]
+pub enum Emulated {
+    Rdtime = 0,
+    MisalignedLoad = 1,
+    MisalignedStore = 2,
+    CounterRead = 3,
+}
+
+const EMULATED_NAMES: [&str; 4] = [
+    "emulated rdtime",
+    "emulated misaligned load",
+    "emulated misaligned store",
+    "emulated counter read",
+];
+
+// 分别统计的 SBI 扩展：(扩展号, 名称, 函数个数)。旧版扩展不看函数号
+const SBI_EXTENSIONS: [(usize, &str, usize); 18] = [
+    (0x00, "legacy set_timer", 1),
+    (0x01, "legacy console_putchar", 1),
+    (0x02, "legacy console_getchar", 1),
+    (0x03, "legacy clear_ipi", 1),
+    (0x04, "legacy send_ipi", 1),
+    (0x05, "legacy remote_fence_i", 1),
+    (0x06, "legacy remote_sfence_vma", 1),
+    (0x07, "legacy remote_sfence_vma_asid", 1),
+    (0x08, "legacy shutdown", 1),
+    (0x10, "BASE", 7),
+    (0x5449_4d45, "TIME", 1),
+    (0x0073_5049, "IPI", 1),
+    (0x5246_4e43, "RFENCE", 7),
+    (0x0048_534d, "HSM", 4),
+    (0x5352_5354, "SRST", 1),
+    (crate::pmu::EXTENSION_PMU, "PMU", 6),
+    (crate::dbcn::EXTENSION_DBCN, "DBCN", 3),
+    (crate::vendor::EXTENSION_NEZHA, "NEZHA", 6),
+];
+
+const TRAP_BASE: usize = 0;
+const EMULATED_BASE: usize = TRAP_BASE + TRAPS.len();
+const SBI_BASE: usize = EMULATED_BASE + EMULATED_NAMES.len();
+const SBI_OTHER: usize = SBI_BASE + sbi_function_count();
+const COUNTERS: usize = SBI_OTHER + 1;
+
+const fn sbi_function_count() -> usize {
+    let mut count = 0;
+    let mut i = 0;
+    while i < SBI_EXTENSIONS.len() {
+        count += SBI_EXTENSIONS[i].2;
+        i += 1;
+    }
+    count
+}
+
+struct HartStats {
+    counters: [AtomicUsize; COUNTERS],
+}
+
+const COUNTER_INIT: AtomicUsize = AtomicUsize::new(0);
+const HART_STATS_INIT: HartStats = HartStats { counters: [COUNTER_INIT; COUNTERS] };
+
+static STATS: [HartStats; NUM_HARTS] = [HART_STATS_INIT; NUM_HARTS];
+
+#[inline]
+fn count(hartid: usize, index: usize) {
+    STATS[hartid].counters[index].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_trap(hartid: usize, trap: &MachineTrap) {
+    let index = match trap {
+        MachineTrap::SbiCall() => 0,
+        MachineTrap::IllegalInstruction() => 1,
+        MachineTrap::ExternalInterrupt() => 2,
+        MachineTrap::MachineTimer() => 3,
+        MachineTrap::MachineSoft() => 4,
+        MachineTrap::InstructionFault(_) => 5,
+        MachineTrap::LoadFault(_) => 6,
+        MachineTrap::StoreFault(_) => 7,
+        MachineTrap::InstructionPageFault(_) => 8,
+        MachineTrap::LoadPageFault(_) => 9,
+        MachineTrap::StorePageFault(_) => 10,
+        MachineTrap::LoadMisaligned(_) => 11,
+        MachineTrap::StoreMisaligned(_) => 12,
+    };
+    count(hartid, TRAP_BASE + index);
+}
+
+pub fn record_emulated(hartid: usize, emulated: Emulated) {
+    count(hartid, EMULATED_BASE + emulated as usize);
+}
+
+pub fn record_sbi_call(hartid: usize, extension: usize, function: usize) {
+    let mut index = SBI_BASE;
+    for &(id, _, functions) in SBI_EXTENSIONS.iter() {
+        if id == extension {
+            let function = if extension < 0x10 { 0 } else { function };
+            if function < functions {
+                return count(hartid, index + function);
+            }
+            break;
+        }
+        index += functions;
+    }
+    count(hartid, SBI_OTHER);
+}
+
+/// 读取一个计数器，hartid 超出范围或者没有这个计数器时返回 None
+pub fn read(hartid: usize, class: usize, code: usize, function: usize) -> Option<usize> {
+    let index = find(class, code, function)?;
+    Some(STATS.get(hartid)?.counters[index].load(Ordering::Relaxed))
+}
+
+// 由计数器的编号找到它的下标
+fn find(class: usize, code: usize, function: usize) -> Option<usize> {
+    match class {
+        CLASS_EXCEPTION | CLASS_INTERRUPT => {
+            let index = TRAPS.iter().position(|&(c, n, _)| c == class && n == code)?;
+            Some(TRAP_BASE + index)
+        }
+        CLASS_EMULATED if code < EMULATED_NAMES.len() => Some(EMULATED_BASE + code),
+        CLASS_SBI if code == SBI_OTHER_EXTENSION => Some(SBI_OTHER),
+        CLASS_SBI => {
+            let mut index = SBI_BASE;
+            for &(id, _, functions) in SBI_EXTENSIONS.iter() {
+                if id == code {
+                    return if function < functions { Some(index + function) } else { None };
+                }
+                index += functions;
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+/// 清零一个核的计数器，hartid 为 usize::MAX 时清零所有核的；hartid 超出范围时返回 false
+pub fn reset(hartid: usize) -> bool {
+    let harts = match hartid {
+        usize::MAX => &STATS[..],
+        hartid if hartid < NUM_HARTS => &STATS[hartid..=hartid],
+        _ => return false,
+    };
+    for hart in harts {
+        for counter in hart.counters.iter() {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+    true
+}
+
+/// 输出所有不为零的计数器，panic 时调用，不受日志级别限制
+pub fn dump() {
+    for (hartid, hart) in STATS.iter().enumerate() {
+        for (index, counter) in hart.counters.iter().enumerate() {
+            let value = counter.load(Ordering::Relaxed);
+            if value == 0 {
+                continue;
+            }
+            match index {
+                i if i < EMULATED_BASE => log::print(LogLevel::Error, format_args!("hart {} {}: {}", hartid, TRAPS[i - TRAP_BASE].2, value)),
+                i if i < SBI_BASE => log::print(LogLevel::Error, format_args!("hart {} {}: {}", hartid, EMULATED_NAMES[i - EMULATED_BASE], value)),
+                SBI_OTHER => log::print(LogLevel::Error, format_args!("hart {} sbi other: {}", hartid, value)),
+                i => {
+                    let (name, function) = sbi_function(i);
+                    log::print(LogLevel::Error, format_args!("hart {} sbi {} function {}: {}", hartid, name, function, value))
+                }
+            }
+        }
+    }
+}
+
+// SBI 计数器对应的扩展名称和函数号
+fn sbi_function(index: usize) -> (&'static str, usize) {
+    let mut offset = index - SBI_BASE;
+    for &(_, name, functions) in SBI_EXTENSIONS.iter() {
+        if offset < functions {
+            return (name, offset);
+        }
+        offset -= functions;
+    }
+    unreachable!("index is below SBI_OTHER")
+}
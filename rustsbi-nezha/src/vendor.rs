@@ -0,0 +1,51 @@
+//! RustSBI-NeZha 的厂商扩展，S 态通过它调整固件的运行时行为。
+use crate::{console, log, runtime::SupervisorContext, stats};
+
+/// 厂商扩展的扩展号，在 SBI 规范为厂商保留的 0x09000000..=0x09FFFFFF 中
+pub const EXTENSION_NEZHA: usize = 0x0900_4e5a;
+
+/// 返回当前的日志级别
+const FUNCTION_GET_LOG_LEVEL: usize = 0x0;
+/// a0 是新的日志级别，0 到 5 分别是 off、error、warn、info、debug、trace；
+/// 返回实际生效的级别，不会超过编译时配置的级别
+const FUNCTION_SET_LOG_LEVEL: usize = 0x1;
+// 0x2 以前返回计数器的个数，配合按下标读取计数器使用，下标会随着计数器的增加而改变，已经去掉
+/// 返回核 a0 的统计计数器，a1 是类别，a2 和 a3 是类别中的编号，见 stats 模块
+const FUNCTION_READ_COUNTER: usize = 0x3;
+/// 清零核 a0 的统计计数器，a0 为 -1 时清零所有核的
+const FUNCTION_RESET_COUNTERS: usize = 0x4;
+/// 返回控制台接收时丢失字节的次数
+const FUNCTION_GET_CONSOLE_OVERRUNS: usize = 0x5;
+
+const EXTENSION_BASE: usize = 0x10;
+const FUNCTION_BASE_PROBE_EXTENSION: usize = 0x3;
+
+const SBI_ERR_NOT_SUPPORTED: usize = -2isize as usize;
+const SBI_ERR_INVALID_PARAM: usize = -3isize as usize;
+
+/// 处理厂商扩展的调用，以及对它的探测；返回是否处理了这次调用
+pub fn emulate_sbi_call(ctx: &mut SupervisorContext) -> bool {
+    let (error, value) = match (ctx.a7, ctx.a6) {
+        // rustsbi 不知道这个扩展，探测它时在这里返回
+        (EXTENSION_BASE, FUNCTION_BASE_PROBE_EXTENSION) if ctx.a0 == EXTENSION_NEZHA => (0, 1),
+        (EXTENSION_NEZHA, FUNCTION_GET_LOG_LEVEL) => (0, log::level() as usize),
+        (EXTENSION_NEZHA, FUNCTION_SET_LOG_LEVEL) => match log::set_level(ctx.a0) {
+            Some(level) => (0, level as usize),
+            None => (SBI_ERR_INVALID_PARAM, 0),
+        },
+        (EXTENSION_NEZHA, FUNCTION_READ_COUNTER) => match stats::read(ctx.a0, ctx.a1, ctx.a2, ctx.a3) {
+            Some(value) => (0, value),
+            None => (SBI_ERR_INVALID_PARAM, 0),
+        },
+        (EXTENSION_NEZHA, FUNCTION_RESET_COUNTERS) => match stats::reset(ctx.a0) {
+            true => (0, 0),
+            false => (SBI_ERR_INVALID_PARAM, 0),
+        },
+        (EXTENSION_NEZHA, FUNCTION_GET_CONSOLE_OVERRUNS) => (0, console::overruns()),
+        (EXTENSION_NEZHA, _) => (SBI_ERR_NOT_SUPPORTED, 0),
+        _ => return false,
+    };
+    ctx.a0 = error;
+    ctx.a1 = value;
+    true
+}
@@ -6,13 +6,23 @@ use std::path::PathBuf;
 fn main() {
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
 
-    // Put the linker script somewhere the linker can find it
+    // Put the linker script somewhere the linker can find it,
+    // loading the kernel where the firmware jumps to
+    let linker_script = include_str!("src/linker.ld")
+        .replace("${NEXT_STAGE_ADDRESS}", &format!("{:#x}", boot_config::NEXT_STAGE_ADDRESS));
     fs::File::create(out_dir.join("linker.ld"))
         .unwrap()
-        .write_all(include_bytes!("src/linker.ld"))
+        .write_all(linker_script.as_bytes())
         .unwrap();
     println!("cargo:rustc-link-search={}", out_dir.display());
 
+    // Firmware memory is protected by PMP, tests check that SBI calls refuse buffers in it
+    fs::write(
+        out_dir.join("layout.rs"),
+        format!("pub const FIRMWARE_BASE: usize = {:#x};\n", boot_config::FIRMWARE_BASE),
+    )
+    .unwrap();
+
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=src/linker.ld");
 }
@@ -2,8 +2,20 @@ mod base_extension;
 mod delegate_trap;
 mod sfence_vma;
 mod catch_page_fault;
+mod hsm;
+mod misaligned;
+mod log_level;
+mod trap_stats;
+mod pmu;
+mod dbcn;
 
 pub use base_extension::test_base_extension;
 pub use delegate_trap::test_delegate_trap;
 pub use sfence_vma::test_sfence_vma;
 pub use catch_page_fault::test_catch_page_fault;
+pub use hsm::{test_hsm, secondary_main};
+pub use misaligned::test_emulate_misaligned;
+pub use log_level::test_log_level;
+pub use trap_stats::test_trap_stats;
+pub use pmu::test_pmu;
+pub use dbcn::test_dbcn;
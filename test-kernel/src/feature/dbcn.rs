@@ -0,0 +1,61 @@
+use crate::{sbi, println};
+
+include!(concat!(env!("OUT_DIR"), "/layout.rs"));
+
+const MESSAGE: &str = "<< Test-kernel: DBCN console write\n";
+// DBCN 是 SBI 2.0 的扩展
+const SPEC_VERSION_2_0: usize = 2 << 24;
+
+pub fn test_dbcn() {
+    println!(">> Test-kernel: Testing SBI debug console extension");
+    if sbi::probe_extension(sbi::EXTENSION_DBCN) == 0 {
+        println!("<< Test-kernel: DBCN extension not supported, skipped");
+        return;
+    }
+    if sbi::get_spec_version() < SPEC_VERSION_2_0 {
+        println!("!! Test-kernel: DBCN is supported but SBI specification version is {:#x}", sbi::get_spec_version());
+        println!("!! Test-kernel: SBI test FAILED due to wrong debug console");
+        sbi::shutdown()
+    }
+    // 测试内核还没有开启分页，虚拟地址就是物理地址
+    let ret = sbi::console_write(MESSAGE.len(), MESSAGE.as_ptr() as usize, 0);
+    if ret.error != 0 || ret.value != MESSAGE.len() {
+        println!("!! Test-kernel: console write failed, error: {:#x}, value: {}", ret.error, ret.value);
+        println!("!! Test-kernel: SBI test FAILED due to wrong debug console");
+        sbi::shutdown()
+    }
+    for &byte in b"<< Test-kernel: DBCN console write byte\n" {
+        sbi::console_write_byte(byte);
+    }
+    if sbi::console_write(MESSAGE.len(), MESSAGE.as_ptr() as usize, 1).error == 0 {
+        println!("!! Test-kernel: a buffer above 64-bit address space was accepted");
+        println!("!! Test-kernel: SBI test FAILED due to wrong debug console");
+        sbi::shutdown()
+    }
+    // 固件所在的内存由 PMP 保护，S 态不能访问，固件也不能替 S 态读写
+    if sbi::console_write(MESSAGE.len(), FIRMWARE_BASE, 0).error == 0 {
+        println!("!! Test-kernel: a buffer in firmware memory {:#x} was written", FIRMWARE_BASE);
+        println!("!! Test-kernel: SBI test FAILED due to wrong debug console");
+        sbi::shutdown()
+    }
+    if sbi::console_read(16, FIRMWARE_BASE, 0).error == 0 {
+        println!("!! Test-kernel: a buffer in firmware memory {:#x} was accepted for reading", FIRMWARE_BASE);
+        println!("!! Test-kernel: SBI test FAILED due to wrong debug console");
+        sbi::shutdown()
+    }
+    let mut buf = [0u8; 16];
+    let ret = sbi::console_read(buf.len(), buf.as_mut_ptr() as usize, 0);
+    if ret.error != 0 || ret.value > buf.len() {
+        println!("!! Test-kernel: console read failed, error: {:#x}, value: {}", ret.error, ret.value);
+        println!("!! Test-kernel: SBI test FAILED due to wrong debug console");
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: DBCN read {} pending bytes", ret.value);
+    let ret = sbi::nezha_get_console_overruns();
+    if ret.error != 0 {
+        println!("!! Test-kernel: cannot read console overruns, error: {:#x}", ret.error);
+        println!("!! Test-kernel: SBI test FAILED due to wrong debug console");
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: {} console overruns so far", ret.value);
+}
@@ -0,0 +1,91 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::{sbi, println};
+
+// 用来测试启动和停止的从核，QEMU virt 上由 xtask 以两个核启动
+const SECONDARY_HART: usize = 1;
+// 等待从核状态变化时最多查询的次数
+const WAIT_LIMIT: usize = 1_000_000;
+
+// 从核启动后写入收到的 opaque
+static SECONDARY_OPAQUE: AtomicUsize = AtomicUsize::new(0);
+
+pub fn test_hsm(hartid: usize) {
+    println!(">> Test-kernel: Testing hart state monitor extension");
+    if sbi::probe_extension(sbi::EXTENSION_HSM) == 0 {
+        println!("!! Test-kernel: no hsm extension probed");
+        println!("!! Test-kernel: SBI test FAILED due to no hsm extension found");
+        sbi::shutdown()
+    }
+    let ret = sbi::hart_get_status(hartid);
+    if ret.error != 0 || ret.value != sbi::HART_STATE_STARTED {
+        println!("!! Test-kernel: current hart {} is not reported as started, error: {:#x}, value: {}", hartid, ret.error, ret.value);
+        println!("!! Test-kernel: SBI test FAILED due to wrong hart state");
+        sbi::shutdown()
+    }
+    let ret = sbi::hart_start(hartid, 0, 0);
+    if ret.error == 0 {
+        println!("!! Test-kernel: starting an already started hart should fail");
+        println!("!! Test-kernel: SBI test FAILED due to wrong hart_start result");
+        sbi::shutdown()
+    }
+    let ret = sbi::hart_get_status(usize::MAX);
+    if ret.error == 0 {
+        println!("!! Test-kernel: hart_get_status accepted an invalid hart id");
+        println!("!! Test-kernel: SBI test FAILED due to wrong hart_get_status result");
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: Hart {} state: started", hartid);
+    test_start_stop();
+}
+
+// 启动从核，等它停止，再启动一次，确认停止的核可以重新启动
+fn test_start_stop() {
+    let ret = sbi::hart_get_status(SECONDARY_HART);
+    if ret.error != 0 {
+        println!("<< Test-kernel: no hart {} to start, skipped", SECONDARY_HART);
+        return;
+    }
+    if ret.value != sbi::HART_STATE_STOPPED {
+        println!("!! Test-kernel: hart {} is not stopped before start, value: {}", SECONDARY_HART, ret.value);
+        println!("!! Test-kernel: SBI test FAILED due to wrong hart state");
+        sbi::shutdown()
+    }
+    for &opaque in [0x1234_5678, 0x9abc_def0].iter() {
+        let ret = sbi::hart_start(SECONDARY_HART, crate::entry as usize, opaque);
+        if ret.error != 0 {
+            println!("!! Test-kernel: cannot start hart {}, error: {:#x}", SECONDARY_HART, ret.error);
+            println!("!! Test-kernel: SBI test FAILED due to wrong hart_start result");
+            sbi::shutdown()
+        }
+        if !wait_until(|| SECONDARY_OPAQUE.load(Ordering::Acquire) == opaque) {
+            println!("!! Test-kernel: hart {} did not start with opaque {:#x}", SECONDARY_HART, opaque);
+            println!("!! Test-kernel: SBI test FAILED due to wrong hart_start result");
+            sbi::shutdown()
+        }
+        if !wait_until(|| sbi::hart_get_status(SECONDARY_HART).value == sbi::HART_STATE_STOPPED) {
+            println!("!! Test-kernel: hart {} did not stop", SECONDARY_HART);
+            println!("!! Test-kernel: SBI test FAILED due to wrong hart_stop result");
+            sbi::shutdown()
+        }
+    }
+    println!("<< Test-kernel: Hart {} started and stopped twice", SECONDARY_HART);
+}
+
+fn wait_until(condition: impl Fn() -> bool) -> bool {
+    (0..WAIT_LIMIT).any(|_| condition())
+}
+
+/// 从核进入测试内核后调用：报告收到的 opaque，然后停止
+pub fn secondary_main(hartid: usize, opaque: usize) -> ! {
+    let ret = sbi::hart_get_status(hartid);
+    if ret.error != 0 || ret.value != sbi::HART_STATE_STARTED {
+        println!("!! Test-kernel: hart {} is not reported as started, error: {:#x}, value: {}", hartid, ret.error, ret.value);
+        println!("!! Test-kernel: SBI test FAILED due to wrong hart state");
+        sbi::shutdown()
+    }
+    SECONDARY_OPAQUE.store(opaque, Ordering::Release);
+    let ret = sbi::hart_stop();
+    println!("!! Test-kernel: hart_stop returned on hart {}, error: {:#x}", hartid, ret.error);
+    println!("!! Test-kernel: SBI test FAILED due to wrong hart_stop result");
+    sbi::shutdown()
+}
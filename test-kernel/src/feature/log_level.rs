@@ -0,0 +1,36 @@
+use crate::{sbi, println};
+
+pub fn test_log_level() {
+    println!(">> Test-kernel: Testing vendor log level extension");
+    if sbi::probe_extension(sbi::EXTENSION_NEZHA) == 0 {
+        println!("!! Test-kernel: no vendor extension probed");
+        println!("!! Test-kernel: SBI test FAILED due to no vendor extension found");
+        sbi::shutdown()
+    }
+    let ret = sbi::nezha_get_log_level();
+    if ret.error != 0 || ret.value > 5 {
+        println!("!! Test-kernel: cannot read log level, error: {:#x}, value: {}", ret.error, ret.value);
+        println!("!! Test-kernel: SBI test FAILED due to wrong log level");
+        sbi::shutdown()
+    }
+    let level = ret.value;
+    // 只输出错误，之后恢复原来的级别
+    let ret = sbi::nezha_set_log_level(1);
+    if ret.error != 0 || ret.value != level.min(1) || sbi::nezha_get_log_level().value != ret.value {
+        println!("!! Test-kernel: cannot lower log level, error: {:#x}, value: {}", ret.error, ret.value);
+        println!("!! Test-kernel: SBI test FAILED due to wrong log level");
+        sbi::shutdown()
+    }
+    if sbi::nezha_set_log_level(6).error == 0 {
+        println!("!! Test-kernel: an invalid log level was accepted");
+        println!("!! Test-kernel: SBI test FAILED due to wrong log level");
+        sbi::shutdown()
+    }
+    let ret = sbi::nezha_set_log_level(level);
+    if ret.error != 0 || ret.value != level {
+        println!("!! Test-kernel: cannot restore log level {}, error: {:#x}, value: {}", level, ret.error, ret.value);
+        println!("!! Test-kernel: SBI test FAILED due to wrong log level");
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: Firmware log level: {}", level);
+}
@@ -0,0 +1,23 @@
+use crate::{sbi, println};
+
+static mut BUFFER: [u8; 16] = [0; 16];
+
+pub fn test_emulate_misaligned() {
+    println!(">> Test-kernel: Testing misaligned load and store emulation");
+    let addr = unsafe { BUFFER.as_mut_ptr() as usize } + 1;
+    let value: usize = 0x1122_3344_5566_7788;
+    let (word, dword): (usize, usize);
+    unsafe {
+        asm!("
+            sd      {value}, 0({addr})
+            lw      {word}, 0({addr})
+            ld      {dword}, 0({addr})
+        ", addr = in(reg) addr, value = in(reg) value, word = out(reg) word, dword = out(reg) dword)
+    };
+    if dword != value || word != 0x5566_7788 {
+        println!("!! Test-kernel: Misaligned access got wrong value: {:#x}, {:#x}", word, dword);
+        println!("!! Test-kernel: SBI test FAILED due to wrong misaligned emulation");
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: Misaligned load and store success");
+}
@@ -0,0 +1,57 @@
+use crate::{sbi, println};
+
+// 通用硬件事件 CPU_CYCLES，固定使用 cycle 计数器
+const EVENT_CPU_CYCLES: usize = 0x1;
+// 固件事件 SET_TIMER
+const EVENT_FW_SET_TIMER: usize = 0xf0005;
+
+pub fn test_pmu() {
+    println!(">> Test-kernel: Testing SBI PMU extension");
+    if sbi::probe_extension(sbi::EXTENSION_PMU) == 0 {
+        println!("<< Test-kernel: PMU extension not supported, skipped");
+        return;
+    }
+    let count = sbi::pmu_num_counters().value;
+    let all = if count >= 64 { usize::MAX } else { (1 << count) - 1 };
+    let flags = sbi::PMU_CONFIG_FLAG_CLEAR_VALUE | sbi::PMU_CONFIG_FLAG_AUTO_START;
+    let ret = sbi::pmu_counter_config_matching(0, all, flags, EVENT_CPU_CYCLES, 0);
+    if ret.error != 0 || ret.value != 0 {
+        println!("!! Test-kernel: cannot configure cycle counter, error: {:#x}, value: {}", ret.error, ret.value);
+        println!("!! Test-kernel: SBI test FAILED due to wrong PMU counters");
+        sbi::shutdown()
+    }
+    // 配置之后 S 态可以直接读取 cycle
+    let (before, after): (usize, usize);
+    unsafe {
+        asm!("rdcycle {}", out(reg) before);
+        asm!("rdcycle {}", out(reg) after);
+    }
+    if after <= before {
+        println!("!! Test-kernel: cycle counter is not running, {} then {}", before, after);
+        println!("!! Test-kernel: SBI test FAILED due to wrong PMU counters");
+        sbi::shutdown()
+    }
+    sbi::pmu_counter_stop(0, 1, sbi::PMU_STOP_FLAG_RESET);
+    let ret = sbi::pmu_counter_config_matching(0, all, flags, EVENT_FW_SET_TIMER, 0);
+    if ret.error != 0 {
+        println!("!! Test-kernel: cannot configure firmware counter, error: {:#x}", ret.error);
+        println!("!! Test-kernel: SBI test FAILED due to wrong PMU counters");
+        sbi::shutdown()
+    }
+    let index = ret.value;
+    for _ in 0..3 {
+        sbi::set_timer(usize::MAX);
+    }
+    let ret = sbi::pmu_counter_fw_read(index);
+    if ret.error != 0 || ret.value != 3 {
+        println!("!! Test-kernel: wrong firmware counter, error: {:#x}, value: {}", ret.error, ret.value);
+        println!("!! Test-kernel: SBI test FAILED due to wrong PMU counters");
+        sbi::shutdown()
+    }
+    if sbi::pmu_counter_stop(index, 1, sbi::PMU_STOP_FLAG_RESET).error != 0 || sbi::pmu_counter_fw_read(index).error == 0 {
+        println!("!! Test-kernel: firmware counter was not released");
+        println!("!! Test-kernel: SBI test FAILED due to wrong PMU counters");
+        sbi::shutdown()
+    }
+    println!("<< Test-kernel: PMU cycle and firmware counters work");
+}
@@ -0,0 +1,55 @@
+use crate::{sbi, println};
+
+// 计数器按 mcause 中的异常码编号，S 态的 ecall 是 9
+const EXCEPTION_SUPERVISOR_ECALL: usize = 9;
+const INTERRUPT_MACHINE_TIMER: usize = 7;
+const EMULATED_RDTIME: usize = 0;
+const EXTENSION_BASE: usize = 0x10;
+const FUNCTION_BASE_GET_SPEC_VERSION: usize = 0x0;
+
+pub fn test_trap_stats(hartid: usize) {
+    println!(">> Test-kernel: Testing vendor trap statistics");
+    let ret = sbi::nezha_read_counter(hartid, sbi::NEZHA_COUNTER_EXCEPTION, EXCEPTION_SUPERVISOR_ECALL, 0);
+    if ret.error != 0 || ret.value == 0 {
+        println!("!! Test-kernel: previous SBI calls were not counted, error: {:#x}, value: {}", ret.error, ret.value);
+        fail()
+    }
+    println!("<< Test-kernel: {} SBI calls so far", ret.value);
+    for &(class, code) in [(sbi::NEZHA_COUNTER_INTERRUPT, INTERRUPT_MACHINE_TIMER), (sbi::NEZHA_COUNTER_EMULATED, EMULATED_RDTIME)].iter() {
+        let ret = sbi::nezha_read_counter(hartid, class, code, 0);
+        if ret.error != 0 {
+            println!("!! Test-kernel: cannot read counter ({}, {}), error: {:#x}", class, code, ret.error);
+            fail()
+        }
+    }
+    // 清零之后只有读取计数器的这次调用
+    if sbi::nezha_reset_counters(hartid).error != 0
+        || sbi::nezha_read_counter(hartid, sbi::NEZHA_COUNTER_EXCEPTION, EXCEPTION_SUPERVISOR_ECALL, 0).value != 1 {
+        println!("!! Test-kernel: counters were not reset");
+        fail()
+    }
+    // 按扩展号和函数号统计的 SBI 调用
+    sbi::get_spec_version();
+    sbi::get_spec_version();
+    let ret = sbi::nezha_read_counter(hartid, sbi::NEZHA_COUNTER_SBI, EXTENSION_BASE, FUNCTION_BASE_GET_SPEC_VERSION);
+    if ret.error != 0 || ret.value != 2 {
+        println!("!! Test-kernel: get_spec_version was counted {} times, error: {:#x}", ret.value, ret.error);
+        fail()
+    }
+    for &(class, code, function) in [(4, 0, 0), (sbi::NEZHA_COUNTER_EXCEPTION, 3, 0), (sbi::NEZHA_COUNTER_SBI, EXTENSION_BASE, 0x100)].iter() {
+        if sbi::nezha_read_counter(hartid, class, code, function).error == 0 {
+            println!("!! Test-kernel: invalid counter ({}, {:#x}, {:#x}) was accepted", class, code, function);
+            fail()
+        }
+    }
+    if sbi::nezha_read_counter(usize::MAX - 1, sbi::NEZHA_COUNTER_EXCEPTION, EXCEPTION_SUPERVISOR_ECALL, 0).error == 0 {
+        println!("!! Test-kernel: an invalid hart was accepted");
+        fail()
+    }
+    println!("<< Test-kernel: Trap statistics reset");
+}
+
+fn fail() -> ! {
+    println!("!! Test-kernel: SBI test FAILED due to wrong trap statistics");
+    sbi::shutdown()
+}
@@ -25,13 +25,22 @@ extern "C" fn rust_main(hartid: usize, opaque: usize) -> ! {
     if hartid == 0 {
         init_bss();
         init_heap();
+    } else {
+        // 从核由启动核在 test_hsm 中通过 HSM 扩展启动
+        feature::secondary_main(hartid, opaque)
     }
     println!("<< Test-kernel: Hart id = {}, opaque = {:#x}", hartid, opaque);
     feature::test_base_extension();
+    feature::test_dbcn();
+    feature::test_hsm(hartid);
+    feature::test_log_level();
     feature::test_delegate_trap();
     test_emulate_rdtime();
+    feature::test_emulate_misaligned();
     feature::test_sfence_vma();
     feature::test_catch_page_fault();
+    feature::test_trap_stats(hartid);
+    feature::test_pmu();
     println!("<< Test-kernel: SBI test SUCCESS, shutdown");
     read_char();
     sbi::shutdown()
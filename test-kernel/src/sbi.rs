@@ -6,6 +6,10 @@ pub const EXTENSION_IPI: usize = 0x735049;
 pub const EXTENSION_RFENCE: usize = 0x52464E43;
 pub const EXTENSION_HSM: usize = 0x48534D;
 pub const EXTENSION_SRST: usize = 0x53525354;
+pub const EXTENSION_PMU: usize = 0x504D55;
+pub const EXTENSION_DBCN: usize = 0x4442434E;
+// RustSBI-NeZha 的厂商扩展
+pub const EXTENSION_NEZHA: usize = 0x09004E5A;
 
 const FUNCTION_BASE_GET_SPEC_VERSION: usize = 0x0;
 const FUNCTION_BASE_GET_SBI_IMPL_ID: usize = 0x1;
@@ -15,6 +19,32 @@ const FUNCTION_BASE_GET_MVENDORID: usize = 0x4;
 const FUNCTION_BASE_GET_MARCHID: usize = 0x5;
 const FUNCTION_BASE_GET_MIMPID: usize = 0x6;
 
+const FUNCTION_HSM_HART_START: usize = 0x0;
+const FUNCTION_HSM_HART_STOP: usize = 0x1;
+const FUNCTION_HSM_HART_GET_STATUS: usize = 0x2;
+
+const FUNCTION_DBCN_CONSOLE_WRITE: usize = 0x0;
+const FUNCTION_DBCN_CONSOLE_READ: usize = 0x1;
+const FUNCTION_DBCN_CONSOLE_WRITE_BYTE: usize = 0x2;
+
+const FUNCTION_PMU_NUM_COUNTERS: usize = 0x0;
+const FUNCTION_PMU_COUNTER_CONFIG_MATCHING: usize = 0x2;
+const FUNCTION_PMU_COUNTER_STOP: usize = 0x4;
+const FUNCTION_PMU_COUNTER_FW_READ: usize = 0x5;
+
+pub const PMU_CONFIG_FLAG_CLEAR_VALUE: usize = 1 << 1;
+pub const PMU_CONFIG_FLAG_AUTO_START: usize = 1 << 2;
+pub const PMU_STOP_FLAG_RESET: usize = 1 << 0;
+
+const FUNCTION_NEZHA_GET_LOG_LEVEL: usize = 0x0;
+const FUNCTION_NEZHA_SET_LOG_LEVEL: usize = 0x1;
+const FUNCTION_NEZHA_READ_COUNTER: usize = 0x3;
+const FUNCTION_NEZHA_RESET_COUNTERS: usize = 0x4;
+const FUNCTION_NEZHA_GET_CONSOLE_OVERRUNS: usize = 0x5;
+
+pub const HART_STATE_STARTED: usize = 0;
+pub const HART_STATE_STOPPED: usize = 1;
+
 #[repr(C)]
 pub struct SbiRet {
     /// Error number
@@ -43,6 +73,27 @@ fn sbi_call(extension: usize, function: usize, arg0: usize, arg1: usize, arg2: u
     SbiRet { error, value }
 }
 
+// PMU 扩展的部分函数需要 5 个参数
+#[inline(always)]
+fn sbi_call_5(extension: usize, function: usize, args: [usize; 5]) -> SbiRet {
+    let (error, value);
+    match () {
+        #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+        () => unsafe { asm!(
+            "ecall", 
+            in("a0") args[0], in("a1") args[1], in("a2") args[2], in("a3") args[3], in("a4") args[4],
+            in("a6") function, in("a7") extension,
+            lateout("a0") error, lateout("a1") value,
+        ) },
+        #[cfg(not(any(target_arch = "riscv32", target_arch = "riscv64")))]
+        () => {
+            drop((extension, function, args));
+            unimplemented!("not RISC-V instruction set architecture")
+        }
+    };
+    SbiRet { error, value }
+}
+
 #[inline]
 pub fn get_spec_version() -> usize {
     sbi_call(EXTENSION_BASE, FUNCTION_BASE_GET_SPEC_VERSION, 0, 0, 0).value
@@ -78,6 +129,87 @@ pub fn get_mimpid() -> usize {
     sbi_call(EXTENSION_BASE, FUNCTION_BASE_GET_MIMPID, 0, 0, 0).value
 }
 
+#[inline]
+pub fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> SbiRet {
+    sbi_call(EXTENSION_HSM, FUNCTION_HSM_HART_START, hartid, start_addr, opaque)
+}
+
+#[inline]
+pub fn hart_stop() -> SbiRet {
+    sbi_call(EXTENSION_HSM, FUNCTION_HSM_HART_STOP, 0, 0, 0)
+}
+
+#[inline]
+pub fn hart_get_status(hartid: usize) -> SbiRet {
+    sbi_call(EXTENSION_HSM, FUNCTION_HSM_HART_GET_STATUS, hartid, 0, 0)
+}
+
+#[inline]
+pub fn console_write(num_bytes: usize, base_lo: usize, base_hi: usize) -> SbiRet {
+    sbi_call(EXTENSION_DBCN, FUNCTION_DBCN_CONSOLE_WRITE, num_bytes, base_lo, base_hi)
+}
+
+#[inline]
+pub fn console_read(num_bytes: usize, base_lo: usize, base_hi: usize) -> SbiRet {
+    sbi_call(EXTENSION_DBCN, FUNCTION_DBCN_CONSOLE_READ, num_bytes, base_lo, base_hi)
+}
+
+#[inline]
+pub fn console_write_byte(byte: u8) -> SbiRet {
+    sbi_call(EXTENSION_DBCN, FUNCTION_DBCN_CONSOLE_WRITE_BYTE, byte as usize, 0, 0)
+}
+
+#[inline]
+pub fn pmu_num_counters() -> SbiRet {
+    sbi_call(EXTENSION_PMU, FUNCTION_PMU_NUM_COUNTERS, 0, 0, 0)
+}
+
+#[inline]
+pub fn pmu_counter_config_matching(base: usize, mask: usize, flags: usize, event_idx: usize, event_data: usize) -> SbiRet {
+    sbi_call_5(EXTENSION_PMU, FUNCTION_PMU_COUNTER_CONFIG_MATCHING, [base, mask, flags, event_idx, event_data])
+}
+
+#[inline]
+pub fn pmu_counter_stop(base: usize, mask: usize, flags: usize) -> SbiRet {
+    sbi_call(EXTENSION_PMU, FUNCTION_PMU_COUNTER_STOP, base, mask, flags)
+}
+
+#[inline]
+pub fn pmu_counter_fw_read(index: usize) -> SbiRet {
+    sbi_call(EXTENSION_PMU, FUNCTION_PMU_COUNTER_FW_READ, index, 0, 0)
+}
+
+#[inline]
+pub fn nezha_get_log_level() -> SbiRet {
+    sbi_call(EXTENSION_NEZHA, FUNCTION_NEZHA_GET_LOG_LEVEL, 0, 0, 0)
+}
+
+#[inline]
+pub fn nezha_set_log_level(level: usize) -> SbiRet {
+    sbi_call(EXTENSION_NEZHA, FUNCTION_NEZHA_SET_LOG_LEVEL, level, 0, 0)
+}
+
+// 统计计数器的类别
+pub const NEZHA_COUNTER_EXCEPTION: usize = 0;
+pub const NEZHA_COUNTER_INTERRUPT: usize = 1;
+pub const NEZHA_COUNTER_EMULATED: usize = 2;
+pub const NEZHA_COUNTER_SBI: usize = 3;
+
+#[inline]
+pub fn nezha_read_counter(hartid: usize, class: usize, code: usize, function: usize) -> SbiRet {
+    sbi_call_5(EXTENSION_NEZHA, FUNCTION_NEZHA_READ_COUNTER, [hartid, class, code, function, 0])
+}
+
+#[inline]
+pub fn nezha_reset_counters(hartid: usize) -> SbiRet {
+    sbi_call(EXTENSION_NEZHA, FUNCTION_NEZHA_RESET_COUNTERS, hartid, 0, 0)
+}
+
+#[inline]
+pub fn nezha_get_console_overruns() -> SbiRet {
+    sbi_call(EXTENSION_NEZHA, FUNCTION_NEZHA_GET_CONSOLE_OVERRUNS, 0, 0, 0)
+}
+
 #[inline(always)]
 fn sbi_call_legacy(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     let ret;
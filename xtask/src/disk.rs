@@ -0,0 +1,287 @@
+//! SD 卡镜像：BROM 读取的 boot0、boot0 读取的 TOC1 启动包，以及可选的 FAT 分区。
+//!
+//! 启动数据不属于任何分区，分区表和分区都放在它们后面，不会互相覆盖。
+use std::{convert::TryFrom, fmt};
+
+use boot_config::image::crc32;
+
+const SECTOR_SIZE: usize = 512;
+/// BROM 从这里读取 boot0
+pub const BOOT0_OFFSET: usize = 8 * 1024;
+/// 第一份 boot0 校验失败时，BROM 再从这里读取
+pub const BOOT0_BACKUP_OFFSET: usize = 128 * 1024;
+// boot0 不能覆盖备份
+const BOOT0_MAX_SIZE: usize = BOOT0_BACKUP_OFFSET - BOOT0_OFFSET;
+// GPT 的分区项数组，默认位置在第 2 扇区，会和 boot0 重叠，移到两份 boot0 之后
+const GPT_ENTRIES_OFFSET: usize = 1024 * 1024;
+/// 全志的 boot0 从第 32800 扇区读取 TOC1 启动包
+pub const TOC1_OFFSET: usize = 32800 * SECTOR_SIZE;
+/// 第一个分区的起始位置
+pub const PARTITION_START: usize = 32 * 1024 * 1024;
+
+const GPT_ENTRY_COUNT: usize = 128;
+const GPT_ENTRY_SIZE: usize = 128;
+const GPT_ENTRIES_SECTORS: usize = GPT_ENTRY_COUNT * GPT_ENTRY_SIZE / SECTOR_SIZE;
+const GPT_HEADER_SIZE: usize = 92;
+// Microsoft basic data，FAT 分区使用这个类型
+const GPT_BASIC_DATA: [u8; 16] = guid_bytes(0xebd0_a0a2, 0xb9e5, 0x4433, [0x87, 0xc0, 0x68, 0xb6, 0xb7, 0x26, 0x99, 0xc7]);
+const MBR_FAT16_LBA: u8 = 0x0e;
+const MBR_PROTECTIVE: u8 = 0xee;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionTable {
+    Mbr,
+    Gpt,
+}
+
+#[derive(Debug)]
+pub enum DiskError {
+    Boot0TooLarge(usize),
+    Toc1TooLarge(usize),
+    /// GPT 的可用区域从 PARTITION_START 开始，没有分区时这个区域是空的
+    GptWithoutPartition,
+}
+
+impl fmt::Display for DiskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiskError::Boot0TooLarge(size) => write!(f, "boot0 ({:#x} bytes) is larger than {:#x} bytes", size, BOOT0_MAX_SIZE),
+            DiskError::Toc1TooLarge(size) => write!(f, "TOC1 package ({:#x} bytes) is larger than {:#x} bytes", size, PARTITION_START - TOC1_OFFSET),
+            DiskError::GptWithoutPartition => write!(f, "GPT needs a FAT partition"),
+        }
+    }
+}
+
+/// 生成 SD 卡镜像。fat 是已经格式化好的分区，放在 PARTITION_START
+pub fn build(boot0: &[u8], toc1: &[u8], fat: Option<&[u8]>, table: PartitionTable) -> Result<Vec<u8>, DiskError> {
+    if boot0.len() > BOOT0_MAX_SIZE {
+        return Err(DiskError::Boot0TooLarge(boot0.len()));
+    }
+    if toc1.len() > PARTITION_START - TOC1_OFFSET {
+        return Err(DiskError::Toc1TooLarge(toc1.len()));
+    }
+    if table == PartitionTable::Gpt && fat.is_none() {
+        return Err(DiskError::GptWithoutPartition);
+    }
+    let partition_size = fat.map_or(0, |fat| fat.len());
+    // GPT 在磁盘末尾还有一份备份
+    let backup_size = match table {
+        PartitionTable::Mbr => 0,
+        PartitionTable::Gpt => (GPT_ENTRIES_SECTORS + 1) * SECTOR_SIZE,
+    };
+    let mut disk = vec![0u8; PARTITION_START + partition_size + backup_size];
+    disk[BOOT0_OFFSET..BOOT0_OFFSET + boot0.len()].copy_from_slice(boot0);
+    disk[BOOT0_BACKUP_OFFSET..BOOT0_BACKUP_OFFSET + boot0.len()].copy_from_slice(boot0);
+    disk[TOC1_OFFSET..TOC1_OFFSET + toc1.len()].copy_from_slice(toc1);
+    let partition = fat.map(|fat| {
+        disk[PARTITION_START..PARTITION_START + fat.len()].copy_from_slice(fat);
+        (PARTITION_START / SECTOR_SIZE, fat.len() / SECTOR_SIZE)
+    });
+    match table {
+        PartitionTable::Mbr => write_mbr(&mut disk, partition.map(|(start, count)| (MBR_FAT16_LBA, start, count))),
+        PartitionTable::Gpt => write_gpt(&mut disk, partition, toc1),
+    }
+    Ok(disk)
+}
+
+// MBR 只用第一个分区项，分区项中的 CHS 地址填成表示“使用 LBA”的最大值
+fn write_mbr(disk: &mut [u8], partition: Option<(u8, usize, usize)>) {
+    if let Some((kind, start, count)) = partition {
+        let entry = &mut disk[446..462];
+        entry[1..4].copy_from_slice(&[0xfe, 0xff, 0xff]);
+        entry[4] = kind;
+        entry[5..8].copy_from_slice(&[0xfe, 0xff, 0xff]);
+        entry[8..12].copy_from_slice(&u32::try_from(start).unwrap_or(u32::MAX).to_le_bytes());
+        entry[12..16].copy_from_slice(&u32::try_from(count).unwrap_or(u32::MAX).to_le_bytes());
+    }
+    disk[510] = 0x55;
+    disk[511] = 0xaa;
+}
+
+fn write_gpt(disk: &mut [u8], partition: Option<(usize, usize)>, seed: &[u8]) {
+    let last_lba = disk.len() / SECTOR_SIZE - 1;
+    write_mbr(disk, Some((MBR_PROTECTIVE, 1, last_lba)));
+    let mut entries = vec![0u8; GPT_ENTRY_COUNT * GPT_ENTRY_SIZE];
+    if let Some((start, count)) = partition {
+        let entry = &mut entries[..GPT_ENTRY_SIZE];
+        entry[..16].copy_from_slice(&GPT_BASIC_DATA);
+        entry[16..32].copy_from_slice(&random_guid(seed, 1));
+        entry[32..40].copy_from_slice(&(start as u64).to_le_bytes());
+        entry[40..48].copy_from_slice(&((start + count - 1) as u64).to_le_bytes());
+        for (index, c) in "boot".encode_utf16().enumerate() {
+            entry[56 + index * 2..58 + index * 2].copy_from_slice(&c.to_le_bytes());
+        }
+    }
+    let entries_crc = crc32(&entries);
+    let primary_entries = GPT_ENTRIES_OFFSET / SECTOR_SIZE;
+    let backup_entries = last_lba - GPT_ENTRIES_SECTORS;
+    let disk_guid = random_guid(seed, 0);
+    let header = |current: usize, backup: usize, entries_lba: usize| {
+        let mut header = [0u8; GPT_HEADER_SIZE];
+        header[..8].copy_from_slice(b"EFI PART");
+        header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&(GPT_HEADER_SIZE as u32).to_le_bytes());
+        header[24..32].copy_from_slice(&(current as u64).to_le_bytes());
+        header[32..40].copy_from_slice(&(backup as u64).to_le_bytes());
+        // 启动数据之前的区域不给分区使用
+        header[40..48].copy_from_slice(&((PARTITION_START / SECTOR_SIZE) as u64).to_le_bytes());
+        header[48..56].copy_from_slice(&((backup_entries - 1) as u64).to_le_bytes());
+        header[56..72].copy_from_slice(&disk_guid);
+        header[72..80].copy_from_slice(&(entries_lba as u64).to_le_bytes());
+        header[80..84].copy_from_slice(&(GPT_ENTRY_COUNT as u32).to_le_bytes());
+        header[84..88].copy_from_slice(&(GPT_ENTRY_SIZE as u32).to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+        let header_crc = crc32(&header);
+        header[16..20].copy_from_slice(&header_crc.to_le_bytes());
+        header
+    };
+    disk[SECTOR_SIZE..SECTOR_SIZE + GPT_HEADER_SIZE].copy_from_slice(&header(1, last_lba, primary_entries));
+    disk[GPT_ENTRIES_OFFSET..GPT_ENTRIES_OFFSET + entries.len()].copy_from_slice(&entries);
+    let backup_header = last_lba * SECTOR_SIZE;
+    disk[backup_header..backup_header + GPT_HEADER_SIZE].copy_from_slice(&header(last_lba, 1, backup_entries));
+    let backup_entries = backup_entries * SECTOR_SIZE;
+    disk[backup_entries..backup_entries + entries.len()].copy_from_slice(&entries);
+}
+
+// 由启动包的内容生成 GUID，同样的输入生成同样的镜像
+fn random_guid(seed: &[u8], index: u32) -> [u8; 16] {
+    let mut guid = [0u8; 16];
+    let mut state = crc32(seed) ^ index.wrapping_mul(0x9e37_79b9);
+    for chunk in guid.chunks_mut(4) {
+        state = crc32(&state.to_le_bytes());
+        chunk.copy_from_slice(&state.to_le_bytes());
+    }
+    // 第 4 版（随机）GUID
+    guid[7] = (guid[7] & 0x0f) | 0x40;
+    guid[8] = (guid[8] & 0x3f) | 0x80;
+    guid
+}
+
+// GUID 的前三段按小端存放
+const fn guid_bytes(a: u32, b: u16, c: u16, d: [u8; 8]) -> [u8; 16] {
+    let a = a.to_le_bytes();
+    let b = b.to_le_bytes();
+    let c = c.to_le_bytes();
+    [a[0], a[1], a[2], a[3], b[0], b[1], c[0], c[1], d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn boot_data() -> (Vec<u8>, Vec<u8>) {
+        let boot0 = (0..0x8000).map(|i| (i % 251) as u8).collect();
+        let toc1 = (0..0x10000).map(|i| (i % 241) as u8 | 1).collect();
+        (boot0, toc1)
+    }
+
+    // FAT 分区的内容对分区表没有影响，填上可以识别的数据即可
+    fn partition() -> Vec<u8> {
+        vec![0xa5; 4 * 1024 * 1024]
+    }
+
+    fn check_boot_data(disk: &[u8], boot0: &[u8], toc1: &[u8]) {
+        assert_eq!(disk[BOOT0_OFFSET..][..boot0.len()], boot0[..]);
+        assert_eq!(disk[BOOT0_BACKUP_OFFSET..][..boot0.len()], boot0[..]);
+        assert_eq!(TOC1_OFFSET, 32800 * 512);
+        assert_eq!(disk[TOC1_OFFSET..][..toc1.len()], toc1[..]);
+    }
+
+    // 检查 GPT 头部的 CRC 和各个字段，返回头部中的可用区域和分区项数组的位置
+    fn check_gpt_header(disk: &[u8], lba: usize, backup: usize) -> (u64, u64, usize) {
+        let mut header = disk[lba * SECTOR_SIZE..][..GPT_HEADER_SIZE].to_vec();
+        assert_eq!(&header[..8], b"EFI PART");
+        assert_eq!(read_u32(&header, 12) as usize, GPT_HEADER_SIZE);
+        assert_eq!(read_u64(&header, 24), lba as u64);
+        assert_eq!(read_u64(&header, 32), backup as u64);
+        let header_crc = read_u32(&header, 16);
+        header[16..20].fill(0);
+        assert_eq!(crc32(&header), header_crc);
+        let entries_lba = read_u64(&header, 72) as usize;
+        let entries = &disk[entries_lba * SECTOR_SIZE..][..GPT_ENTRY_COUNT * GPT_ENTRY_SIZE];
+        assert_eq!(crc32(entries), read_u32(&header, 88));
+        (read_u64(&header, 40), read_u64(&header, 48), entries_lba)
+    }
+
+    #[test]
+    fn mbr_layout() {
+        let (boot0, toc1) = boot_data();
+        let fat = partition();
+        let disk = build(&boot0, &toc1, Some(&fat), PartitionTable::Mbr).unwrap();
+        assert_eq!(disk.len(), PARTITION_START + fat.len());
+        check_boot_data(&disk, &boot0, &toc1);
+        assert_eq!(disk[510..512], [0x55, 0xaa]);
+        let entry = &disk[446..462];
+        assert_eq!(entry[4], MBR_FAT16_LBA);
+        assert_eq!(read_u32(entry, 8) as usize, PARTITION_START / SECTOR_SIZE);
+        assert_eq!(read_u32(entry, 12) as usize, fat.len() / SECTOR_SIZE);
+        assert_eq!(disk[PARTITION_START..], fat[..]);
+    }
+
+    #[test]
+    fn mbr_without_partition() {
+        let (boot0, toc1) = boot_data();
+        let disk = build(&boot0, &toc1, None, PartitionTable::Mbr).unwrap();
+        assert_eq!(disk.len(), PARTITION_START);
+        check_boot_data(&disk, &boot0, &toc1);
+        assert!(disk[446..510].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn gpt_layout() {
+        let (boot0, toc1) = boot_data();
+        let fat = partition();
+        let disk = build(&boot0, &toc1, Some(&fat), PartitionTable::Gpt).unwrap();
+        check_boot_data(&disk, &boot0, &toc1);
+        let last_lba = disk.len() / SECTOR_SIZE - 1;
+        assert_eq!(disk[446 + 4], MBR_PROTECTIVE);
+        let primary = check_gpt_header(&disk, 1, last_lba);
+        let backup = check_gpt_header(&disk, last_lba, 1);
+        // 两份头部只有自己的位置和分区项数组的位置不同
+        assert_eq!((primary.0, primary.1), (backup.0, backup.1));
+        assert_eq!(primary.2 * SECTOR_SIZE, GPT_ENTRIES_OFFSET);
+        assert_eq!(backup.2, last_lba - GPT_ENTRIES_SECTORS);
+        let entries_size = GPT_ENTRY_COUNT * GPT_ENTRY_SIZE;
+        assert_eq!(disk[GPT_ENTRIES_OFFSET..][..entries_size], disk[backup.2 * SECTOR_SIZE..][..entries_size]);
+        let (first_usable, last_usable, _) = primary;
+        assert_eq!(first_usable as usize, PARTITION_START / SECTOR_SIZE);
+        assert!(first_usable <= last_usable);
+        assert!((last_usable as usize) < backup.2);
+        // 分区项数组在两份 boot0 和启动包之间
+        assert!(GPT_ENTRIES_OFFSET >= BOOT0_BACKUP_OFFSET + boot0.len());
+        assert!(GPT_ENTRIES_OFFSET + entries_size <= TOC1_OFFSET);
+        let entry = &disk[GPT_ENTRIES_OFFSET..][..GPT_ENTRY_SIZE];
+        assert_eq!(entry[..16], GPT_BASIC_DATA);
+        let (start, end) = (read_u64(entry, 32), read_u64(entry, 40));
+        assert!(first_usable <= start && end <= last_usable);
+        assert_eq!((end - start + 1) as usize * SECTOR_SIZE, fat.len());
+        assert!(start as usize * SECTOR_SIZE >= TOC1_OFFSET + toc1.len());
+        assert_eq!(disk[start as usize * SECTOR_SIZE..][..fat.len()], fat[..]);
+        // 其余的分区项都是空的
+        assert!(disk[GPT_ENTRIES_OFFSET + GPT_ENTRY_SIZE..][..entries_size - GPT_ENTRY_SIZE].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn gpt_needs_partition() {
+        let (boot0, toc1) = boot_data();
+        assert!(matches!(build(&boot0, &toc1, None, PartitionTable::Gpt), Err(DiskError::GptWithoutPartition)));
+    }
+
+    #[test]
+    fn boot_data_too_large() {
+        let (boot0, toc1) = boot_data();
+        let large = vec![0u8; BOOT0_MAX_SIZE + 1];
+        assert!(matches!(build(&large, &toc1, None, PartitionTable::Mbr), Err(DiskError::Boot0TooLarge(_))));
+        let large = vec![0u8; PARTITION_START - TOC1_OFFSET + 1];
+        assert!(matches!(build(&boot0, &large, None, PartitionTable::Mbr), Err(DiskError::Toc1TooLarge(_))));
+    }
+}
@@ -0,0 +1,332 @@
+//! 生成只有根目录的 FAT16 文件系统，用来在 SD 卡镜像中存放内核和设备树。
+//!
+//! 文件名只支持 8.3 格式的短文件名，不写长文件名。
+use std::fmt;
+
+const SECTOR_SIZE: usize = 512;
+const RESERVED_SECTORS: usize = 1;
+const FAT_COUNT: usize = 2;
+const ROOT_ENTRIES: usize = 512;
+const DIR_ENTRY_SIZE: usize = 32;
+// FAT16 的簇数范围，超出范围时会被识别成 FAT12 或 FAT32
+const MIN_CLUSTERS: usize = 4085;
+const MAX_CLUSTERS: usize = 65524;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_ARCHIVE: u8 = 0x20;
+// 1980-01-01，没有使用当前时间，同样的输入生成同样的镜像
+const FAT_DATE: u16 = (1 << 5) | 1;
+
+#[derive(Debug)]
+pub enum FatError {
+    /// 分区太小或太大，不能格式化成 FAT16
+    BadSize(usize),
+    TooManyFiles,
+    DuplicateName(String),
+    /// 文件放不下，参数是需要的簇数和可用的簇数
+    NoSpace { needed: usize, available: usize },
+}
+
+impl fmt::Display for FatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FatError::BadSize(size) => write!(f, "cannot format {:#x} bytes as FAT16", size),
+            FatError::TooManyFiles => write!(f, "too many files for the root directory"),
+            FatError::DuplicateName(name) => write!(f, "duplicate file name {}", name),
+            FatError::NoSpace { needed, available } => write!(f, "files need {} clusters, only {} available", needed, available),
+        }
+    }
+}
+
+/// 格式化一个 size 字节的分区并写入文件。hidden_sectors 是分区在磁盘上的起始扇区
+pub fn build(size: usize, hidden_sectors: u32, label: &str, files: &[(String, Vec<u8>)]) -> Result<Vec<u8>, FatError> {
+    let total_sectors = size / SECTOR_SIZE;
+    let root_sectors = ROOT_ENTRIES * DIR_ENTRY_SIZE / SECTOR_SIZE;
+    // 选择最小的簇大小，让簇数不超过 FAT16 的上限
+    let mut sectors_per_cluster = 1;
+    let (fat_sectors, clusters) = loop {
+        let clusters_estimate = total_sectors / sectors_per_cluster;
+        let fat_sectors = align_up((clusters_estimate + 2) * 2, SECTOR_SIZE) / SECTOR_SIZE;
+        let data_sectors = total_sectors.saturating_sub(RESERVED_SECTORS + FAT_COUNT * fat_sectors + root_sectors);
+        let clusters = data_sectors / sectors_per_cluster;
+        if clusters <= MAX_CLUSTERS {
+            break (fat_sectors, clusters);
+        }
+        sectors_per_cluster *= 2;
+        if sectors_per_cluster > 128 {
+            return Err(FatError::BadSize(size));
+        }
+    };
+    if clusters < MIN_CLUSTERS {
+        return Err(FatError::BadSize(size));
+    }
+    if files.len() + 1 > ROOT_ENTRIES {
+        return Err(FatError::TooManyFiles);
+    }
+    let cluster_size = sectors_per_cluster * SECTOR_SIZE;
+    let needed = files.iter().map(|(_, data)| align_up(data.len(), cluster_size) / cluster_size).sum();
+    if needed > clusters {
+        return Err(FatError::NoSpace { needed, available: clusters });
+    }
+
+    let mut image = vec![0u8; total_sectors * SECTOR_SIZE];
+    write_boot_sector(&mut image[..SECTOR_SIZE], total_sectors, sectors_per_cluster, fat_sectors, hidden_sectors, label);
+    let fat_start = RESERVED_SECTORS * SECTOR_SIZE;
+    let root_start = fat_start + FAT_COUNT * fat_sectors * SECTOR_SIZE;
+    let data_start = root_start + root_sectors * SECTOR_SIZE;
+    let mut fat = vec![0u16; fat_sectors * SECTOR_SIZE / 2];
+    fat[0] = 0xfff8;
+    fat[1] = 0xffff;
+    let mut root = Vec::with_capacity((files.len() + 1) * DIR_ENTRY_SIZE);
+    root.extend_from_slice(&dir_entry(&volume_label(label), ATTR_VOLUME_ID, 0, 0));
+    let mut names = Vec::new();
+    // 簇号从 2 开始，各个文件连续存放
+    let mut next_cluster = 2;
+    for (name, data) in files {
+        let short_name = short_name(name);
+        if names.contains(&short_name) {
+            return Err(FatError::DuplicateName(name.clone()));
+        }
+        names.push(short_name);
+        let count = align_up(data.len(), cluster_size) / cluster_size;
+        let first_cluster = if count == 0 { 0 } else { next_cluster };
+        let end = next_cluster + count;
+        for (cluster, value) in fat.iter_mut().enumerate().take(end).skip(next_cluster) {
+            *value = if cluster + 1 == end { 0xffff } else { (cluster + 1) as u16 };
+        }
+        let offset = data_start + (next_cluster - 2) * cluster_size;
+        image[offset..offset + data.len()].copy_from_slice(data);
+        root.extend_from_slice(&dir_entry(&short_name, ATTR_ARCHIVE, first_cluster as u16, data.len() as u32));
+        next_cluster += count;
+    }
+    for index in 0..FAT_COUNT {
+        let start = fat_start + index * fat_sectors * SECTOR_SIZE;
+        for (entry, value) in fat.iter().enumerate() {
+            image[start + entry * 2..start + entry * 2 + 2].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+    image[root_start..root_start + root.len()].copy_from_slice(&root);
+    Ok(image)
+}
+
+/// 文件在 FAT 中的短文件名，比如 "Image" 变成 "IMAGE"，"sun20i-d1.dtb" 变成 "SUN20I-D.DTB"
+pub fn display_name(name: &str) -> String {
+    let short_name = short_name(name);
+    let base = String::from_utf8_lossy(&short_name[..8]).trim_end().to_string();
+    let ext = String::from_utf8_lossy(&short_name[8..]).trim_end().to_string();
+    if ext.is_empty() { base } else { format!("{}.{}", base, ext) }
+}
+
+fn short_name(name: &str) -> [u8; 11] {
+    let (base, ext) = match name.rfind('.') {
+        Some(index) if index > 0 => (&name[..index], &name[index + 1..]),
+        _ => (name, ""),
+    };
+    let mut short_name = [b' '; 11];
+    let convert = |c: char| if c.is_ascii_alphanumeric() || "-_~!#$%&".contains(c) {
+        c.to_ascii_uppercase() as u8
+    } else {
+        b'_'
+    };
+    for (dst, c) in short_name[..8].iter_mut().zip(base.chars()) {
+        *dst = convert(c);
+    }
+    for (dst, c) in short_name[8..].iter_mut().zip(ext.chars()) {
+        *dst = convert(c);
+    }
+    short_name
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+fn volume_label(label: &str) -> [u8; 11] {
+    let mut buf = [b' '; 11];
+    for (dst, c) in buf.iter_mut().zip(label.bytes()) {
+        *dst = c.to_ascii_uppercase();
+    }
+    buf
+}
+
+fn dir_entry(name: &[u8; 11], attr: u8, first_cluster: u16, size: u32) -> [u8; DIR_ENTRY_SIZE] {
+    let mut entry = [0u8; DIR_ENTRY_SIZE];
+    entry[..11].copy_from_slice(name);
+    entry[11] = attr;
+    entry[16..18].copy_from_slice(&FAT_DATE.to_le_bytes());
+    entry[18..20].copy_from_slice(&FAT_DATE.to_le_bytes());
+    entry[24..26].copy_from_slice(&FAT_DATE.to_le_bytes());
+    entry[26..28].copy_from_slice(&first_cluster.to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+    entry
+}
+
+fn write_boot_sector(sector: &mut [u8], total_sectors: usize, sectors_per_cluster: usize, fat_sectors: usize, hidden_sectors: u32, label: &str) {
+    sector[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+    sector[3..11].copy_from_slice(b"RUSTSBI ");
+    sector[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    sector[13] = sectors_per_cluster as u8;
+    sector[14..16].copy_from_slice(&(RESERVED_SECTORS as u16).to_le_bytes());
+    sector[16] = FAT_COUNT as u8;
+    sector[17..19].copy_from_slice(&(ROOT_ENTRIES as u16).to_le_bytes());
+    if total_sectors <= 0xffff {
+        sector[19..21].copy_from_slice(&(total_sectors as u16).to_le_bytes());
+    } else {
+        sector[32..36].copy_from_slice(&(total_sectors as u32).to_le_bytes());
+    }
+    sector[21] = 0xf8; // 固定磁盘
+    sector[22..24].copy_from_slice(&(fat_sectors as u16).to_le_bytes());
+    sector[24..26].copy_from_slice(&63u16.to_le_bytes());
+    sector[26..28].copy_from_slice(&255u16.to_le_bytes());
+    sector[28..32].copy_from_slice(&hidden_sectors.to_le_bytes());
+    sector[36] = 0x80;
+    sector[38] = 0x29;
+    sector[39..43].copy_from_slice(&hidden_sectors.wrapping_mul(0x9e37_79b9).to_le_bytes());
+    sector[43..54].copy_from_slice(&volume_label(label));
+    sector[54..62].copy_from_slice(b"FAT16   ");
+    sector[510] = 0x55;
+    sector[511] = 0xaa;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+    }
+
+    fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    }
+
+    // 按引导扇区中的参数解析文件系统
+    struct Volume<'a> {
+        image: &'a [u8],
+        cluster_size: usize,
+        fat_start: usize,
+        fat_size: usize,
+        root_start: usize,
+        data_start: usize,
+        clusters: usize,
+    }
+
+    impl<'a> Volume<'a> {
+        fn new(image: &'a [u8]) -> Self {
+            assert_eq!(image[510..512], [0x55, 0xaa]);
+            assert_eq!(read_u16(image, 11) as usize, SECTOR_SIZE);
+            let sectors_per_cluster = image[13] as usize;
+            let reserved = read_u16(image, 14) as usize;
+            let fat_count = image[16] as usize;
+            let root_sectors = read_u16(image, 17) as usize * DIR_ENTRY_SIZE / SECTOR_SIZE;
+            let total_sectors = match read_u16(image, 19) {
+                0 => read_u32(image, 32) as usize,
+                sectors => sectors as usize,
+            };
+            assert_eq!(total_sectors * SECTOR_SIZE, image.len());
+            let fat_sectors = read_u16(image, 22) as usize;
+            let root_start = (reserved + fat_count * fat_sectors) * SECTOR_SIZE;
+            let data_sectors = total_sectors - reserved - fat_count * fat_sectors - root_sectors;
+            Volume {
+                image,
+                cluster_size: sectors_per_cluster * SECTOR_SIZE,
+                fat_start: reserved * SECTOR_SIZE,
+                fat_size: fat_sectors * SECTOR_SIZE,
+                root_start,
+                data_start: root_start + root_sectors * SECTOR_SIZE,
+                clusters: data_sectors / sectors_per_cluster,
+            }
+        }
+
+        fn fat(&self, index: usize, cluster: usize) -> u16 {
+            read_u16(self.image, self.fat_start + index * self.fat_size + cluster * 2)
+        }
+
+        fn read_file(&self, name: &[u8; 11]) -> Vec<u8> {
+            let entry = self.image[self.root_start..self.data_start].chunks(DIR_ENTRY_SIZE)
+                .find(|entry| &entry[..11] == name)
+                .expect("file not found");
+            assert_eq!(entry[11], ATTR_ARCHIVE);
+            let size = read_u32(entry, 28) as usize;
+            let mut cluster = read_u16(entry, 26) as usize;
+            let mut data = Vec::new();
+            while cluster != 0 && cluster < 0xfff8 {
+                assert!(cluster >= 2 && cluster < self.clusters + 2);
+                // 两份 FAT 相同
+                assert_eq!(self.fat(0, cluster), self.fat(1, cluster));
+                let offset = self.data_start + (cluster - 2) * self.cluster_size;
+                data.extend_from_slice(&self.image[offset..offset + self.cluster_size]);
+                cluster = self.fat(0, cluster) as usize;
+            }
+            assert_eq!(align_up(size, self.cluster_size), data.len());
+            data.truncate(size);
+            data
+        }
+    }
+
+    fn files() -> Vec<(String, Vec<u8>)> {
+        vec![
+            (String::from("Image"), (0..100_000).map(|i| (i % 253) as u8).collect()),
+            (String::from("empty.txt"), Vec::new()),
+            (String::from("sun20i-d1-nezha.dtb"), (0..0x1234).map(|i| (i % 7) as u8).collect()),
+        ]
+    }
+
+    #[test]
+    fn files_and_fat_chains() {
+        let image = build(8 * 1024 * 1024, 65536, "nezha", &files()).unwrap();
+        let volume = Volume::new(&image);
+        assert_eq!(read_u32(&image, 28), 65536);
+        assert_eq!(&image[43..54], b"NEZHA      ");
+        assert_eq!(&image[54..62], b"FAT16   ");
+        assert_eq!(&image[volume.root_start..volume.root_start + 11], b"NEZHA      ");
+        assert_eq!(volume.fat(0, 0), 0xfff8);
+        assert_eq!(volume.fat(0, 1), 0xffff);
+        assert_eq!(volume.read_file(b"IMAGE      "), files()[0].1);
+        assert_eq!(volume.read_file(b"EMPTY   TXT"), files()[1].1);
+        assert_eq!(volume.read_file(b"SUN20I-DDTB"), files()[2].1);
+    }
+
+    #[test]
+    fn cluster_count_in_fat16_range() {
+        for &mib in &[3, 4, 16, 32, 33, 64, 100, 128, 256, 512, 1024, 2047] {
+            let image = build(mib * 1024 * 1024, 0, "nezha", &[]).unwrap();
+            let volume = Volume::new(&image);
+            assert!((MIN_CLUSTERS..=MAX_CLUSTERS).contains(&volume.clusters), "{} MiB: {} clusters", mib, volume.clusters);
+            // FAT 能够表示所有的簇
+            assert!(volume.fat_size / 2 >= volume.clusters + 2);
+        }
+    }
+
+    #[test]
+    fn bad_sizes() {
+        assert!(matches!(build(1024 * 1024, 0, "nezha", &[]), Err(FatError::BadSize(_))));
+        assert!(matches!(build(4096 * 1024 * 1024, 0, "nezha", &[]), Err(FatError::BadSize(_))));
+    }
+
+    #[test]
+    fn no_space() {
+        let files = [(String::from("big"), vec![0u8; 4 * 1024 * 1024])];
+        assert!(matches!(build(4 * 1024 * 1024, 0, "nezha", &files), Err(FatError::NoSpace { .. })));
+    }
+
+    #[test]
+    fn short_names() {
+        assert_eq!(display_name("Image"), "IMAGE");
+        assert_eq!(display_name("sun20i-d1-nezha.dtb"), "SUN20I-D.DTB");
+        assert_eq!(display_name("initrd.img.gz"), "INITRD_I.GZ");
+        assert_eq!(display_name(".config"), "_CONFIG");
+        assert_eq!(display_name("a b+c.toolong"), "A_B_C.TOO");
+    }
+
+    #[test]
+    fn short_name_collision() {
+        let files = [
+            (String::from("sun20i-d1-nezha.dtb"), vec![1]),
+            (String::from("sun20i-d1-lichee-rv.dtb"), vec![2]),
+        ];
+        match build(4 * 1024 * 1024, 0, "nezha", &files) {
+            Err(FatError::DuplicateName(name)) => assert_eq!(name, "sun20i-d1-lichee-rv.dtb"),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+}
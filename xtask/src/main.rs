@@ -1,9 +1,19 @@
-use std::{env, fs, io::{Seek, SeekFrom, Write}, path::{Path, PathBuf}, process::{self, Command}};
+mod disk;
+mod fat;
+mod monitor;
+mod qemu;
+mod sunxi;
+mod upload;
+
+use std::{convert::TryFrom, env, fs, path::{Path, PathBuf}, process::{self, Command}, time::Duration};
+
+use boot_config::{MemoryLayout, image::{crc32, ImageEntry, ImageHeader, IMAGE_HEADER_SIZE}};
 
 use clap::{clap_app, crate_authors, crate_description, crate_version};
 #[derive(Debug)]
 struct XtaskEnv {
     compile_mode: CompileMode,
+    platform: Platform,
 }
 
 #[derive(Debug)]
@@ -11,6 +21,39 @@ enum CompileMode {
     Debug,
     Release
 }
+
+#[derive(Debug)]
+enum Platform {
+    Nezha,
+    Qemu,
+}
+
+impl Platform {
+    fn layout(&self) -> &'static MemoryLayout {
+        match self {
+            Platform::Nezha => &boot_config::D1,
+            Platform::Qemu => &boot_config::QEMU_VIRT,
+        }
+    }
+
+    // 固件和测试内核都用这个特性选择平台
+    fn features(&self, command: &mut Command) {
+        match self {
+            Platform::Nezha => {},
+            Platform::Qemu => { command.args(["--no-default-features", "--features", "qemu-virt"]); },
+        }
+    }
+
+    fn fused_image_name(&self) -> &'static str {
+        match self {
+            Platform::Nezha => "nezha-fused.bin",
+            Platform::Qemu => "qemu-fused.bin",
+        }
+    }
+}
+
+const DEFAULT_TEST_TIMEOUT: &str = "60";
+const DEFAULT_FAT_SIZE: &str = "64";
 const DEFAULT_TARGET: &'static str = "riscv64imac-unknown-none-elf";
 fn main() {
     let matches = clap_app!(xtask =>
@@ -24,14 +67,79 @@ fn main() {
         (@subcommand nezha =>
             (about: "Run project on actual board")
             (@arg release: --release "Build artifacts in release mode, with optimizations")
+            (@arg payload: --payload +takes_value "Fuse this file as the next stage instead, ELF files are loaded by their segments")
+            (@arg dtb: --dtb +takes_value "Append a device tree blob to the fused image")
+            (@arg initrd: --initrd +takes_value "Append an initrd to the fused image")
+            (@arg monitor: --monitor "Open the serial monitor after starting the image")
+            (@arg port: --port +takes_value "Serial port of the board, defaults to /dev/ttyUSB0")
+            (@arg baud: --baud +takes_value "Baud rate of the serial port, defaults to 115200")
+            (@arg log: --log +takes_value "Also write the serial output to this file")
+            (@arg success: --success +takes_value +multiple number_of_values(1) "Exit successfully when the output contains this")
+            (@arg failure: --failure +takes_value +multiple number_of_values(1) "Exit with failure when the output contains this")
+            (@arg timeout: --timeout +takes_value "Exit with failure after this many seconds")
         )
         (@subcommand zcore =>
             (about: "run zcore")
             (@arg release: --release "Build artifacts in release mode, with optimizations")
+            (@arg payload: --payload +takes_value "Fuse this file as the next stage instead, ELF files are loaded by their segments")
+            (@arg dtb: --dtb +takes_value "Append a device tree blob to the fused image")
+            (@arg initrd: --initrd +takes_value "Append an initrd to the fused image")
+            (@arg monitor: --monitor "Open the serial monitor after starting the image")
+            (@arg port: --port +takes_value "Serial port of the board, defaults to /dev/ttyUSB0")
+            (@arg baud: --baud +takes_value "Baud rate of the serial port, defaults to 115200")
+            (@arg log: --log +takes_value "Also write the serial output to this file")
+            (@arg success: --success +takes_value +multiple number_of_values(1) "Exit successfully when the output contains this")
+            (@arg failure: --failure +takes_value +multiple number_of_values(1) "Exit with failure when the output contains this")
+            (@arg timeout: --timeout +takes_value "Exit with failure after this many seconds")
+        )
+        (@subcommand qemu =>
+            (about: "Run project in qemu-system-riscv64")
+            (@arg release: --release "Build artifacts in release mode, with optimizations")
+            (@arg payload: --payload +takes_value "Fuse this file as the next stage instead of test-kernel")
+            (@arg dtb: --dtb +takes_value "Append a device tree blob to the fused image")
+            (@arg initrd: --initrd +takes_value "Append an initrd to the fused image")
+        )
+        (@subcommand monitor =>
+            (about: "Show the serial output of the board")
+            (@arg port: --port +takes_value "Serial port of the board, defaults to /dev/ttyUSB0")
+            (@arg baud: --baud +takes_value "Baud rate of the serial port, defaults to 115200")
+            (@arg log: --log +takes_value "Also write the serial output to this file")
+            (@arg success: --success +takes_value +multiple number_of_values(1) "Exit successfully when the output contains this")
+            (@arg failure: --failure +takes_value +multiple number_of_values(1) "Exit with failure when the output contains this")
+            (@arg timeout: --timeout +takes_value "Exit with failure after this many seconds")
+        )
+        (@subcommand upload =>
+            (about: "Upload a next stage to the firmware over the serial port with YMODEM")
+            (@arg FILE: +required "File to upload, ELF files are loaded by their segments")
+            (@arg xmodem: --xmodem "Use XMODEM-1K instead of YMODEM")
+            (@arg monitor: --monitor "Open the serial monitor after uploading")
+            (@arg port: --port +takes_value "Serial port of the board, defaults to /dev/ttyUSB0")
+            (@arg baud: --baud +takes_value "Baud rate of the serial port, defaults to 115200")
+            (@arg log: --log +takes_value "Also write the serial output to this file")
+            (@arg success: --success +takes_value +multiple number_of_values(1) "Exit successfully when the output contains this")
+            (@arg failure: --failure +takes_value +multiple number_of_values(1) "Exit with failure when the output contains this")
+            (@arg timeout: --timeout +takes_value "Exit with failure after this many seconds")
+        )
+        (@subcommand image =>
+            (about: "Package the firmware and the next stage into a TOC1 boot package for SD card or SPI NAND")
+            (@arg release: --release "Build artifacts in release mode, with optimizations")
+            (@arg payload: --payload +takes_value "Package this file as the next stage instead of test-kernel")
+            (@arg dtb: --dtb +takes_value "Package a device tree blob, boot0 passes it to the firmware")
+            (@arg boot0: --boot0 +takes_value "Also add an eGON header to this boot0 binary")
+            (@arg sd: --sd requires[boot0] "Also write an SD card disk image with boot0 and the TOC1 package")
+            (@arg kernel: --kernel +takes_value requires[sd] "Put this kernel and the device tree into a FAT partition of the SD card image")
+            (@arg fat_size: --("fat-size") +takes_value requires[kernel] "Size of the FAT partition in MiB, defaults to 64")
+            (@arg gpt: --gpt requires[kernel] "Use a GPT partition table instead of MBR")
+        )
+        (@subcommand test =>
+            (about: "Run test-kernel in qemu-system-riscv64 and check the result")
+            (@arg release: --release "Build artifacts in release mode, with optimizations")
+            (@arg timeout: --timeout +takes_value "Fail if the test does not finish in this many seconds")
         )
     ).get_matches();
     let mut xtask_env = XtaskEnv {
         compile_mode: CompileMode::Debug,
+        platform: Platform::Nezha,
     };
     println!("xtask: mode: {:?}", xtask_env.compile_mode);
     if let Some(matches) = matches.subcommand_matches("zcore") {
@@ -40,12 +148,22 @@ fn main() {
         }
         xtask_build_sbi(&xtask_env);
         xtask_binary_sbi(&xtask_env);
-        xtask_fuse_zcore(&xtask_env);
+        xtask_fuse_zcore(&xtask_env, &FuseExtra::from_matches(matches));
+        let monitor = if matches.is_present("monitor") {
+            let config = monitor::MonitorConfig::from_matches(matches);
+            let port = monitor::open(&config);
+            Some((config, port))
+        } else {
+            None
+        };
         xtask_run_nezha(&xtask_env);
+        if let Some((config, port)) = monitor {
+            xtask_monitor(&config, port);
+        }
     } else if let Some(_matches) = matches.subcommand_matches("make") {
         xtask_build_sbi(&xtask_env);
         xtask_binary_sbi(&xtask_env);
-    } else if let Some(_matches) = matches.subcommand_matches("nezha"){
+    } else if let Some(matches) = matches.subcommand_matches("nezha"){
         if matches.is_present("release") {
             xtask_env.compile_mode = CompileMode::Release;
         }
@@ -53,9 +171,78 @@ fn main() {
         xtask_binary_sbi(&xtask_env);
         xtask_build_test_kernel(&xtask_env);
         xtask_binary_test_kernel(&xtask_env);
-        xtask_fuse_binary(&xtask_env);
-        xtask_fuse_binary(&xtask_env);
+        xtask_fuse_binary(&xtask_env, &FuseExtra::from_matches(matches));
+        let monitor = if matches.is_present("monitor") {
+            let config = monitor::MonitorConfig::from_matches(matches);
+            let port = monitor::open(&config);
+            Some((config, port))
+        } else {
+            None
+        };
         xtask_run_nezha(&xtask_env);
+        if let Some((config, port)) = monitor {
+            xtask_monitor(&config, port);
+        }
+    } else if let Some(matches) = matches.subcommand_matches("monitor") {
+        let config = monitor::MonitorConfig::from_matches(matches);
+        let port = monitor::open(&config);
+        xtask_monitor(&config, port);
+    } else if let Some(matches) = matches.subcommand_matches("upload") {
+        let config = monitor::MonitorConfig::from_matches(matches);
+        let mut port = monitor::open(&config);
+        let protocol = if matches.is_present("xmodem") {
+            upload::Protocol::Xmodem
+        } else {
+            upload::Protocol::Ymodem
+        };
+        xtask_upload(&mut port, Path::new(matches.value_of("FILE").unwrap()), protocol);
+        if matches.is_present("monitor") {
+            xtask_monitor(&config, port);
+        }
+    } else if let Some(matches) = matches.subcommand_matches("qemu") {
+        if matches.is_present("release") {
+            xtask_env.compile_mode = CompileMode::Release;
+        }
+        xtask_env.platform = Platform::Qemu;
+        xtask_build_sbi(&xtask_env);
+        xtask_binary_sbi(&xtask_env);
+        xtask_build_test_kernel(&xtask_env);
+        xtask_binary_test_kernel(&xtask_env);
+        let test_kernel_binary_path = dist_dir(&xtask_env).join("test-kernel.bin");
+        fuse_image(&xtask_env, &test_kernel_binary_path, &FuseExtra::from_matches(matches));
+        qemu::run(&dist_dir(&xtask_env).join(xtask_env.platform.fused_image_name()));
+    } else if let Some(matches) = matches.subcommand_matches("image") {
+        if matches.is_present("release") {
+            xtask_env.compile_mode = CompileMode::Release;
+        }
+        xtask_build_sbi(&xtask_env);
+        xtask_binary_sbi(&xtask_env);
+        xtask_build_test_kernel(&xtask_env);
+        xtask_binary_test_kernel(&xtask_env);
+        let extra = FuseExtra { initrd: None, ..FuseExtra::from_matches(matches) };
+        let toc1 = xtask_toc1(&xtask_env, &extra);
+        if let Some(boot0) = matches.value_of("boot0") {
+            let boot0 = xtask_egon(&xtask_env, Path::new(boot0));
+            if matches.is_present("sd") {
+                let table = if matches.is_present("gpt") {
+                    disk::PartitionTable::Gpt
+                } else {
+                    disk::PartitionTable::Mbr
+                };
+                let fat_size = matches.value_of("fat_size").unwrap_or(DEFAULT_FAT_SIZE)
+                    .parse::<usize>().expect("FAT size should be a number of MiB");
+                let kernel = matches.value_of("kernel").map(Path::new);
+                xtask_sd_image(&xtask_env, &boot0, &toc1, kernel, extra.dtb.as_deref(), fat_size, table);
+            }
+        }
+    } else if let Some(matches) = matches.subcommand_matches("test") {
+        if matches.is_present("release") {
+            xtask_env.compile_mode = CompileMode::Release;
+        }
+        let timeout = matches.value_of("timeout").unwrap_or(DEFAULT_TEST_TIMEOUT)
+            .parse().expect("timeout should be a number of seconds");
+        xtask_env.platform = Platform::Qemu;
+        xtask_test_qemu(&xtask_env, Duration::from_secs(timeout));
     } else {
         println!("Use `cargo k210` to run, `cargo xtask --help` for help")
     }
@@ -73,21 +260,40 @@ fn xtask_run_nezha(xtask_env: &XtaskEnv) {
     let status = Command::new("xfel")
     .current_dir(project_root().join("xtask"))
     .arg("write")
-    .arg("0x40000000")
-    .arg(dist_dir(xtask_env).join("nezha-fused.bin"))
+    .arg(format!("{:#x}", boot_config::D1.firmware_base))
+    .arg(dist_dir(xtask_env).join(xtask_env.platform.fused_image_name()))
     .status().unwrap();
     if !status.success() {
         panic!("run nezha failed")
     }
     let status = Command::new("xfel")
     .arg("exec")
-    .arg("0x40000000")
+    .arg(format!("{:#x}", boot_config::D1.firmware_base))
     .status().unwrap();
     if !status.success() {
         panic!("run nezha failed")
     }
 }
 
+fn xtask_monitor(config: &monitor::MonitorConfig, port: Box<dyn serialport::SerialPort>) -> ! {
+    let outcome = monitor::run(port, config);
+    process::exit(outcome.exit_code())
+}
+
+// 固件在合并镜像校验失败时等待上传，上传的文件当作下一阶段启动
+fn xtask_upload(port: &mut Box<dyn serialport::SerialPort>, path: &Path, protocol: upload::Protocol) {
+    let data = read_file(path);
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    println!("xtask: waiting for the firmware to request {} ({:#x} bytes) with {:?}", path.display(), data.len(), protocol);
+    match upload::send(port, &name, &data, protocol, &mut std::io::stdout()) {
+        Ok(()) => println!("xtask: upload finished"),
+        Err(e) => {
+            println!("xtask: upload failed: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
 fn xtask_build_sbi(xtask_env: &XtaskEnv) {
     let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
     let mut command = Command::new(cargo);
@@ -99,6 +305,11 @@ fn xtask_build_sbi(xtask_env: &XtaskEnv) {
     }
     command.args(&["--package", "rustsbi-nezha"]);
     command.args(&["--target", DEFAULT_TARGET]);
+    xtask_env.platform.features(&mut command);
+    // 关掉默认特性之后，固件的可选子系统要重新打开
+    if let Platform::Qemu = xtask_env.platform {
+        command.args(["--features", "subsystems"]);
+    }
     let status = command
         .status().unwrap();
     if !status.success() {
@@ -134,6 +345,7 @@ fn xtask_build_test_kernel(xtask_env: &XtaskEnv) {
     }
     command.args(&["--package", "test-kernel"]);
     command.args(&["--target", DEFAULT_TARGET]);
+    xtask_env.platform.features(&mut command);
     let status = command
         .status().unwrap();
     if !status.success() {
@@ -158,32 +370,189 @@ fn xtask_binary_test_kernel(xtask_env: &XtaskEnv) {
     }
 }
 
-fn xtask_fuse_binary(xtask_env: &XtaskEnv) {
-    let sbi_binary_path = dist_dir(xtask_env).join("rustsbi-nezha.bin");
+fn xtask_test_qemu(xtask_env: &XtaskEnv, timeout: Duration) {
+    xtask_build_sbi(xtask_env);
+    xtask_binary_sbi(xtask_env);
+    xtask_build_test_kernel(xtask_env);
+    xtask_binary_test_kernel(xtask_env);
+    let test_kernel_binary_path = dist_dir(xtask_env).join("test-kernel.bin");
+    let extra = FuseExtra { payload: None, dtb: None, initrd: None };
+    fuse_image(xtask_env, &test_kernel_binary_path, &extra);
+    let fused_image = dist_dir(xtask_env).join(xtask_env.platform.fused_image_name());
+    match qemu::run_test(&fused_image, timeout) {
+        qemu::TestOutcome::Success => println!("xtask: test-kernel passed"),
+        qemu::TestOutcome::Failed(line) => {
+            println!("xtask: test-kernel failed: {}", line);
+            process::exit(1);
+        }
+        qemu::TestOutcome::Timeout => {
+            println!("xtask: test-kernel did not finish in {} seconds", timeout.as_secs());
+            process::exit(1);
+        }
+        qemu::TestOutcome::Exited(status) => {
+            println!("xtask: qemu exited before test-kernel finished, status: {:?}", status);
+            process::exit(1);
+        }
+    }
+}
+
+fn xtask_fuse_binary(xtask_env: &XtaskEnv, extra: &FuseExtra) {
    // let test_kernel_binary_path = dist_dir(xtask_env).join("test-kernel.bin");
     let test_kernel_binary_path = project_root().join("zcore.bin");
-    let output_path = dist_dir(xtask_env).join("nezha-fused.bin");
-    let offset = 0x20000;
-    fs::copy(sbi_binary_path, &output_path).expect("copy sbi base");
-    let mut output = fs::OpenOptions::new().read(true).write(true).open(output_path)
-        .expect("open output file");
-    let buf = fs::read(test_kernel_binary_path).expect("read kernel binary");
-    output.seek(SeekFrom::Start(offset)).expect("seek to offset");
-    output.write(&buf).expect("write output");
+    fuse_image(xtask_env, &test_kernel_binary_path, extra);
 }
 
-fn xtask_fuse_zcore(xtask_env: &XtaskEnv) {
-    let sbi_binary_path = dist_dir(xtask_env).join("rustsbi-nezha.bin");
+fn xtask_fuse_zcore(xtask_env: &XtaskEnv, extra: &FuseExtra) {
     //let test_kernel_binary_path = dist_dir(xtask_env).join("test-kernel.bin");
     let test_kernel_binary_path = project_root().join("zcore.bin");
-    let output_path = dist_dir(xtask_env).join("nezha-fused.bin");
-    let offset = 0x20000;
-    fs::copy(sbi_binary_path, &output_path).expect("copy sbi base");
-    let mut output = fs::OpenOptions::new().read(true).write(true).open(output_path)
-        .expect("open output file");
-    let buf = fs::read(test_kernel_binary_path).expect("read kernel binary");
-    output.seek(SeekFrom::Start(offset)).expect("seek to offset");
-    output.write(&buf).expect("write output");
+    fuse_image(xtask_env, &test_kernel_binary_path, extra);
+}
+
+/// 合并镜像中下一阶段之后附带的文件
+#[derive(Debug)]
+struct FuseExtra {
+    payload: Option<PathBuf>,
+    dtb: Option<PathBuf>,
+    initrd: Option<PathBuf>,
+}
+
+impl FuseExtra {
+    fn from_matches(matches: &clap::ArgMatches) -> Self {
+        FuseExtra {
+            payload: matches.value_of("payload").map(PathBuf::from),
+            dtb: matches.value_of("dtb").map(PathBuf::from),
+            initrd: matches.value_of("initrd").map(PathBuf::from),
+        }
+    }
+}
+
+// 附带的文件在镜像中按 4KiB 对齐
+const FUSE_ALIGN: usize = 0x1000;
+
+fn fuse_image(xtask_env: &XtaskEnv, payload_path: &Path, extra: &FuseExtra) {
+    let output_path = dist_dir(xtask_env).join(xtask_env.platform.fused_image_name());
+    let (image, _) = fuse(xtask_env, payload_path, extra);
+    fs::write(&output_path, &image).expect("write output");
+    println!("xtask: fused image {} ({:#x} bytes)", output_path.display(), image.len());
+}
+
+// 合并镜像：固件、头部、下一阶段，以及可选的设备树和 initrd
+fn fuse(xtask_env: &XtaskEnv, payload_path: &Path, extra: &FuseExtra) -> (Vec<u8>, ImageHeader) {
+    let layout = xtask_env.platform.layout();
+    let sbi_binary_path = dist_dir(xtask_env).join("rustsbi-nezha.bin");
+    check_sbi_binary_size(&sbi_binary_path, layout);
+    let mut image = fs::read(sbi_binary_path).expect("read sbi binary");
+    image.resize(layout.payload_offset, 0);
+    let payload = read_file(extra.payload.as_deref().unwrap_or(payload_path));
+    // ELF 文件先整体放到暂存区，固件再从那里按段加载到各自的物理地址
+    let payload_address = if payload.starts_with(b"\x7fELF") {
+        layout.elf_staging_address
+    } else {
+        layout.next_stage_address()
+    };
+    let payload = append_entry(&mut image, &payload, payload_address);
+    // 设备树由固件复制到 DEVICE_TREE_ADDRESS 再修正，这里原地使用即可
+    let device_tree = extra.dtb.as_ref().map(|path| append_entry(&mut image, &read_file(path), 0));
+    let initrd = extra.initrd.as_ref().map(|path| append_entry(&mut image, &read_file(path), layout.initrd_address));
+    let header = ImageHeader { payload, device_tree, initrd };
+    let offset = layout.image_header_offset();
+    image[offset..offset + IMAGE_HEADER_SIZE].copy_from_slice(&header.to_bytes());
+    (image, header)
+}
+
+// 把合并镜像按条目拆成启动包的各项，boot0 把它们加载到合并镜像中原本的位置，
+// 这样固件看到的内存和从 FEL 启动时一样，仍然按镜像头部校验和放置各段
+fn xtask_toc1(xtask_env: &XtaskEnv, extra: &FuseExtra) -> Vec<u8> {
+    let layout = xtask_env.platform.layout();
+    let test_kernel_binary_path = dist_dir(xtask_env).join("test-kernel.bin");
+    let (image, header) = fuse(xtask_env, &test_kernel_binary_path, extra);
+    let item = |name: &str, start: usize, end: usize| sunxi::Toc1Item {
+        name: name.to_string(),
+        run_address: u32::try_from(layout.firmware_base + start).expect("run address out of range"),
+        data: image[start..end].to_vec(),
+    };
+    let entry_item = |name: &str, entry: &ImageEntry| item(name, entry.offset as usize, (entry.offset + entry.size) as usize);
+    // 全志的 boot0 按名字查找各项：跳转到 opensbi，把 dtb 的地址作为参数
+    let mut items = vec![
+        item("opensbi", 0, layout.payload_offset),
+        entry_item("u-boot", &header.payload),
+    ];
+    if let Some(device_tree) = &header.device_tree {
+        items.push(entry_item("dtb", device_tree));
+    }
+    let package = sunxi::build_toc1(&items);
+    match sunxi::parse_toc1(&package) {
+        Ok(parsed) if parsed == items => {},
+        Ok(_) => panic!("TOC1 package does not round-trip"),
+        Err(e) => panic!("TOC1 package does not round-trip: {}", e),
+    }
+    let output_path = dist_dir(xtask_env).join("nezha-toc1.bin");
+    fs::write(&output_path, &package).expect("write output");
+    println!("xtask: TOC1 package {} ({:#x} bytes)", output_path.display(), package.len());
+    for item in &items {
+        println!("xtask:   {:8} {:#010x} {:#x} bytes", item.name, item.run_address, item.data.len());
+    }
+    package
+}
+
+fn xtask_egon(xtask_env: &XtaskEnv, boot0_path: &Path) -> Vec<u8> {
+    let boot0 = read_file(boot0_path);
+    let image = sunxi::build_egon(&boot0);
+    if let Err(e) = sunxi::parse_egon(&image) {
+        panic!("eGON image does not round-trip: {}", e);
+    }
+    let output_path = dist_dir(xtask_env).join("boot0-egon.bin");
+    fs::write(&output_path, &image).expect("write output");
+    println!("xtask: eGON boot0 {} ({:#x} bytes)", output_path.display(), image.len());
+    image
+}
+
+// boot0 和启动包放在分区之外，内核和设备树放在 FAT 分区中，由下一阶段（比如 U-Boot）读取
+fn xtask_sd_image(xtask_env: &XtaskEnv, boot0: &[u8], toc1: &[u8], kernel: Option<&Path>, dtb: Option<&Path>, fat_size: usize, table: disk::PartitionTable) {
+    let fat = kernel.map(|kernel| {
+        let files: Vec<_> = Some(kernel).into_iter().chain(dtb)
+            .map(|path| (path.file_name().unwrap().to_string_lossy().into_owned(), read_file(path)))
+            .collect();
+        for (name, data) in &files {
+            println!("xtask:   FAT {} ({:#x} bytes)", fat::display_name(name), data.len());
+        }
+        let hidden_sectors = (disk::PARTITION_START / 512) as u32;
+        fat::build(fat_size * 1024 * 1024, hidden_sectors, "NEZHA", &files).unwrap_or_else(|e| {
+            println!("xtask: cannot build FAT partition: {}, try a larger --fat-size", e);
+            process::exit(1);
+        })
+    });
+    let image = disk::build(boot0, toc1, fat.as_deref(), table).unwrap_or_else(|e| {
+        println!("xtask: cannot build SD card image: {}", e);
+        process::exit(1);
+    });
+    let output_path = dist_dir(xtask_env).join("nezha-sd.img");
+    fs::write(&output_path, &image).expect("write output");
+    println!("xtask: SD card image {} ({:#x} bytes, {:?})", output_path.display(), image.len(), table);
+}
+
+fn read_file(path: &Path) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| panic!("read {}: {}", path.display(), e))
+}
+
+fn append_entry(image: &mut Vec<u8>, data: &[u8], load_address: usize) -> ImageEntry {
+    let offset = (image.len() + FUSE_ALIGN - 1) & !(FUSE_ALIGN - 1);
+    image.resize(offset, 0);
+    image.extend_from_slice(data);
+    ImageEntry {
+        offset: u32::try_from(offset).expect("image too large"),
+        size: u32::try_from(data.len()).expect("image too large"),
+        load_address: load_address as u64,
+        crc32: crc32(data),
+    }
+}
+
+fn check_sbi_binary_size(sbi_binary_path: &Path, layout: &MemoryLayout) {
+    let size = fs::metadata(sbi_binary_path).expect("read sbi binary metadata").len();
+    if size > layout.image_header_offset() as u64 {
+        println!("sbi binary ({:#x} bytes) overlaps boot image header at offset {:#x}", size, layout.image_header_offset());
+        process::exit(1);
+    }
 }
 
 fn dist_dir(xtask_env: &XtaskEnv) -> PathBuf {
@@ -0,0 +1,307 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, Read, Write},
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use serialport::SerialPort;
+
+pub const DEFAULT_PORT: &str = "/dev/ttyUSB0";
+pub const DEFAULT_BAUD: &str = "115200";
+
+// 读串口的超时，决定了检查总超时的间隔
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub struct MonitorConfig {
+    pub port: String,
+    pub baud: u32,
+    /// 同时把输出写到这个文件
+    pub log: Option<PathBuf>,
+    /// 输出中出现这些字符串时以成功退出
+    pub success: Vec<String>,
+    /// 输出中出现这些字符串时以失败退出
+    pub failure: Vec<String>,
+    pub timeout: Option<Duration>,
+}
+
+impl MonitorConfig {
+    pub fn from_matches(matches: &clap::ArgMatches) -> Self {
+        let values = |name| matches.values_of(name)
+            .map(|values| values.map(String::from).collect())
+            .unwrap_or_default();
+        MonitorConfig {
+            port: matches.value_of("port").unwrap_or(DEFAULT_PORT).to_string(),
+            baud: matches.value_of("baud").unwrap_or(DEFAULT_BAUD)
+                .parse().expect("baud should be a number"),
+            log: matches.value_of("log").map(PathBuf::from),
+            success: values("success"),
+            failure: values("failure"),
+            timeout: matches.value_of("timeout")
+                .map(|secs| Duration::from_secs(secs.parse().expect("timeout should be a number of seconds"))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MonitorOutcome {
+    Success(String),
+    Failure(String),
+    Timeout,
+    /// 串口被关闭，比如开发板断开了
+    Closed(io::Error),
+}
+
+impl fmt::Display for MonitorOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MonitorOutcome::Success(pattern) => write!(f, "success, found {:?}", pattern),
+            MonitorOutcome::Failure(pattern) => write!(f, "failure, found {:?}", pattern),
+            MonitorOutcome::Timeout => write!(f, "timeout"),
+            MonitorOutcome::Closed(e) => write!(f, "serial port closed: {}", e),
+        }
+    }
+}
+
+impl MonitorOutcome {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MonitorOutcome::Success(_) => 0,
+            _ => 1,
+        }
+    }
+}
+
+/// 打开串口。在启动开发板之前打开，才不会错过最开始的输出
+pub fn open(config: &MonitorConfig) -> Box<dyn SerialPort> {
+    serialport::new(config.port.as_str(), config.baud)
+        .timeout(READ_TIMEOUT)
+        .open()
+        .unwrap_or_else(|e| panic!("open serial port {}: {}", config.port, e))
+}
+
+/// 把串口的输出加上时间戳打印出来，并把键盘输入转发到串口
+pub fn run(port: Box<dyn SerialPort>, config: &MonitorConfig) -> MonitorOutcome {
+    let mut writer = port.try_clone().expect("clone serial port");
+    println!("xtask: monitor {} at {} baud", config.port, config.baud);
+    // 终端是行缓冲的，输入在回车之后才会转发；开发板上的 shell 一般以 \r 作为回车
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if writer.write_all(line.as_bytes()).and_then(|_| writer.write_all(b"\r")).is_err() {
+                break;
+            }
+        }
+    });
+    let log = config.log.as_ref().map(|path| {
+        File::create(path).unwrap_or_else(|e| panic!("create log file {}: {}", path.display(), e))
+    });
+    let outcome = watch(port, io::stdout(), log, config);
+    println!();
+    println!("xtask: monitor finished: {}", outcome);
+    outcome
+}
+
+/// 读取 input 直到出现成功或失败的字符串、超时或者输入被关闭
+pub fn watch(mut input: impl Read, mut output: impl Write, mut log: Option<File>, config: &MonitorConfig) -> MonitorOutcome {
+    let start = Instant::now();
+    let mut buf = [0u8; 256];
+    // 当前行的内容，用来匹配字符串；提示符等不以换行结尾的内容也能匹配到
+    let mut line = Vec::new();
+    let mut line_start = true;
+    loop {
+        if let Some(timeout) = config.timeout {
+            if start.elapsed() >= timeout {
+                return MonitorOutcome::Timeout;
+            }
+        }
+        let len = match input.read(&mut buf) {
+            Ok(0) => return MonitorOutcome::Closed(io::ErrorKind::UnexpectedEof.into()),
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return MonitorOutcome::Closed(e),
+        };
+        let mut text = Vec::with_capacity(len * 2);
+        for &byte in &buf[..len] {
+            if line_start {
+                let elapsed = start.elapsed();
+                text.extend_from_slice(format!("[{:5}.{:03}] ", elapsed.as_secs(), elapsed.subsec_millis()).as_bytes());
+                line_start = false;
+            }
+            text.push(byte);
+            if byte == b'\n' {
+                line_start = true;
+            }
+        }
+        let _ = output.write_all(&text).and_then(|_| output.flush());
+        if let Some(log) = log.as_mut() {
+            let _ = log.write_all(&text);
+        }
+        // 每读完一行或者一次读取结束时匹配，同一行中同时出现时失败优先
+        for &byte in &buf[..len] {
+            if byte == b'\n' {
+                if let Some(outcome) = match_line(&line, config) {
+                    return outcome;
+                }
+                line.clear();
+            } else {
+                line.push(byte);
+            }
+        }
+        if let Some(outcome) = match_line(&line, config) {
+            return outcome;
+        }
+    }
+}
+
+fn match_line(line: &[u8], config: &MonitorConfig) -> Option<MonitorOutcome> {
+    let line = String::from_utf8_lossy(line);
+    if let Some(pattern) = config.failure.iter().find(|p| line.contains(p.as_str())) {
+        return Some(MonitorOutcome::Failure(pattern.clone()));
+    }
+    config.success.iter().find(|p| line.contains(p.as_str())).map(|pattern| MonitorOutcome::Success(pattern.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{watch, MonitorConfig, MonitorOutcome};
+    use std::{
+        collections::VecDeque,
+        io::{self, Read, Write},
+        thread,
+        time::Duration,
+    };
+
+    // 按给定的分段返回数据，之后一直超时，像没有输出的串口
+    struct Script(VecDeque<&'static [u8]>);
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.pop_front() {
+                Some(chunk) => {
+                    buf[..chunk.len()].copy_from_slice(chunk);
+                    Ok(chunk.len())
+                }
+                None => {
+                    thread::sleep(Duration::from_millis(10));
+                    Err(io::ErrorKind::TimedOut.into())
+                }
+            }
+        }
+    }
+
+    fn script(chunks: &[&'static [u8]]) -> Script {
+        Script(chunks.iter().copied().collect())
+    }
+
+    fn config(success: &[&str], failure: &[&str], timeout: Option<Duration>) -> MonitorConfig {
+        MonitorConfig {
+            port: String::new(),
+            baud: 115200,
+            log: None,
+            success: success.iter().map(|s| s.to_string()).collect(),
+            failure: failure.iter().map(|s| s.to_string()).collect(),
+            timeout,
+        }
+    }
+
+    // 把 "[    0.012] " 这样的时间戳换成 "[T] "
+    fn strip_timestamps(output: &[u8]) -> String {
+        let output = String::from_utf8_lossy(output);
+        let mut ans = String::new();
+        let mut rest = &output[..];
+        while let Some(start) = rest.find('[') {
+            let end = start + rest[start..].find("] ").expect("unterminated timestamp");
+            let stamp = &rest[start + 1..end];
+            assert!(stamp.trim_start().split('.').all(|part| part.bytes().all(|b| b.is_ascii_digit())), "{:?}", stamp);
+            ans.push_str(&rest[..start]);
+            ans.push_str("[T] ");
+            rest = &rest[end + 2..];
+        }
+        ans.push_str(rest);
+        ans
+    }
+
+    #[test]
+    fn success_pattern() {
+        let mut output = Vec::new();
+        let input = script(&[b"booting\n", b"<< Test-kernel: SBI test SUC", b"CESS, shutdown\n", b"never read\n"]);
+        let outcome = watch(input, &mut output, None, &config(&["SBI test SUCCESS"], &["FAILED"], None));
+        assert!(matches!(outcome, MonitorOutcome::Success(ref p) if p == "SBI test SUCCESS"));
+        assert_eq!(strip_timestamps(&output), "[T] booting\n[T] << Test-kernel: SBI test SUCCESS, shutdown\n");
+    }
+
+    #[test]
+    fn failure_takes_priority() {
+        let mut output = Vec::new();
+        let input = script(&[b"test SUCCESS then FAILED\n"]);
+        let outcome = watch(input, &mut output, None, &config(&["SUCCESS"], &["FAILED"], None));
+        assert!(matches!(outcome, MonitorOutcome::Failure(ref p) if p == "FAILED"));
+        // 不同的行按出现的顺序匹配
+        let input = script(&[b"SUCCESS\nFAILED\n"]);
+        let outcome = watch(input, io::sink(), None, &config(&["SUCCESS"], &["FAILED"], None));
+        assert!(matches!(outcome, MonitorOutcome::Success(_)));
+    }
+
+    #[test]
+    fn pattern_in_unterminated_prompt() {
+        let mut output = Vec::new();
+        let input = script(&[b"U-Boot 2021\n", b"=> "]);
+        let outcome = watch(input, &mut output, None, &config(&["=> "], &[], Some(Duration::from_secs(5))));
+        assert!(matches!(outcome, MonitorOutcome::Success(_)));
+        assert_eq!(strip_timestamps(&output), "[T] U-Boot 2021\n[T] => ");
+    }
+
+    #[test]
+    fn pattern_split_over_lines_does_not_match() {
+        let input = script(&[b"SUC\nCESS\n"]);
+        let outcome = watch(input, io::sink(), None, &config(&["SUCCESS"], &[], Some(Duration::from_millis(50))));
+        assert!(matches!(outcome, MonitorOutcome::Timeout));
+    }
+
+    #[test]
+    fn timeout_without_output() {
+        let outcome = watch(script(&[]), io::sink(), None, &config(&["SUCCESS"], &[], Some(Duration::from_millis(50))));
+        assert!(matches!(outcome, MonitorOutcome::Timeout));
+        assert_eq!(outcome.exit_code(), 1);
+    }
+
+    #[test]
+    fn closed_input() {
+        let outcome = watch(&b"partial output"[..], io::sink(), None, &config(&["SUCCESS"], &[], None));
+        assert!(matches!(outcome, MonitorOutcome::Closed(ref e) if e.kind() == io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn timestamps_only_at_line_starts() {
+        let mut output = Vec::new();
+        let input = script(&[b"hel", b"lo\nwor", b"ld\n\nlast"]);
+        watch(input, &mut output, None, &config(&[], &[], Some(Duration::from_millis(50))));
+        assert_eq!(strip_timestamps(&output), "[T] hello\n[T] world\n[T] \n[T] last");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn over_pseudo_terminal() {
+        let (mut board, host) = serialport::TTYPort::pair().expect("open pty pair");
+        let writer = thread::spawn(move || {
+            board.write_all(b"boot\r\n").unwrap();
+            thread::sleep(Duration::from_millis(50));
+            board.write_all(b"<< Test-kernel: SBI test SUCCESS\r\n").unwrap();
+            // 保持打开，直到监视结束
+            thread::sleep(Duration::from_millis(500));
+        });
+        let mut output = Vec::new();
+        let outcome = watch(host, &mut output, None, &config(&["SBI test SUCCESS"], &["FAILED"], Some(Duration::from_secs(5))));
+        assert!(matches!(outcome, MonitorOutcome::Success(_)), "{}", outcome);
+        assert_eq!(strip_timestamps(&output), "[T] boot\r\n[T] << Test-kernel: SBI test SUCCESS\r\n");
+        writer.join().unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    io::{BufRead, BufReader},
+    path::Path,
+    process::{Command, ExitStatus, Stdio},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+const QEMU: &str = "qemu-system-riscv64";
+// 和固件的 QemuVirt::MAX_HARTS 一致
+const QEMU_SMP: &str = "2";
+
+const SUCCESS_MARKER: &str = "<< Test-kernel: SBI test SUCCESS";
+const FAILURE_MARKER: &str = "!! Test-kernel: SBI test FAILED";
+
+fn qemu_command(fused_image: &Path) -> Command {
+    let mut command = Command::new(QEMU);
+    command.args(["-machine", "virt", "-smp", QEMU_SMP, "-m", "128M"]);
+    command.arg("-bios").arg(fused_image);
+    command
+}
+
+/// 在 QEMU 中交互地运行合并镜像，串口接到当前终端，按 Ctrl-A X 退出
+pub fn run(fused_image: &Path) -> ExitStatus {
+    qemu_command(fused_image)
+        .arg("-nographic")
+        .status()
+        .unwrap_or_else(|e| panic!("run {}: {}", QEMU, e))
+}
+
+#[derive(Debug)]
+pub enum TestOutcome {
+    Success,
+    Failed(String),
+    Timeout,
+    /// QEMU 在输出结果之前退出了
+    Exited(Option<ExitStatus>),
+}
+
+/// 测试内核输出的一行是否表示测试结束
+pub fn parse_marker(line: &str) -> Option<TestOutcome> {
+    if line.contains(SUCCESS_MARKER) {
+        Some(TestOutcome::Success)
+    } else if line.contains(FAILURE_MARKER) {
+        Some(TestOutcome::Failed(line.trim().to_string()))
+    } else {
+        None
+    }
+}
+
+/// 不带界面地运行测试内核，转发串口输出，直到看到结果、QEMU 退出或者超时
+pub fn run_test(fused_image: &Path, timeout: Duration) -> TestOutcome {
+    let mut child = qemu_command(fused_image)
+        .args(["-display", "none", "-monitor", "none", "-serial", "stdio"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|e| panic!("run {}: {}", QEMU, e));
+    let stdout = child.stdout.take().unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stdout);
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) | Err(_) => break,
+                // 测试内核的输出不一定是合法的 UTF-8
+                Ok(_) => if tx.send(String::from_utf8_lossy(&buf).into_owned()).is_err() {
+                    break
+                },
+            }
+        }
+    });
+    let deadline = Instant::now() + timeout;
+    let outcome = loop {
+        match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(line) => {
+                print!("{}", line);
+                if let Some(outcome) = parse_marker(&line) {
+                    break outcome;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => break TestOutcome::Timeout,
+            Err(RecvTimeoutError::Disconnected) => break TestOutcome::Exited(child.wait().ok()),
+        }
+    };
+    let _ = child.kill();
+    let _ = child.wait();
+    outcome
+}
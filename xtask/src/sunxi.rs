@@ -0,0 +1,298 @@
+//! 全志 BROM 和 boot0 使用的镜像格式：eGON 格式的 boot0，以及 TOC1 格式的启动包。
+//!
+//! 两种格式的校验和相同：校验和字段先填入 STAMP_VALUE，再把整个镜像按小端 u32 累加。
+use std::{convert::TryFrom, fmt};
+
+const STAMP_VALUE: u32 = 0x5f0a_6c39;
+
+const EGON_MAGIC: &[u8; 8] = b"eGON.BT0";
+// 和 U-Boot 的 boot_file_head 一样大，boot0 的代码紧跟在头部之后
+const EGON_HEADER_SIZE: usize = 0x60;
+// BROM 按块读取 boot0，长度按 8KiB 对齐
+const EGON_ALIGN: usize = 0x2000;
+
+const TOC1_NAME: &[u8] = b"sunxi-package";
+const TOC1_MAGIC: u32 = 0x8911_9800;
+const TOC1_MAIN_END: u32 = 0x3b45_494d; // "MIE;"
+const TOC1_ITEM_END: u32 = 0x3b45_4949; // "IIE;"
+const TOC1_HEADER_SIZE: usize = 64;
+const TOC1_ITEM_SIZE: usize = 368;
+const TOC1_ITEM_NAME_SIZE: usize = 64;
+// 二进制文件，boot0 把它加载到 run_addr
+const TOC1_TYPE_BINARY: u32 = 3;
+// 各项数据按扇区对齐，整个启动包按 16KiB 对齐
+const TOC1_ITEM_ALIGN: usize = 0x200;
+const TOC1_ALIGN: usize = 0x4000;
+
+#[derive(Debug)]
+pub enum SunxiError {
+    Truncated,
+    BadMagic,
+    BadLength(u32),
+    BadChecksum { expected: u32, found: u32 },
+    BadItem(usize),
+}
+
+impl fmt::Display for SunxiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SunxiError::Truncated => write!(f, "image is truncated"),
+            SunxiError::BadMagic => write!(f, "bad magic"),
+            SunxiError::BadLength(length) => write!(f, "bad length {:#x}", length),
+            SunxiError::BadChecksum { expected, found } => write!(f, "checksum mismatch, expected {:#010x}, found {:#010x}", expected, found),
+            SunxiError::BadItem(index) => write!(f, "item {} is out of the package", index),
+        }
+    }
+}
+
+/// 给 boot0 加上 eGON 头部。已经带有 eGON 头部的 boot0（比如全志提供的）只重新计算长度和校验和
+pub fn build_egon(boot0: &[u8]) -> Vec<u8> {
+    let mut image = if boot0.len() >= 12 && &boot0[4..12] == EGON_MAGIC {
+        boot0.to_vec()
+    } else {
+        let mut image = vec![0u8; EGON_HEADER_SIZE];
+        image[0..4].copy_from_slice(&riscv_jump(EGON_HEADER_SIZE as u32).to_le_bytes());
+        image[4..12].copy_from_slice(EGON_MAGIC);
+        image.extend_from_slice(boot0);
+        image
+    };
+    image.resize(align_up(image.len(), EGON_ALIGN), 0);
+    let length = u32::try_from(image.len()).expect("boot0 too large");
+    write_u32(&mut image, 16, length);
+    seal(&mut image, 12);
+    image
+}
+
+/// 校验 eGON 格式的 boot0，返回头部之后的代码
+pub fn parse_egon(image: &[u8]) -> Result<&[u8], SunxiError> {
+    if image.len() < EGON_HEADER_SIZE {
+        return Err(SunxiError::Truncated);
+    }
+    if &image[4..12] != EGON_MAGIC {
+        return Err(SunxiError::BadMagic);
+    }
+    let length = read_u32(image, 16);
+    if (length as usize) < EGON_HEADER_SIZE {
+        return Err(SunxiError::BadLength(length));
+    }
+    let image = checked_image(image, length)?;
+    verify_checksum(image, 12)?;
+    Ok(&image[EGON_HEADER_SIZE..])
+}
+
+/// 启动包中的一项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Toc1Item {
+    pub name: String,
+    pub run_address: u32,
+    pub data: Vec<u8>,
+}
+
+/// 把各项打包成 TOC1 格式的启动包
+pub fn build_toc1(items: &[Toc1Item]) -> Vec<u8> {
+    let headers_size = TOC1_HEADER_SIZE + items.len() * TOC1_ITEM_SIZE;
+    let mut package = vec![0u8; headers_size];
+    package[..TOC1_NAME.len()].copy_from_slice(TOC1_NAME);
+    write_u32(&mut package, 16, TOC1_MAGIC);
+    write_u32(&mut package, 32, items.len() as u32);
+    write_u32(&mut package, 60, TOC1_MAIN_END);
+    for (index, item) in items.iter().enumerate() {
+        assert!(item.name.len() < TOC1_ITEM_NAME_SIZE, "item name {} too long", item.name);
+        let offset = align_up(package.len(), TOC1_ITEM_ALIGN);
+        package.resize(offset, 0);
+        package.extend_from_slice(&item.data);
+        let header = TOC1_HEADER_SIZE + index * TOC1_ITEM_SIZE;
+        package[header..header + item.name.len()].copy_from_slice(item.name.as_bytes());
+        write_u32(&mut package, header + 64, u32::try_from(offset).expect("package too large"));
+        write_u32(&mut package, header + 68, u32::try_from(item.data.len()).expect("package too large"));
+        write_u32(&mut package, header + 76, TOC1_TYPE_BINARY);
+        write_u32(&mut package, header + 80, item.run_address);
+        write_u32(&mut package, header + TOC1_ITEM_SIZE - 4, TOC1_ITEM_END);
+    }
+    package.resize(align_up(package.len(), TOC1_ALIGN), 0);
+    let length = u32::try_from(package.len()).expect("package too large");
+    write_u32(&mut package, 36, length);
+    seal(&mut package, 20);
+    package
+}
+
+/// 校验 TOC1 格式的启动包，返回其中的各项
+pub fn parse_toc1(package: &[u8]) -> Result<Vec<Toc1Item>, SunxiError> {
+    if package.len() < TOC1_HEADER_SIZE {
+        return Err(SunxiError::Truncated);
+    }
+    if read_u32(package, 16) != TOC1_MAGIC || read_u32(package, 60) != TOC1_MAIN_END {
+        return Err(SunxiError::BadMagic);
+    }
+    let package = checked_image(package, read_u32(package, 36))?;
+    verify_checksum(package, 20)?;
+    let count = read_u32(package, 32) as usize;
+    if TOC1_HEADER_SIZE + count * TOC1_ITEM_SIZE > package.len() {
+        return Err(SunxiError::Truncated);
+    }
+    let mut items = Vec::with_capacity(count);
+    for index in 0..count {
+        let header = &package[TOC1_HEADER_SIZE + index * TOC1_ITEM_SIZE..][..TOC1_ITEM_SIZE];
+        if read_u32(header, TOC1_ITEM_SIZE - 4) != TOC1_ITEM_END {
+            return Err(SunxiError::BadMagic);
+        }
+        let name = &header[..TOC1_ITEM_NAME_SIZE];
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(TOC1_ITEM_NAME_SIZE)];
+        let offset = read_u32(header, 64) as usize;
+        let size = read_u32(header, 68) as usize;
+        let data = package.get(offset..offset + size).ok_or(SunxiError::BadItem(index))?;
+        items.push(Toc1Item {
+            name: String::from_utf8_lossy(name).into_owned(),
+            run_address: read_u32(header, 80),
+            data: data.to_vec(),
+        });
+    }
+    Ok(items)
+}
+
+// RISC-V 的 j 指令，跳过头部
+fn riscv_jump(offset: u32) -> u32 {
+    0x6f | ((offset & 0x10_0000) << 11) | ((offset & 0x7fe) << 20) | ((offset & 0x800) << 9) | (offset & 0xf_f000)
+}
+
+fn checked_image(image: &[u8], length: u32) -> Result<&[u8], SunxiError> {
+    if length & 3 != 0 {
+        return Err(SunxiError::BadLength(length));
+    }
+    image.get(..length as usize).ok_or(SunxiError::Truncated)
+}
+
+fn seal(image: &mut [u8], checksum_offset: usize) {
+    write_u32(image, checksum_offset, STAMP_VALUE);
+    let checksum = checksum(image);
+    write_u32(image, checksum_offset, checksum);
+}
+
+fn verify_checksum(image: &[u8], checksum_offset: usize) -> Result<(), SunxiError> {
+    let found = read_u32(image, checksum_offset);
+    let mut stamped = image.to_vec();
+    write_u32(&mut stamped, checksum_offset, STAMP_VALUE);
+    let expected = checksum(&stamped);
+    if expected != found {
+        return Err(SunxiError::BadChecksum { expected, found });
+    }
+    Ok(())
+}
+
+fn checksum(image: &[u8]) -> u32 {
+    image.chunks(4).fold(0u32, |sum, word| sum.wrapping_add(read_u32(word, 0)))
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(name: &str, run_address: u32, size: usize) -> Toc1Item {
+        let data = (0..size).map(|i| (i as u8).wrapping_mul(31) ^ name.len() as u8).collect();
+        Toc1Item { name: name.to_string(), run_address, data }
+    }
+
+    #[test]
+    fn toc1_round_trip() {
+        let items = vec![item("opensbi", 0x4000_0000, 0x1234), item("u-boot", 0x4020_0000, 0x3), item("dtb", 0x4420_0000, 0x201)];
+        let package = build_toc1(&items);
+        assert_eq!(package.len() % TOC1_ALIGN, 0);
+        assert_eq!(read_u32(&package, 36) as usize, package.len());
+        for index in 0..items.len() {
+            let offset = read_u32(&package, TOC1_HEADER_SIZE + index * TOC1_ITEM_SIZE + 64) as usize;
+            assert_eq!(offset % TOC1_ITEM_ALIGN, 0);
+        }
+        assert_eq!(parse_toc1(&package).unwrap(), items);
+    }
+
+    #[test]
+    fn toc1_bad_checksum() {
+        let mut package = build_toc1(&[item("opensbi", 0x4000_0000, 0x100)]);
+        let last = package.len() - 1;
+        package[last] ^= 1;
+        assert!(matches!(parse_toc1(&package), Err(SunxiError::BadChecksum { .. })));
+    }
+
+    #[test]
+    fn toc1_truncated_and_bad_item() {
+        let package = build_toc1(&[item("opensbi", 0x4000_0000, 0x100)]);
+        assert!(matches!(parse_toc1(&package[..32]), Err(SunxiError::Truncated)));
+        assert!(matches!(parse_toc1(&package[..package.len() - 4]), Err(SunxiError::Truncated)));
+        // 数据超出启动包的项，重新计算校验和之后仍然是错的
+        let mut package = package;
+        let length = package.len() as u32;
+        write_u32(&mut package, TOC1_HEADER_SIZE + 64, length - 0x80);
+        seal(&mut package, 20);
+        assert!(matches!(parse_toc1(&package), Err(SunxiError::BadItem(0))));
+        write_u32(&mut package, 36, length - 2);
+        assert!(matches!(parse_toc1(&package), Err(SunxiError::BadLength(_))));
+    }
+
+    #[test]
+    fn egon_round_trip() {
+        let boot0 = item("boot0", 0, 0x2345).data;
+        let image = build_egon(&boot0);
+        assert_eq!(image.len(), 0x4000);
+        assert_eq!(read_u32(&image, 0), riscv_jump(EGON_HEADER_SIZE as u32));
+        let code = parse_egon(&image).unwrap();
+        assert_eq!(code[..boot0.len()], boot0[..]);
+        assert!(code[boot0.len()..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn egon_reseal_keeps_header() {
+        let mut boot0 = build_egon(&item("boot0", 0, 0x100).data);
+        // 全志的 boot0 在头部中有别的字段，重新计算时保持不变
+        write_u32(&mut boot0, 0x20, 0xdead_beef);
+        boot0.extend_from_slice(&[0x55; 0x10]);
+        let image = build_egon(&boot0);
+        assert_eq!(image.len(), 0x4000);
+        assert_eq!(read_u32(&image, 16), 0x4000);
+        assert_eq!(read_u32(&image, 0x20), 0xdead_beef);
+        assert_eq!(image[..12], boot0[..12]);
+        assert_eq!(image[0x2000..0x2010], [0x55; 0x10]);
+        parse_egon(&image).unwrap();
+        // 再加一次头部得到同样的结果
+        assert_eq!(build_egon(&image), image);
+    }
+
+    #[test]
+    fn egon_errors() {
+        let image = build_egon(&[0x13; 0x40]);
+        assert!(matches!(parse_egon(&image[..0x40]), Err(SunxiError::Truncated)));
+        assert!(matches!(parse_egon(&image[..0x1000]), Err(SunxiError::Truncated)));
+        let mut bad = image.clone();
+        bad[0x100] ^= 0x80;
+        assert!(matches!(parse_egon(&bad), Err(SunxiError::BadChecksum { .. })));
+        let mut bad = image.clone();
+        bad[4] = b'x';
+        assert!(matches!(parse_egon(&bad), Err(SunxiError::BadMagic)));
+        let mut bad = image;
+        write_u32(&mut bad, 16, 0x10);
+        assert!(matches!(parse_egon(&bad), Err(SunxiError::BadLength(0x10))));
+    }
+
+    #[test]
+    fn riscv_jump_known_answers() {
+        // j 0x60、j 0x7fe、j 0x800、j 0x1000、j 0x2468
+        assert_eq!(riscv_jump(0x60), 0x0600_006f);
+        assert_eq!(riscv_jump(0x7fe), 0x7fe0_006f);
+        assert_eq!(riscv_jump(0x800), 0x0010_006f);
+        assert_eq!(riscv_jump(0x1000), 0x0000_106f);
+        assert_eq!(riscv_jump(0x2468), 0x4680_206f);
+    }
+}
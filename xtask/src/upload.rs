@@ -0,0 +1,348 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const CAN: u8 = 0x18;
+const CRC_REQUEST: u8 = b'C';
+// 最后一块用它填充
+const PADDING: u8 = 0x1a;
+
+const BLOCK_SIZE: usize = 1024;
+// 同一块重发这么多次之后放弃
+const MAX_RETRIES: usize = 10;
+// 固件每秒请求一次，等待开始的时间长一些，留出给开发板上电的时间
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Ymodem,
+    /// XMODEM-1K，没有文件名和文件大小
+    Xmodem,
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    /// 接收方没有请求开始传输
+    NotStarted,
+    Cancelled,
+    TooManyRetries(usize),
+    Io(io::Error),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::NotStarted => write!(f, "receiver did not request the transfer"),
+            UploadError::Cancelled => write!(f, "transfer cancelled by receiver"),
+            UploadError::TooManyRetries(block) => write!(f, "too many retries on block {}", block),
+            UploadError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for UploadError {
+    fn from(e: io::Error) -> Self {
+        UploadError::Io(e)
+    }
+}
+
+/// 发送一个文件。等待接收方请求之前收到的内容是固件的输出，原样写到 echo
+pub fn send(port: &mut (impl Read + Write), name: &str, data: &[u8], protocol: Protocol, echo: &mut impl Write) -> Result<(), UploadError> {
+    wait_for_request(port, echo)?;
+    if protocol == Protocol::Ymodem {
+        send_block(port, 0, &file_info(name, data.len()), 0)?;
+        // 文件信息块确认之后，接收方再次请求开始发送数据
+        if read_response(port, RESPONSE_TIMEOUT)? != Some(CRC_REQUEST) {
+            return Err(UploadError::NotStarted);
+        }
+    }
+    let total = data.chunks(BLOCK_SIZE).len();
+    for (index, chunk) in data.chunks(BLOCK_SIZE).enumerate() {
+        let mut block = [PADDING; BLOCK_SIZE];
+        block[..chunk.len()].copy_from_slice(chunk);
+        send_block(port, (index + 1) as u8, &block, index + 1)?;
+        print!("\rxtask: upload block {}/{}", index + 1, total);
+        let _ = io::stdout().flush();
+    }
+    println!();
+    send_eot(port)?;
+    if protocol == Protocol::Ymodem {
+        // 文件名为空的块表示没有更多文件
+        if read_response(port, RESPONSE_TIMEOUT)? != Some(CRC_REQUEST) {
+            return Err(UploadError::NotStarted);
+        }
+        send_block(port, 0, &[0u8; 128], 0)?;
+    }
+    Ok(())
+}
+
+// YMODEM 的文件信息：文件名、0、十进制的文件大小
+fn file_info(name: &str, size: usize) -> [u8; 128] {
+    let mut block = [0u8; 128];
+    let info = format!("{}\0{}", name, size);
+    let len = info.len().min(block.len() - 1);
+    block[..len].copy_from_slice(&info.as_bytes()[..len]);
+    block
+}
+
+fn wait_for_request(port: &mut (impl Read + Write), echo: &mut impl Write) -> Result<(), UploadError> {
+    let start = Instant::now();
+    let mut byte = [0u8];
+    while start.elapsed() < START_TIMEOUT {
+        match port.read(&mut byte) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(_) if byte[0] == CRC_REQUEST => {
+                // 丢掉等待期间积累的请求，它们会被当成对第一块的回复
+                while let Ok(1) = port.read(&mut byte) {}
+                return Ok(());
+            }
+            Ok(_) => {
+                let _ = echo.write_all(&byte).and_then(|_| echo.flush());
+            }
+            Err(e) if is_timeout(&e) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err(UploadError::NotStarted)
+}
+
+fn send_block(port: &mut (impl Read + Write), seq: u8, data: &[u8], index: usize) -> Result<(), UploadError> {
+    let mut packet = Vec::with_capacity(data.len() + 5);
+    packet.push(if data.len() == BLOCK_SIZE { STX } else { SOH });
+    packet.push(seq);
+    packet.push(!seq);
+    packet.extend_from_slice(data);
+    packet.extend_from_slice(&crc16(data).to_be_bytes());
+    for _ in 0..MAX_RETRIES {
+        port.write_all(&packet)?;
+        port.flush()?;
+        loop {
+            match read_response(port, RESPONSE_TIMEOUT)? {
+                Some(ACK) => return Ok(()),
+                Some(CAN) => return Err(UploadError::Cancelled),
+                // 接收方在收到第一块之前可能又请求了一次，继续等待回复
+                Some(CRC_REQUEST) if seq == 0 => {}
+                // NAK、超时或者别的内容都重发
+                _ => break,
+            }
+        }
+    }
+    cancel(port);
+    Err(UploadError::TooManyRetries(index))
+}
+
+// YMODEM 的接收方对第一个 EOT 回复 NAK，XMODEM 的接收方直接回复 ACK
+fn send_eot(port: &mut (impl Read + Write)) -> Result<(), UploadError> {
+    for _ in 0..MAX_RETRIES {
+        port.write_all(&[EOT])?;
+        port.flush()?;
+        match read_response(port, RESPONSE_TIMEOUT)? {
+            Some(ACK) => return Ok(()),
+            Some(CAN) => return Err(UploadError::Cancelled),
+            _ => {}
+        }
+    }
+    cancel(port);
+    Err(UploadError::TooManyRetries(0))
+}
+
+// 读取接收方的一个回复，超时返回 None
+fn read_response(port: &mut impl Read, timeout: Duration) -> Result<Option<u8>, UploadError> {
+    let start = Instant::now();
+    let mut byte = [0u8];
+    while start.elapsed() < timeout {
+        match port.read(&mut byte) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(e) if is_timeout(&e) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(None)
+}
+
+fn cancel(port: &mut impl Write) {
+    let _ = port.write_all(&[CAN, CAN]).and_then(|_| port.flush());
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock)
+}
+
+// CRC-16/XMODEM，和固件中的一致
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{send, Protocol, UploadError, CAN};
+    use serialport::{SerialPort, TTYPort};
+    use std::{
+        io::{self, Read, Write},
+        sync::{mpsc, OnceLock},
+        thread,
+        time::{Duration, Instant},
+    };
+    use ymodem::{Receiver, YmodemError};
+
+    const NAK: u8 = 0x15;
+
+    // 固件一侧：伪终端的一端接到 YMODEM 接收方
+    struct Board<'a>(&'a mut TTYPort);
+
+    impl ymodem::Serial for Board<'_> {
+        fn try_read(&mut self) -> Option<u8> {
+            let mut byte = [0u8];
+            match self.0.read(&mut byte) {
+                Ok(1) => Some(byte[0]),
+                _ => None,
+            }
+        }
+
+        fn write(&mut self, byte: u8) {
+            self.0.write_all(&[byte]).and_then(|_| self.0.flush()).unwrap();
+        }
+    }
+
+    fn now() -> u64 {
+        static START: OnceLock<Instant> = OnceLock::new();
+        START.get_or_init(Instant::now).elapsed().as_micros() as u64
+    }
+
+    type Received = (Result<usize, YmodemError>, Vec<u8>);
+
+    // 在另一个线程中接收，返回接收的结果和缓冲区。
+    // 伪终端的一端关闭之后另一端读不到还没读取的数据，所以接收完之后等发送方结束再关闭
+    fn receiver(mut board: TTYPort, capacity: usize) -> (thread::JoinHandle<Received>, mpsc::Sender<()>) {
+        let (done, wait) = mpsc::channel();
+        board.set_timeout(Duration::from_millis(1)).unwrap();
+        let handle = thread::spawn(move || {
+            let mut buf = vec![0u8; capacity];
+            let ans = Receiver::new(Board(&mut board), now, 1_000_000).receive(&mut buf);
+            let _ = wait.recv();
+            (ans, buf)
+        });
+        (handle, done)
+    }
+
+    fn finish((handle, done): (thread::JoinHandle<Received>, mpsc::Sender<()>)) -> Received {
+        drop(done);
+        handle.join().unwrap()
+    }
+
+    fn file(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i * 7 + i / 251) as u8).collect()
+    }
+
+    #[test]
+    fn ymodem_transfer() {
+        let (board, mut host) = TTYPort::pair().unwrap();
+        let receiver = receiver(board, 0x4000);
+        let data = file(3000);
+        send(&mut host, "kernel.bin", &data, Protocol::Ymodem, &mut io::sink()).unwrap();
+        let (ans, buf) = finish(receiver);
+        assert_eq!(ans.unwrap(), data.len());
+        assert_eq!(buf[..data.len()], data[..]);
+    }
+
+    #[test]
+    fn xmodem_transfer_keeps_padding() {
+        let (board, mut host) = TTYPort::pair().unwrap();
+        let receiver = receiver(board, 0x4000);
+        let data = file(3000);
+        send(&mut host, "kernel.bin", &data, Protocol::Xmodem, &mut io::sink()).unwrap();
+        let (ans, buf) = finish(receiver);
+        assert_eq!(ans.unwrap(), 3072);
+        assert_eq!(buf[..data.len()], data[..]);
+        assert!(buf[data.len()..3072].iter().all(|&b| b == 0x1a));
+    }
+
+    // 破坏发送的第 corrupt 个数据包中的一个字节，记录接收方的回复
+    struct Corrupting {
+        port: TTYPort,
+        corrupt: usize,
+        packets: usize,
+        replies: Vec<u8>,
+    }
+
+    impl Read for Corrupting {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.port.read(buf)?;
+            self.replies.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+    }
+
+    impl Write for Corrupting {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if buf.len() > 1 {
+                self.packets += 1;
+                if self.packets == self.corrupt {
+                    let mut packet = buf.to_vec();
+                    packet[100] ^= 0xff;
+                    return self.port.write_all(&packet).map(|_| buf.len());
+                }
+            }
+            self.port.write_all(buf).map(|_| buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.port.flush()
+        }
+    }
+
+    #[test]
+    fn corrupted_block_is_retried() {
+        let (board, host) = TTYPort::pair().unwrap();
+        let receiver = receiver(board, 0x4000);
+        let data = file(2048);
+        // 第 1 个包是文件信息，第 2 个是第一块数据
+        let mut host = Corrupting { port: host, corrupt: 2, packets: 0, replies: Vec::new() };
+        send(&mut host, "kernel.bin", &data, Protocol::Ymodem, &mut io::sink()).unwrap();
+        let (ans, buf) = finish(receiver);
+        assert_eq!(ans.unwrap(), data.len());
+        assert_eq!(buf[..data.len()], data[..]);
+        // 文件信息、三块数据（其中一块重发）、结束的空块
+        assert_eq!(host.packets, 5);
+        assert!(host.replies.contains(&NAK));
+    }
+
+    #[test]
+    fn sender_cancels() {
+        let (board, mut host) = TTYPort::pair().unwrap();
+        let receiver = receiver(board, 0x4000);
+        let mut byte = [0u8];
+        while !matches!(host.read(&mut byte), Ok(1) if byte[0] == b'C') {}
+        host.write_all(&[CAN, CAN]).unwrap();
+        let (ans, _) = finish(receiver);
+        assert!(matches!(ans, Err(YmodemError::Cancelled)));
+    }
+
+    #[test]
+    fn file_too_large() {
+        let (board, mut host) = TTYPort::pair().unwrap();
+        let receiver = receiver(board, 2048);
+        let data = file(3000);
+        let ans = send(&mut host, "kernel.bin", &data, Protocol::Ymodem, &mut io::sink());
+        assert!(matches!(ans, Err(UploadError::Cancelled)), "{:?}", ans);
+        let (ans, buf) = finish(receiver);
+        assert!(matches!(ans, Err(YmodemError::TooLarge)));
+        // 放得下的两块已经收到了
+        assert_eq!(buf[..], data[..2048]);
+    }
+}
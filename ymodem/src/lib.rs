@@ -0,0 +1,265 @@
+//! 通过串口以 YMODEM 协议接收文件，也兼容 XMODEM-1K。
+//!
+//! 接收期间串口只能用来传输，不能打印任何内容。
+//! 串口和时钟由使用者提供，所以可以在主机上测试。
+#![no_std]
+
+use core::fmt;
+
+/// 接收方使用的串口
+pub trait Serial {
+    /// 读取一个字节，没有数据时立即返回 None
+    fn try_read(&mut self) -> Option<u8>;
+    /// 写出一个字节，等到它发送出去再返回
+    fn write(&mut self, byte: u8);
+}
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+// 接收方请求以 CRC16 校验的方式发送
+const CRC_REQUEST: u8 = b'C';
+
+// 连续出错这么多次之后放弃
+const MAX_ERRORS: usize = 10;
+
+#[derive(Debug)]
+pub enum YmodemError {
+    /// 发送方取消了传输
+    Cancelled,
+    /// 文件超过了接收缓冲区的大小
+    TooLarge,
+    TooManyErrors,
+}
+
+impl fmt::Display for YmodemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            YmodemError::Cancelled => write!(f, "transfer cancelled by sender"),
+            YmodemError::TooLarge => write!(f, "file too large"),
+            YmodemError::TooManyErrors => write!(f, "too many errors"),
+        }
+    }
+}
+
+enum Packet {
+    Block { seq: u8, len: usize },
+    Eot,
+    Cancel,
+    Error,
+}
+
+/// 接收方，now 返回以 ticks_per_second 为频率的当前时间
+pub struct Receiver<S> {
+    serial: S,
+    now: fn() -> u64,
+    ticks_per_second: u64,
+}
+
+impl<S: Serial> Receiver<S> {
+    pub fn new(serial: S, now: fn() -> u64, ticks_per_second: u64) -> Self {
+        Self { serial, now, ticks_per_second }
+    }
+
+    /// 接收一个文件放到 buf 中，返回文件的大小。
+    ///
+    /// 每秒发送一次 'C' 等待发送方开始，没有超时。XMODEM-1K 没有文件大小，返回的大小包括最后一块的填充。
+    pub fn receive(&mut self, buf: &mut [u8]) -> Result<usize, YmodemError> {
+        let mut block = [0u8; 1024];
+        // 等待第一块：YMODEM 是序号为 0 的文件信息，XMODEM 直接是序号为 1 的数据
+        let (ymodem, size, mut received) = loop {
+            self.send(CRC_REQUEST);
+            match self.read_packet(&mut block) {
+                Some(Packet::Block { seq: 0, len }) => {
+                    let size = parse_file_info(&block[..len]);
+                    self.send(ACK);
+                    self.send(CRC_REQUEST);
+                    break (true, size, 0);
+                }
+                Some(Packet::Block { seq: 1, len }) => {
+                    self.store(buf, 0, &block[..len])?;
+                    self.send(ACK);
+                    break (false, None, len);
+                }
+                Some(Packet::Cancel) => return Err(YmodemError::Cancelled),
+                Some(_) => self.purge(),
+                None => {}
+            }
+        };
+        let mut next_seq: u8 = if ymodem { 1 } else { 2 };
+        let mut eot_seen = false;
+        let mut errors = 0;
+        loop {
+            match self.read_packet(&mut block) {
+                Some(Packet::Block { seq, len }) if seq == next_seq => {
+                    self.store(buf, received, &block[..len])?;
+                    received += len;
+                    next_seq = next_seq.wrapping_add(1);
+                    errors = 0;
+                    self.send(ACK);
+                }
+                // 发送方没有收到上一块的 ACK，重新发送了它
+                Some(Packet::Block { seq, .. }) if seq == next_seq.wrapping_sub(1) => self.send(ACK),
+                // YMODEM 要对第一个 EOT 回复 NAK，发送方再次发送 EOT 才算结束
+                Some(Packet::Eot) if ymodem && !eot_seen => {
+                    eot_seen = true;
+                    self.send(NAK);
+                }
+                Some(Packet::Eot) => {
+                    self.send(ACK);
+                    break;
+                }
+                Some(Packet::Cancel) => return Err(YmodemError::Cancelled),
+                Some(_) | None => {
+                    errors += 1;
+                    if errors > MAX_ERRORS {
+                        self.cancel();
+                        return Err(YmodemError::TooManyErrors);
+                    }
+                    self.purge();
+                    self.send(NAK);
+                }
+            }
+        }
+        if ymodem {
+            self.finish_batch(&mut block);
+        }
+        Ok(size.map_or(received, |size| size.min(received)))
+    }
+
+    // YMODEM 最后发送一个文件名为空的块表示没有更多的文件，只接收第一个文件
+    fn finish_batch(&mut self, block: &mut [u8; 1024]) {
+        for _ in 0..MAX_ERRORS {
+            self.send(CRC_REQUEST);
+            match self.read_packet(block) {
+                Some(Packet::Block { seq: 0, len }) => {
+                    if block[..len][0] == 0 {
+                        self.send(ACK);
+                    } else {
+                        self.cancel();
+                    }
+                    return;
+                }
+                Some(Packet::Cancel) => return,
+                Some(_) => self.purge(),
+                None => {}
+            }
+        }
+    }
+
+    fn store(&mut self, buf: &mut [u8], offset: usize, data: &[u8]) -> Result<(), YmodemError> {
+        match buf.get_mut(offset..offset + data.len()) {
+            Some(dst) => {
+                dst.copy_from_slice(data);
+                Ok(())
+            }
+            None => {
+                self.cancel();
+                Err(YmodemError::TooLarge)
+            }
+        }
+    }
+
+    // 读取一块；一秒内没有收到任何数据时返回 None
+    fn read_packet(&mut self, block: &mut [u8; 1024]) -> Option<Packet> {
+        let len = match self.read_byte()? {
+            SOH => 128,
+            STX => 1024,
+            EOT => return Some(Packet::Eot),
+            CAN => return Some(Packet::Cancel),
+            _ => return Some(Packet::Error),
+        };
+        let mut header = [0u8; 2];
+        for byte in header.iter_mut() {
+            *byte = match self.read_byte() {
+                Some(byte) => byte,
+                None => return Some(Packet::Error),
+            };
+        }
+        for byte in block[..len].iter_mut() {
+            *byte = match self.read_byte() {
+                Some(byte) => byte,
+                None => return Some(Packet::Error),
+            };
+        }
+        let (hi, lo) = match (self.read_byte(), self.read_byte()) {
+            (Some(hi), Some(lo)) => (hi, lo),
+            _ => return Some(Packet::Error),
+        };
+        if header[0] != !header[1] || crc16(&block[..len]) != u16::from_be_bytes([hi, lo]) {
+            return Some(Packet::Error);
+        }
+        Some(Packet::Block { seq: header[0], len })
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        let deadline = (self.now)() + self.ticks_per_second;
+        while (self.now)() < deadline {
+            if let Some(byte) = self.serial.try_read() {
+                return Some(byte);
+            }
+        }
+        None
+    }
+
+    // 丢弃出错的块剩下的数据，直到线路空闲
+    fn purge(&mut self) {
+        while self.read_byte().is_some() {}
+    }
+
+    fn cancel(&mut self) {
+        self.send(CAN);
+        self.send(CAN);
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.serial.write(byte);
+    }
+}
+
+// 文件信息块：以 0 结尾的文件名，后面是十进制的文件大小
+fn parse_file_info(block: &[u8]) -> Option<usize> {
+    let name_end = block.iter().position(|&b| b == 0)?;
+    let mut size = None;
+    for &byte in &block[name_end + 1..] {
+        match byte {
+            b'0'..=b'9' => size = Some(size.unwrap_or(0usize).checked_mul(10)?.checked_add((byte - b'0') as usize)?),
+            _ => break,
+        }
+    }
+    size
+}
+
+// CRC-16/XMODEM
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc16, parse_file_info};
+
+    #[test]
+    fn crc16_known_answer() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(crc16(&[]), 0);
+    }
+
+    #[test]
+    fn file_info() {
+        assert_eq!(parse_file_info(b"kernel.bin\x003000 13771234 100644\0\0"), Some(3000));
+        assert_eq!(parse_file_info(b"kernel.bin\x00\0\0"), None);
+        assert_eq!(parse_file_info(b"no terminator"), None);
+        assert_eq!(parse_file_info(b"big\x00999999999999999999999999"), None);
+    }
+}